@@ -15,6 +15,9 @@ use rings_node::config;
 use rings_node::logging::node::init_logging;
 use rings_node::logging::node::LogLevel;
 use rings_node::measure::PeriodicMeasure;
+use rings_node::prelude::rings_core::blocklist::Blocklist;
+use rings_node::prelude::rings_core::dht::admission::gen_admitted_key;
+use rings_node::prelude::rings_core::dht::admission::is_admitted;
 use rings_node::prelude::rings_core::dht::Did;
 use rings_node::prelude::rings_core::dht::Stabilization;
 use rings_node::prelude::rings_core::ecc::SecretKey;
@@ -56,6 +59,8 @@ enum Command {
     Send(SendCommand),
     #[command(about = "Registers or looks up a service on the network.", subcommand)]
     Service(ServiceCommand),
+    #[command(about = "Manages and messages subrings.", subcommand)]
+    Subring(SubringCommand),
 }
 
 #[derive(Args, Debug)]
@@ -85,6 +90,13 @@ struct InitCommand {
         help = "Your ecdsa_key. If not provided, a new key will be generated"
     )]
     pub ecdsa_key: Option<SecretKey>,
+
+    #[arg(
+        long,
+        default_value_t = 0,
+        help = "Difficulty of admission for Dids of the network. The generated key will meet it"
+    )]
+    pub admission_difficulty: u8,
 }
 
 #[derive(Args, Debug)]
@@ -120,6 +132,34 @@ struct RunCommand {
     )]
     pub stabilize_timeout: Option<usize>,
 
+    #[arg(
+        long,
+        help = "How many successors keep a replica of stored data. If not provided, use replica_factor in config file or 2",
+        env
+    )]
+    pub replica_factor: Option<u8>,
+
+    #[arg(
+        long,
+        help = "Difficulty of admission for Dids of the network, 0 means disabled. If not provided, use admission_difficulty in config file or 0",
+        env
+    )]
+    pub admission_difficulty: Option<u8>,
+
+    #[arg(
+        long,
+        help = "Id of the network to join, nodes of other networks are rejected. If not provided, use network_id in config file or 0",
+        env
+    )]
+    pub network_id: Option<u32>,
+
+    #[arg(
+        long,
+        help = "Look up storing nodes of data iteratively instead of recursively. If not provided, use iterative_lookup in config file or false",
+        env
+    )]
+    pub iterative_lookup: Option<bool>,
+
     #[arg(long, help = "external ip address", env)]
     pub external_ip: Option<String>,
 
@@ -266,6 +306,12 @@ enum PeerCommand {
     List(PeerListCommand),
     #[command(about = "Disconnect peer")]
     Disconnect(PeerDisconnectCommand),
+    #[command(about = "Block peer, its connection will be closed and messages will be dropped")]
+    Block(PeerBlockCommand),
+    #[command(about = "Unblock peer")]
+    Unblock(PeerUnblockCommand),
+    #[command(about = "List blocked peers")]
+    ListBlocked(PeerListBlockedCommand),
 }
 
 #[derive(Args, Debug)]
@@ -282,6 +328,28 @@ struct PeerDisconnectCommand {
     address: String,
 }
 
+#[derive(Args, Debug)]
+struct PeerBlockCommand {
+    #[command(flatten)]
+    client_args: ClientArgs,
+
+    address: String,
+}
+
+#[derive(Args, Debug)]
+struct PeerUnblockCommand {
+    #[command(flatten)]
+    client_args: ClientArgs,
+
+    address: String,
+}
+
+#[derive(Args, Debug)]
+struct PeerListBlockedCommand {
+    #[command(flatten)]
+    client_args: ClientArgs,
+}
+
 #[derive(Subcommand, Debug)]
 #[command(rename_all = "kebab-case")]
 enum PendingCommand {
@@ -389,6 +457,12 @@ struct ServiceRegisterCommand {
     #[command(flatten)]
     client_args: ClientArgs,
 
+    #[arg(
+        long,
+        help = "Expire the registration after ttl milliseconds unless renewed"
+    )]
+    ttl_ms: Option<u64>,
+
     name: String,
 }
 
@@ -400,6 +474,56 @@ struct ServiceLookupCommand {
     name: String,
 }
 
+#[derive(Subcommand, Debug)]
+#[command(rename_all = "kebab-case")]
+enum SubringCommand {
+    #[command(about = "Joins a subring.")]
+    Join(SubringJoinCommand),
+    #[command(about = "Leaves a subring, or removes a member from it.")]
+    Leave(SubringLeaveCommand),
+    #[command(about = "Lists members of a subring.")]
+    Members(SubringMembersCommand),
+    #[command(about = "Sends a simple text message to all other members of a subring.")]
+    Broadcast(SubringBroadcastCommand),
+}
+
+#[derive(Args, Debug)]
+struct SubringJoinCommand {
+    #[command(flatten)]
+    client_args: ClientArgs,
+
+    name: String,
+}
+
+#[derive(Args, Debug)]
+struct SubringLeaveCommand {
+    #[command(flatten)]
+    client_args: ClientArgs,
+
+    #[arg(long, help = "Remove the member instead, only allowed for creator")]
+    member: Option<String>,
+
+    name: String,
+}
+
+#[derive(Args, Debug)]
+struct SubringMembersCommand {
+    #[command(flatten)]
+    client_args: ClientArgs,
+
+    name: String,
+}
+
+#[derive(Args, Debug)]
+struct SubringBroadcastCommand {
+    #[command(flatten)]
+    client_args: ClientArgs,
+
+    name: String,
+
+    text: String,
+}
+
 fn get_value<V>(value: Option<V>, default_value: V) -> V {
     value.unwrap_or(default_value)
 }
@@ -412,20 +536,30 @@ async fn daemon_run(args: RunCommand) -> anyhow::Result<()> {
     let did: Did = key.address().into();
     println!("Did: {}", did);
 
-    let (data_storage, measure_storage) = if let Some(storage_path) = args.storage_path {
-        let storage_path = Path::new(&storage_path);
-        let data_path = storage_path.join("data");
-        let measure_path = storage_path.join("measure");
-        let capacity = args
-            .storage_capacity
-            .unwrap_or(config::DEFAULT_STORAGE_CAPACITY);
-        (
-            config::StorageConfig::new(data_path.to_str().unwrap(), capacity),
-            config::StorageConfig::new(measure_path.to_str().unwrap(), capacity),
-        )
-    } else {
-        (c.data_storage, c.measure_storage)
-    };
+    let (data_storage, measure_storage, blocklist_storage, subring_storage) =
+        if let Some(storage_path) = args.storage_path {
+            let storage_path = Path::new(&storage_path);
+            let data_path = storage_path.join("data");
+            let measure_path = storage_path.join("measure");
+            let blocklist_path = storage_path.join("blocklist");
+            let subring_path = storage_path.join("subring");
+            let capacity = args
+                .storage_capacity
+                .unwrap_or(config::DEFAULT_STORAGE_CAPACITY);
+            (
+                config::StorageConfig::new(data_path.to_str().unwrap(), capacity),
+                config::StorageConfig::new(measure_path.to_str().unwrap(), capacity),
+                config::StorageConfig::new(blocklist_path.to_str().unwrap(), capacity),
+                config::StorageConfig::new(subring_path.to_str().unwrap(), capacity),
+            )
+        } else {
+            (
+                c.data_storage,
+                c.measure_storage,
+                c.blocklist_storage,
+                c.subring_storage,
+            )
+        };
 
     let per_data_storage =
         PersistenceStorage::new_with_cap_and_path(data_storage.capacity, data_storage.path).await?;
@@ -435,23 +569,46 @@ async fn daemon_run(args: RunCommand) -> anyhow::Result<()> {
 
     let measure = PeriodicMeasure::new(per_measure_storage);
 
+    let per_blocklist_storage = PersistenceStorage::new_with_cap_and_path(
+        blocklist_storage.capacity,
+        blocklist_storage.path,
+    )
+    .await?;
+    let blocklist = Blocklist::new_with_storage(per_blocklist_storage).await?;
+
+    let per_subring_storage =
+        PersistenceStorage::new_with_cap_and_path(subring_storage.capacity, subring_storage.path)
+            .await?;
+
     let stuns = get_value(args.ice_servers, c.ice_servers);
 
     let external_ip = args.external_ip.map(Some).unwrap_or(c.external_ip);
 
+    let replica_factor = get_value(args.replica_factor, c.replica_factor);
+    let admission_difficulty = get_value(args.admission_difficulty, c.admission_difficulty);
+    let network_id = get_value(args.network_id, c.network_id);
+    let iterative_lookup = get_value(args.iterative_lookup, c.iterative_lookup);
+
     let swarm = Arc::new(
         SwarmBuilder::new(stuns.as_str(), per_data_storage)
             .key(key)
+            .dht_replica_factor(replica_factor)
+            .dht_admission_difficulty(admission_difficulty)
+            .network_id(network_id)
+            .iterative_lookup(iterative_lookup)
             .external_address(external_ip)
             .measure(Box::new(measure))
+            .blocklist(blocklist)
+            .dht_subring_storage(per_subring_storage)
             .build()?,
     );
+    swarm.dht().load_subrings().await?;
 
     let backend_config = c.backend.into();
 
     let (sender, receiver) = tokio::sync::broadcast::channel(1024);
 
-    let callback: Option<CallbackFn> = Some(Box::new(Backend::new(backend_config, sender)));
+    let callback: Option<CallbackFn> = Some(Box::new(Backend::new(backend_config, sender.clone())));
 
     let stabilize_timeout = get_value(args.stabilize_timeout, c.stabilize_timeout);
 
@@ -463,10 +620,18 @@ async fn daemon_run(args: RunCommand) -> anyhow::Result<()> {
 
     let bind_addr = get_value(args.http_addr, c.http_addr);
 
-    let _ = futures::join!(
-        processor.listen(callback),
-        run_service(bind_addr, processor_clone, pubkey, receiver),
-    );
+    tokio::select! {
+        _ = async {
+            futures::join!(
+                processor.listen(callback),
+                run_service(bind_addr, processor_clone, pubkey, sender, receiver),
+            )
+        } => {}
+        _ = tokio::signal::ctrl_c() => {
+            println!("Leaving the network...");
+            processor.leave().await?;
+        }
+    }
 
     Ok(())
 }
@@ -574,6 +739,33 @@ async fn main() -> anyhow::Result<()> {
                 .display();
             Ok(())
         }
+        Command::Peer(PeerCommand::Block(args)) => {
+            args.client_args
+                .new_client()
+                .await?
+                .block_peer(args.address.as_str())
+                .await?
+                .display();
+            Ok(())
+        }
+        Command::Peer(PeerCommand::Unblock(args)) => {
+            args.client_args
+                .new_client()
+                .await?
+                .unblock_peer(args.address.as_str())
+                .await?
+                .display();
+            Ok(())
+        }
+        Command::Peer(PeerCommand::ListBlocked(args)) => {
+            args.client_args
+                .new_client()
+                .await?
+                .list_blocked_peers()
+                .await?
+                .display();
+            Ok(())
+        }
         Command::Pending(PendingCommand::List(args)) => {
             args.client_args
                 .new_client()
@@ -650,7 +842,7 @@ async fn main() -> anyhow::Result<()> {
             args.client_args
                 .new_client()
                 .await?
-                .register_service(args.name.as_str())
+                .register_service(args.name.as_str(), args.ttl_ms)
                 .await?
                 .display();
             Ok(())
@@ -664,12 +856,55 @@ async fn main() -> anyhow::Result<()> {
                 .display();
             Ok(())
         }
+        Command::Subring(SubringCommand::Join(args)) => {
+            args.client_args
+                .new_client()
+                .await?
+                .join_subring(args.name.as_str())
+                .await?
+                .display();
+            Ok(())
+        }
+        Command::Subring(SubringCommand::Leave(args)) => {
+            args.client_args
+                .new_client()
+                .await?
+                .leave_subring(args.name.as_str(), args.member.as_deref())
+                .await?
+                .display();
+            Ok(())
+        }
+        Command::Subring(SubringCommand::Members(args)) => {
+            args.client_args
+                .new_client()
+                .await?
+                .list_subring_members(args.name.as_str())
+                .await?
+                .display();
+            Ok(())
+        }
+        Command::Subring(SubringCommand::Broadcast(args)) => {
+            args.client_args
+                .new_client()
+                .await?
+                .broadcast_to_subring(args.name.as_str(), args.text.as_str())
+                .await?
+                .display();
+            Ok(())
+        }
         Command::Init(args) => {
-            let config = if let Some(key) = args.ecdsa_key {
+            let mut config = if let Some(key) = args.ecdsa_key {
+                if !is_admitted(key.address().into(), args.admission_difficulty) {
+                    return Err(anyhow::anyhow!(
+                        "The key doesn't meet admission difficulty {}",
+                        args.admission_difficulty
+                    ));
+                }
                 config::Config::new_with_key(key)
             } else {
-                config::Config::default()
+                config::Config::new_with_key(gen_admitted_key(args.admission_difficulty))
             };
+            config.admission_difficulty = args.admission_difficulty;
             let p = config.write_fs(args.location.as_str())?;
             println!("Your config file has saved to: {}", p);
             Ok(())
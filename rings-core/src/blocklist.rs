@@ -0,0 +1,109 @@
+//! This module provide the `Blocklist` struct, which holds the peers banned by current node.
+//! Transports from blocked peers are refused, and messages sent by them are dropped.
+#![warn(missing_docs)]
+use crate::dht::Did;
+use crate::err::Result;
+use crate::storage::MemStorage;
+use crate::storage::PersistenceStorage;
+use crate::storage::PersistenceStorageReadAndWrite;
+use crate::storage::PersistenceStorageRemove;
+use crate::utils::get_epoch_ms;
+
+/// `Blocklist` keeps blocked dids in memory for fast checking on every message.
+/// If it's created with a storage, it will be loaded from and written to that storage,
+/// so that blocked peers are kept across restarts.
+pub struct Blocklist {
+    storage: Option<PersistenceStorage>,
+    /// Blocked dids with the timestamps they were blocked at.
+    blocked: MemStorage<Did, u64>,
+}
+
+impl Blocklist {
+    /// Create a `Blocklist` that is kept in memory only.
+    pub fn new() -> Self {
+        Self {
+            storage: None,
+            blocked: MemStorage::new(),
+        }
+    }
+
+    /// Create a `Blocklist` persisted in the storage, blocked dids in it are loaded.
+    pub async fn new_with_storage(storage: PersistenceStorage) -> Result<Self> {
+        let blocked = MemStorage::new();
+        let entries: Vec<(Did, u64)> = storage.get_all().await?;
+        for (did, blocked_at) in entries {
+            blocked.set(&did, blocked_at);
+        }
+        Ok(Self {
+            storage: Some(storage),
+            blocked,
+        })
+    }
+
+    /// Block a did.
+    pub async fn block(&self, did: Did) -> Result<()> {
+        let blocked_at = get_epoch_ms() as u64;
+        if let Some(storage) = &self.storage {
+            storage.put(&did, &blocked_at).await?;
+        }
+        self.blocked.set(&did, blocked_at);
+        Ok(())
+    }
+
+    /// Unblock a did.
+    pub async fn unblock(&self, did: Did) -> Result<()> {
+        if let Some(storage) = &self.storage {
+            storage.remove(&did).await?;
+        }
+        self.blocked.remove(&did);
+        Ok(())
+    }
+
+    /// Check if a did is blocked.
+    pub fn is_blocked(&self, did: Did) -> bool {
+        self.blocked.get(&did).is_some()
+    }
+
+    /// List blocked dids.
+    pub fn list(&self) -> Vec<Did> {
+        self.blocked.keys()
+    }
+}
+
+impl Default for Blocklist {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dht::tests::gen_ordered_dids;
+
+    #[tokio::test]
+    async fn test_blocklist_persistence() -> Result<()> {
+        let dids = gen_ordered_dids(2);
+        let (did1, did2) = (dids[0], dids[1]);
+        let path = PersistenceStorage::random_path("./tmp");
+
+        let blocklist =
+            Blocklist::new_with_storage(PersistenceStorage::new_with_path(path.as_str()).await?)
+                .await?;
+        blocklist.block(did1).await?;
+        blocklist.block(did2).await?;
+        blocklist.unblock(did2).await?;
+        assert!(blocklist.is_blocked(did1));
+        assert!(!blocklist.is_blocked(did2));
+        drop(blocklist);
+
+        let blocklist =
+            Blocklist::new_with_storage(PersistenceStorage::new_with_path(path.as_str()).await?)
+                .await?;
+        assert_eq!(blocklist.list(), vec![did1]);
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+}
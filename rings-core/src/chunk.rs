@@ -56,6 +56,8 @@ impl PartialEq for Chunk {
     }
 }
 
+impl Eq for Chunk {}
+
 /// Meta data of a chunk
 #[derive(Debug, Copy, Clone, Deserialize, Serialize)]
 pub struct ChunkMeta {
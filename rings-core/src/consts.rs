@@ -1,10 +1,41 @@
 //! Constant variables.
+use crate::rate_limit::RateLimit;
+
 ///
 /// default ttl in ms
 pub const DEFAULT_TTL_MS: usize = 300 * 1000;
 pub const MAX_TTL_MS: usize = DEFAULT_TTL_MS * 10;
 pub const TS_OFFSET_TOLERANCE_MS: u128 = 3000;
 pub const DEFAULT_SESSION_TTL_MS: usize = 30 * 24 * 3600 * 1000;
+pub const DEFAULT_NETWORK_ID: u32 = 0;
 pub const TRANSPORT_MTU: usize = 60000;
-pub const TRANSPORT_MAX_SIZE: usize = TRANSPORT_MTU * 16;
+/// Payloads larger than this are split into chunks by swarm, and reassembled by destination.
+pub const PAYLOAD_CHUNK_MTU: usize = TRANSPORT_MTU * 4;
+/// Sending to a data channel waits while more than this is buffered in it.
+pub const TRANSPORT_MAX_BUFFERED_AMOUNT: usize = TRANSPORT_MTU * 16;
+/// Messages being sent by a transport at the same time, more wait in queue.
+pub const TRANSPORT_MAX_PENDING_MESSAGES: usize = 64;
+/// Waiting senders are woken up when the buffered amount of a data channel drops to this.
+pub const TRANSPORT_BUFFERED_AMOUNT_LOW_THRESHOLD: usize = TRANSPORT_MAX_BUFFERED_AMOUNT / 2;
+pub const TRANSPORT_BUFFER_TIMEOUT_MS: u64 = 30 * 1000;
+/// How long a message may wait in the send queue of a transport.
+pub const TRANSPORT_SEND_QUEUE_TIMEOUT_MS: u64 = 30 * 1000;
 pub const VNODE_DATA_MAX_LEN: usize = 1024;
+pub const MAX_PREDECESSOR_CHECK_MISSES: u8 = 3;
+/// How many successors keep a replica of vnodes stored on a node.
+pub const DEFAULT_REPLICA_FACTOR: u8 = 2;
+pub const DEFAULT_FETCH_TIMEOUT_MS: u64 = 10 * 1000;
+pub const SUBRING_MAX_ENTRY_POINTS: usize = 16;
+pub const TOPIC_SUBSCRIPTION_TTL_MS: u128 = 120 * 1000;
+pub const ITERATIVE_LOOKUP_MAX_HOPS: usize = 32;
+pub const ITERATIVE_LOOKUP_CANDIDATES: usize = 3;
+pub const REPUTATION_MIN_SAMPLES: u64 = 20;
+pub const REPUTATION_AVOID_SCORE: f64 = 0.8;
+pub const REPUTATION_EVICT_SCORE: f64 = 0.5;
+pub const RATE_LIMIT_PER_PEER: RateLimit = RateLimit::new(200.0, 100.0);
+pub const RATE_LIMIT_PER_MESSAGE_TYPE: RateLimit = RateLimit::new(100.0, 50.0);
+pub const RATE_LIMIT_HEAVY_MESSAGE: RateLimit = RateLimit::new(20.0, 5.0);
+/// Token buckets refilled to capacity are evicted at this interval.
+pub const RATE_LIMIT_PRUNE_INTERVAL_MS: u128 = 60 * 1000;
+/// A payload waits for payloads of higher priorities at most this long before being sent.
+pub const SCHEDULER_MAX_DELAY_MS: u64 = 1000;
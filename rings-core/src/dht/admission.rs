@@ -0,0 +1,58 @@
+//! Optional admission of Dids to the ring, to resist Sybil attacks.
+//!
+//! Since a Did is just the address of a key, an attacker can generate keys until one lands
+//! next to a target vnode and captures its storage. With admission enabled, a Did is only
+//! admitted if the keccak256 hash of it has at least `difficulty` leading zero bits, so every
+//! try of an attacker costs 2^difficulty key generations. Such a key is generated once
+//! by [gen_admitted_key] when a node is initialized.
+//!
+//! The difficulty is configured per network, and 0 means admission is disabled.
+//! A node that is not admitted can still connect to others and send messages, but it will
+//! never be joined into their rings, thus never be responsible for storing anything.
+use web3::signing::keccak256;
+
+use crate::dht::Did;
+use crate::ecc::SecretKey;
+
+/// The number of leading zero bits of keccak256 hash of did.
+pub fn did_work(did: Did) -> u32 {
+    let mut bits = 0;
+    for byte in keccak256(did.as_bytes()) {
+        bits += byte.leading_zeros();
+        if byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+/// Check if a did is admitted by the difficulty.
+pub fn is_admitted(did: Did, difficulty: u8) -> bool {
+    did_work(did) >= difficulty as u32
+}
+
+/// Generate a random key whose did is admitted by the difficulty.
+/// It takes 2^difficulty tries in average.
+pub fn gen_admitted_key(difficulty: u8) -> SecretKey {
+    loop {
+        let key = SecretKey::random();
+        if is_admitted(key.address().into(), difficulty) {
+            return key;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gen_admitted_key() {
+        let key = gen_admitted_key(8);
+        let did: Did = key.address().into();
+        assert!(did_work(did) >= 8);
+        assert!(is_admitted(did, 8));
+        assert!(is_admitted(did, 0));
+        assert!(!is_admitted(did, (did_work(did) + 1) as u8));
+    }
+}
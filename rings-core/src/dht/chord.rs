@@ -7,13 +7,19 @@ use std::sync::MutexGuard;
 use async_trait::async_trait;
 use num_bigint::BigUint;
 
+use super::admission::is_admitted;
 use super::did::BiasId;
+use super::subring::SubringChord;
 use super::successor::SuccessorSeq;
 use super::types::Chord;
 use super::types::ChordStorage;
 use super::vnode::VNodeOperation;
+use super::vnode::VNodeType;
+use super::vnode::VNodeWritePolicy;
 use super::vnode::VirtualNode;
 use super::FingerTable;
+use crate::consts::MAX_PREDECESSOR_CHECK_MISSES;
+use crate::consts::REPUTATION_AVOID_SCORE;
 use crate::dht::Did;
 use crate::err::Error;
 use crate::err::Result;
@@ -21,6 +27,7 @@ use crate::storage::MemStorage;
 use crate::storage::PersistenceStorage;
 use crate::storage::PersistenceStorageReadAndWrite;
 use crate::storage::PersistenceStorageRemove;
+use crate::utils::get_epoch_ms;
 
 /// PeerRing is used to help a node interact with other nodes.
 /// All nodes in rings network form a clockwise ring in the order of Did.
@@ -39,10 +46,38 @@ pub struct PeerRing {
     pub successor_seq: Arc<Mutex<SuccessorSeq>>,
     /// The did of previous node on the ring.
     pub predecessor: Arc<Mutex<Option<Did>>>,
+    /// The number of predecessor checks that are not answered yet.
+    /// The predecessor will be cleared once it reaches [MAX_PREDECESSOR_CHECK_MISSES].
+    pub predecessor_check_misses: Arc<Mutex<u8>>,
+    /// The tx_id of the last predecessor check, only its answer is counted.
+    pub predecessor_check: Arc<Mutex<Option<uuid::Uuid>>>,
     /// Local storage for [ChordStorage].
     pub storage: Arc<PersistenceStorage>,
     /// Local cache for [ChordStorage].
     pub cache: Arc<MemStorage<Did, VirtualNode>>,
+    /// Replicas of vnodes whose primary is one of the predecessors of current node,
+    /// with the did of the primary.
+    pub replicas: Arc<MemStorage<Did, (Did, VirtualNode)>>,
+    /// How many successors should keep a replica of vnodes stored on current node.
+    /// Replication is disabled when it's 0.
+    pub replica_factor: u8,
+    /// Difficulty of [admission](super::admission) that a node should meet to be joined.
+    /// Admission is disabled when it's 0.
+    pub admission_difficulty: u8,
+    /// Subrings joined by current node, indexed by the did of subring.
+    pub subrings: Arc<MemStorage<Did, SubringChord>>,
+    /// Names of joined subrings, kept across restarts if it's set.
+    pub subring_storage: Option<Arc<PersistenceStorage>>,
+    /// Subscribers of topics stored on current node, with the timestamps they expire at.
+    pub topic_subscribers: Arc<MemStorage<Did, Vec<(Did, u128)>>>,
+    /// Topics subscribed by current node, indexed by the did of topic.
+    pub topic_subscriptions: Arc<MemStorage<Did, String>>,
+    /// Smoothed round-trip times to peers in milliseconds, measured by pinging them.
+    pub rtts: Arc<MemStorage<Did, u128>>,
+    /// Outstanding pings to peers, with tx_id and local time of sending.
+    pub pings: Arc<MemStorage<Did, (uuid::Uuid, u128)>>,
+    /// Reputation scores of peers, calculated from their [Measure](crate::measure::Measure) counters.
+    pub reputations: Arc<MemStorage<Did, f64>>,
 }
 
 /// `PeerRing` use this to describe the result of [Chord] algorithm. Sometimes it's a
@@ -53,6 +88,8 @@ pub enum PeerRingAction {
     None,
     /// Found some VirtualNode.
     SomeVNode(VirtualNode),
+    /// Found VirtualNodes in a range, with the start of next page if the range is not finished.
+    SomeVNodeRange(Vec<VirtualNode>, Option<Did>),
     /// Found some node.
     Some(Did),
     /// Trigger a remote action.
@@ -73,12 +110,18 @@ pub enum RemoteAction {
     FindSuccessor(Did),
     /// Need `did_a` to find virtual node `did_b`.
     FindVNode(Did),
+    /// Need `did_a` to find virtual nodes in the range starting from `did_b`.
+    FindVNodeRange(Did),
     /// Need `did_a` to find VirtualNode for operating.
     FindVNodeForOperate(VNodeOperation),
     /// Let `did_a` [notify](Chord::notify) `did_b`.
     Notify(Did),
     /// Let `did_a` sync data with it's successor.
     SyncVNodeWithSuccessor(Vec<VirtualNode>),
+    /// Need `did_a` to find virtual node `did_b` in its replicas.
+    FindVNodeReplica(Did),
+    /// Let `did_a` apply the operation to its replica.
+    ReplicateVNode(VNodeOperation),
 
     /// Need `did_a` to find `did_b` then send back with `for connect` flag.
     FindSuccessorForConnect(Did),
@@ -86,8 +129,7 @@ pub enum RemoteAction {
     /// Need `did_a` to find `did_b` then send back with `for finger table fixing` flag.
     FindSuccessorForFix(Did),
 
-    // TODO: The check_processor method is not using. Cannot give correct description.
-    /// Check predecessor
+    /// Let `did_a` prove it is still alive as the predecessor.
     CheckPredecessor,
 }
 
@@ -136,11 +178,23 @@ impl PeerRing {
         Ok(Self {
             successor_seq: Arc::new(Mutex::new(SuccessorSeq::new(did, succ_max))),
             predecessor: Arc::new(Mutex::new(None)),
+            predecessor_check_misses: Arc::new(Mutex::new(0)),
+            predecessor_check: Arc::new(Mutex::new(None)),
             // for Eth address, it's 160
             finger: Arc::new(Mutex::new(FingerTable::new(did, 160))),
             did,
             storage: Arc::new(PersistenceStorage::new().await?),
             cache: Arc::new(MemStorage::<Did, VirtualNode>::new()),
+            replicas: Arc::new(MemStorage::<Did, (Did, VirtualNode)>::new()),
+            replica_factor: 0,
+            admission_difficulty: 0,
+            subrings: Arc::new(MemStorage::<Did, SubringChord>::new()),
+            subring_storage: None,
+            topic_subscribers: Arc::new(MemStorage::<Did, Vec<(Did, u128)>>::new()),
+            topic_subscriptions: Arc::new(MemStorage::<Did, String>::new()),
+            rtts: Arc::new(MemStorage::<Did, u128>::new()),
+            pings: Arc::new(MemStorage::<Did, (uuid::Uuid, u128)>::new()),
+            reputations: Arc::new(MemStorage::<Did, f64>::new()),
         })
     }
 
@@ -149,14 +203,78 @@ impl PeerRing {
         Self {
             successor_seq: Arc::new(Mutex::new(SuccessorSeq::new(did, succ_max))),
             predecessor: Arc::new(Mutex::new(None)),
+            predecessor_check_misses: Arc::new(Mutex::new(0)),
+            predecessor_check: Arc::new(Mutex::new(None)),
             // for Eth address, it's 160
             finger: Arc::new(Mutex::new(FingerTable::new(did, 160))),
             storage: Arc::new(storage),
             cache: Arc::new(MemStorage::<Did, VirtualNode>::new()),
+            replicas: Arc::new(MemStorage::<Did, (Did, VirtualNode)>::new()),
+            replica_factor: 0,
+            admission_difficulty: 0,
+            subrings: Arc::new(MemStorage::<Did, SubringChord>::new()),
+            subring_storage: None,
+            topic_subscribers: Arc::new(MemStorage::<Did, Vec<(Did, u128)>>::new()),
+            topic_subscriptions: Arc::new(MemStorage::<Did, String>::new()),
+            rtts: Arc::new(MemStorage::<Did, u128>::new()),
+            pings: Arc::new(MemStorage::<Did, (uuid::Uuid, u128)>::new()),
+            reputations: Arc::new(MemStorage::<Did, f64>::new()),
             did,
         }
     }
 
+    /// Set how many successors should keep a replica of vnodes stored on current node.
+    pub fn with_replica_factor(mut self, replica_factor: u8) -> Self {
+        self.replica_factor = replica_factor;
+        self
+    }
+
+    /// Set difficulty of admission that a node should meet to be joined.
+    pub fn with_admission_difficulty(mut self, difficulty: u8) -> Self {
+        self.admission_difficulty = difficulty;
+        self
+    }
+
+    /// Set the storage that keeps names of joined subrings across restarts.
+    pub fn with_subring_storage(mut self, storage: PersistenceStorage) -> Self {
+        self.subring_storage = Some(Arc::new(storage));
+        self
+    }
+
+    /// Record a joined subring, or return the one recorded already.
+    pub async fn add_subring(&self, chord: SubringChord) -> Result<SubringChord> {
+        if let Some(storage) = &self.subring_storage {
+            storage.put(&chord.did, &chord.name).await?;
+        }
+        Ok(self.subrings.get_or_set(&chord.did, chord))
+    }
+
+    /// Forget a subring that current node has left.
+    pub async fn remove_subring(&self, did: Did) -> Result<Option<SubringChord>> {
+        if let Some(storage) = &self.subring_storage {
+            storage.remove(&did).await?;
+        }
+        Ok(self.subrings.remove(&did).map(|(_, chord)| chord))
+    }
+
+    /// Load subrings joined before restart. Current node is the only known member of them,
+    /// others will be found again by stabilization.
+    pub async fn load_subrings(&self) -> Result<()> {
+        if let Some(storage) = &self.subring_storage {
+            let entries: Vec<(Did, String)> = storage.get_all().await?;
+            for (did, name) in entries {
+                self.subrings
+                    .get_or_set(&did, SubringChord::new(&name, self.did)?);
+            }
+        }
+        Ok(())
+    }
+
+    /// Check if a node is admitted to be joined.
+    pub fn admits(&self, did: Did) -> bool {
+        is_admitted(did, self.admission_difficulty)
+    }
+
     /// Lock and return MutexGuard of successor sequence.
     pub fn lock_successor(&self) -> Result<MutexGuard<SuccessorSeq>> {
         self.successor_seq
@@ -174,6 +292,39 @@ impl PeerRing {
         self.predecessor.lock().map_err(|_| Error::DHTSyncLockError)
     }
 
+    /// Lock and return MutexGuard of predecessor check misses.
+    pub fn lock_predecessor_check_misses(&self) -> Result<MutexGuard<u8>> {
+        self.predecessor_check_misses
+            .lock()
+            .map_err(|_| Error::DHTSyncLockError)
+    }
+
+    /// Remember a predecessor check sent, an unanswered one is replaced by the next check.
+    pub fn start_predecessor_check(&self, tx_id: uuid::Uuid) -> Result<()> {
+        *self
+            .predecessor_check
+            .lock()
+            .map_err(|_| Error::DHTSyncLockError)? = Some(tx_id);
+        Ok(())
+    }
+
+    /// Handle the answer of a predecessor check `tx_id` from a node identified by `did`.
+    /// Reset the check misses if it answers the last check, and that node is still the
+    /// predecessor. Returns if the answer is counted.
+    pub fn predecessor_alive(&self, did: Did, tx_id: uuid::Uuid) -> Result<bool> {
+        let predecessor = self.lock_predecessor()?;
+        let mut check = self
+            .predecessor_check
+            .lock()
+            .map_err(|_| Error::DHTSyncLockError)?;
+        if *predecessor != Some(did) || *check != Some(tx_id) {
+            return Ok(false);
+        }
+        *check = None;
+        *self.lock_predecessor_check_misses()? = 0;
+        Ok(true)
+    }
+
     /// Remove a node from finger table.
     /// Also remove it from successor sequence.
     /// If successor_seq become empty, try setting the closest node to it.
@@ -184,10 +335,13 @@ impl PeerRing {
         if let Some(pid) = *predecessor {
             if pid == did {
                 *predecessor = None;
+                *self.lock_predecessor_check_misses()? = 0;
             }
         }
         finger.remove(did);
         successor.remove(did);
+        self.rtts.remove(&did);
+        self.reputations.remove(&did);
         if successor.is_empty() {
             if let Some(x) = finger.first() {
                 successor.update(x);
@@ -200,6 +354,191 @@ impl PeerRing {
     pub fn bias(&self, did: Did) -> BiasId {
         BiasId::new(self.did, did)
     }
+
+    /// Remove expired entries of vnodes and replicas stored on current node.
+    /// According to the TTL of entries, this method should be called periodically.
+    pub async fn prune_expired_vnodes(&self) -> Result<()> {
+        let now = get_epoch_ms();
+
+        let all_items: Vec<(Did, VirtualNode)> = self.storage.get_all().await?;
+        for (vid, vnode) in all_items.iter() {
+            if vnode.has_expired(now) {
+                self.storage.put(vid, &vnode.prune_expired(now)).await?;
+            }
+        }
+
+        for (vid, (primary, vnode)) in self.replicas.items() {
+            if vnode.has_expired(now) {
+                self.replicas.set(&vid, (primary, vnode.prune_expired(now)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Remove replicas which are no longer needed, so that they won't grow without bound.
+    /// * A replica of vnode that current node is responsible for now is moved to the local
+    ///   storage if it's missing there.
+    /// * A replica whose primary has left is removed, unless the vnode is between the
+    ///   predecessor and current node, since the predecessor may look for it.
+    pub async fn prune_replicas(&self) -> Result<()> {
+        let predecessor = *self.lock_predecessor()?;
+        for (vid, (primary, vnode)) in self.replicas.items() {
+            if let PeerRingAction::Some(_) = self.find_successor(vid)? {
+                if self.storage.get(&vid).await?.is_none() {
+                    self.storage.put(&vid, &vnode).await?;
+                }
+                self.replicas.remove(&vid);
+                continue;
+            }
+            let primary_alive = self.lock_finger()?.contains(Some(primary))
+                || self.lock_successor()?.list().contains(&primary);
+            let in_predecessor_range = predecessor
+                .map(|p| vid == self.did || vid.in_range(p, p, self.did))
+                .unwrap_or(false);
+            if !primary_alive && !in_predecessor_range {
+                self.replicas.remove(&vid);
+            }
+        }
+        Ok(())
+    }
+
+    /// Add or renew a subscriber of topic `vid`, it will expire after `ttl_ms`.
+    pub fn add_topic_subscriber(&self, vid: Did, subscriber: Did, ttl_ms: u128) {
+        let mut subscribers = self.topic_subscribers.get(&vid).unwrap_or_default();
+        subscribers.retain(|(did, _)| *did != subscriber);
+        subscribers.push((subscriber, get_epoch_ms() + ttl_ms));
+        self.topic_subscribers.set(&vid, subscribers);
+    }
+
+    /// Remove a subscriber of topic `vid`.
+    pub fn remove_topic_subscriber(&self, vid: Did, subscriber: Did) {
+        if let Some(mut subscribers) = self.topic_subscribers.get(&vid) {
+            subscribers.retain(|(did, _)| *did != subscriber);
+            if subscribers.is_empty() {
+                self.topic_subscribers.remove(&vid);
+            } else {
+                self.topic_subscribers.set(&vid, subscribers);
+            }
+        }
+    }
+
+    /// Get subscribers of topic `vid` that are not expired.
+    pub fn topic_subscribers_of(&self, vid: Did) -> Vec<Did> {
+        let now = get_epoch_ms();
+        self.topic_subscribers
+            .get(&vid)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|(_, expires_at)| *expires_at > now)
+            .map(|(did, _)| did)
+            .collect()
+    }
+
+    /// Remove expired subscribers of topics. Subscribers are expected to renew
+    /// their subscriptions periodically, so that they will follow the topic when
+    /// it's moved to another node.
+    pub fn prune_expired_topic_subscribers(&self) {
+        let now = get_epoch_ms();
+        for (vid, mut subscribers) in self.topic_subscribers.items() {
+            subscribers.retain(|(_, expires_at)| *expires_at > now);
+            if subscribers.is_empty() {
+                self.topic_subscribers.remove(&vid);
+            } else {
+                self.topic_subscribers.set(&vid, subscribers);
+            }
+        }
+    }
+
+    /// Check if node `did` may be the one storing `vid`. A node stores vids in
+    /// (self, successor], so no node known by current node should lie in (did, vid).
+    pub fn may_store(&self, vid: Did, did: Did) -> Result<bool> {
+        let mut known = self.lock_successor()?.list();
+        known.extend(self.lock_finger()?.list().iter().flatten());
+        known.extend(*self.lock_predecessor()?);
+        known.push(self.did);
+        Ok(!known.into_iter().any(|x| x.in_range(did, did, vid)))
+    }
+
+    /// Check if `vid` handed off by `origin` should be taken over by current node.
+    /// The predecessor hands off vids when current node joins, and a successor hands off
+    /// vids when it leaves. Both fall in (self, successor], where a leaving successor
+    /// is not counted, so others are refused.
+    pub fn may_take_over(&self, vid: Did, origin: Did) -> Result<bool> {
+        let successors = self.lock_successor()?.list();
+        if *self.lock_predecessor()? != Some(origin) && !successors.contains(&origin) {
+            return Ok(false);
+        }
+        let next = successors
+            .into_iter()
+            .filter(|x| *x != origin && *x != self.did)
+            .min_by_key(|x| self.bias(*x));
+        Ok(match next {
+            Some(next) => vid != self.did && self.bias(vid) <= self.bias(next),
+            None => true,
+        })
+    }
+
+    /// Record a round-trip time sample to a peer. Like the SRTT of TCP, samples are
+    /// smoothed with a weight of 1/8 to avoid jitter.
+    pub fn record_rtt(&self, did: Did, rtt_ms: u128) {
+        let srtt = match self.rtts.get(&did) {
+            Some(srtt) => (srtt * 7 + rtt_ms) / 8,
+            None => rtt_ms,
+        };
+        self.rtts.set(&did, srtt);
+    }
+
+    /// Remember a ping sent to a peer, an unanswered one is replaced by the next ping.
+    pub fn start_ping(&self, did: Did, tx_id: uuid::Uuid) {
+        self.pings.set(&did, (tx_id, get_epoch_ms()));
+    }
+
+    /// Record round-trip time of a ping answered by a peer, the time is measured
+    /// by local clock since the ping with the same tx_id is sent.
+    /// Returns None if no such ping is sent.
+    pub fn finish_ping(&self, did: Did, tx_id: uuid::Uuid) -> Option<u128> {
+        match self.pings.get(&did) {
+            Some((id, sent_at)) if id == tx_id => {
+                self.pings.remove(&did);
+                let rtt_ms = get_epoch_ms().saturating_sub(sent_at);
+                self.record_rtt(did, rtt_ms);
+                Some(rtt_ms)
+            }
+            _ => None,
+        }
+    }
+
+    /// Get smoothed round-trip time to a peer, if it's ever measured.
+    pub fn rtt_of(&self, did: Did) -> Option<u128> {
+        self.rtts.get(&did)
+    }
+
+    /// Update reputation score of a peer.
+    pub fn set_reputation(&self, did: Did, score: f64) {
+        self.reputations.set(&did, score);
+    }
+
+    /// Check if a peer should be avoided on routing for its low reputation score.
+    pub fn is_avoided(&self, did: Did) -> bool {
+        self.reputations
+            .get(&did)
+            .map(|score| score < REPUTATION_AVOID_SCORE)
+            .unwrap_or(false)
+    }
+
+    /// Generate actions to replicate the operation to the first successor, which will pass it
+    /// along successors until `replica_factor` of them keep a replica.
+    /// A replica is only accepted from the predecessor, so it cannot be sent to others directly.
+    fn replicate_actions(&self, op: VNodeOperation) -> Result<PeerRingAction> {
+        let successor = self.lock_successor()?.min();
+        if self.replica_factor == 0 || successor == self.did {
+            return Ok(PeerRingAction::None);
+        }
+        Ok(PeerRingAction::MultiActions(vec![
+            PeerRingAction::RemoteAction(successor, RemoteAction::ReplicateVNode(op)),
+        ]))
+    }
 }
 
 impl Chord<PeerRingAction> for PeerRing {
@@ -214,6 +553,9 @@ impl Chord<PeerRingAction> for PeerRing {
         if did == self.did {
             return Ok(PeerRingAction::None);
         }
+        if !self.admits(did) {
+            return Err(Error::DidNotAdmitted(did));
+        }
 
         let mut finger = self.lock_finger()?;
         let mut successor = self.lock_successor()?;
@@ -245,7 +587,18 @@ impl Chord<PeerRingAction> for PeerRing {
             Ok(PeerRingAction::Some(successor.min()))
         } else {
             // Otherwise, find the closest preceding node and ask it to find the successor.
-            let closest = finger.closest(did);
+            // Prefer the lower-latency one if several fingers make equivalent progress.
+            let mut closest = finger.closest_by_proximity(did, |x| self.rtt_of(x));
+            // Route around a peer of low reputation, unless there is no alternative.
+            if self.is_avoided(closest) {
+                if let Some(x) = finger
+                    .closest_list(did, finger.len())
+                    .into_iter()
+                    .find(|x| !self.is_avoided(*x))
+                {
+                    closest = x;
+                }
+            }
             Ok(PeerRingAction::RemoteAction(
                 closest,
                 RemoteAction::FindSuccessor(did),
@@ -258,6 +611,9 @@ impl Chord<PeerRingAction> for PeerRing {
     /// If that node is closer to current node or current node has no predecessor, set it to the did.
     /// This method will return that did if it is set to the predecessor.
     fn notify(&self, did: Did) -> Result<Option<Did>> {
+        if !self.admits(did) {
+            return Ok(None);
+        }
         let mut predecessor = self.lock_predecessor()?;
 
         match *predecessor {
@@ -265,6 +621,7 @@ impl Chord<PeerRingAction> for PeerRing {
                 // If the did is closer to self than predecessor, set it to the predecessor.
                 if self.bias(pre) < self.bias(did) {
                     *predecessor = Some(did);
+                    *self.lock_predecessor_check_misses()? = 0;
                     Ok(Some(did))
                 } else {
                     Ok(None)
@@ -273,6 +630,7 @@ impl Chord<PeerRingAction> for PeerRing {
             None => {
                 // Self has no predecessor, set it to the did directly.
                 *predecessor = Some(did);
+                *self.lock_predecessor_check_misses()? = 0;
                 Ok(Some(did))
             }
         }
@@ -330,10 +688,22 @@ impl Chord<PeerRingAction> for PeerRing {
     }
 
     /// called periodically. checks whether predecessor has failed.
+    /// Every call is counted as a miss until the predecessor answers, see [PeerRing::predecessor_alive].
+    /// If the predecessor missed [MAX_PREDECESSOR_CHECK_MISSES] checks, it will be cleared.
     fn check_predecessor(&self) -> Result<PeerRingAction> {
-        let predecessor = *self.lock_predecessor()?;
-        Ok(match predecessor {
-            Some(p) => PeerRingAction::RemoteAction(p, RemoteAction::CheckPredecessor),
+        let mut predecessor = self.lock_predecessor()?;
+        let mut misses = self.lock_predecessor_check_misses()?;
+        Ok(match *predecessor {
+            Some(p) if *misses >= MAX_PREDECESSOR_CHECK_MISSES => {
+                tracing::info!("predecessor {:?} is dead, clear it", p);
+                *predecessor = None;
+                *misses = 0;
+                PeerRingAction::None
+            }
+            Some(p) => {
+                *misses += 1;
+                PeerRingAction::RemoteAction(p, RemoteAction::CheckPredecessor)
+            }
             None => PeerRingAction::None,
         })
     }
@@ -346,42 +716,122 @@ impl ChordStorage<PeerRingAction> for PeerRing {
     /// Always finds resource by finger table, ignoring the local cache.
     /// If the `vid` is between current node and its successor, its resource should be
     /// stored in current node.
+    /// Replicas will be used when the resource cannot be found on its primary node.
     async fn vnode_lookup(&self, vid: Did) -> Result<PeerRingAction> {
         match self.find_successor(vid) {
             // Resource should be stored in current node.
-            Ok(PeerRingAction::Some(_)) => match self.storage.get(&vid).await {
-                Ok(Some(v)) => Ok(PeerRingAction::SomeVNode(v)),
-                Ok(None) => Ok(PeerRingAction::None),
-                Err(_) => Ok(PeerRingAction::None),
-            },
+            Ok(PeerRingAction::Some(_)) => {
+                if let Ok(Some(v)) = self.storage.get(&vid).await {
+                    return Ok(PeerRingAction::SomeVNode(v));
+                }
+                if let Some((_, v)) = self.replicas.get(&vid) {
+                    return Ok(PeerRingAction::SomeVNode(v));
+                }
+                // The previous primary may be gone, ask successor for its replica.
+                let successor = self.lock_successor()?.min();
+                if self.replica_factor > 0 && successor != self.did {
+                    Ok(PeerRingAction::RemoteAction(
+                        successor,
+                        RemoteAction::FindVNodeReplica(vid),
+                    ))
+                } else {
+                    Ok(PeerRingAction::None)
+                }
+            }
             // Resource is stored in other nodes.
-            // Return an action to describe how to find it.
+            // Return an action to describe how to find it, unless a replica is kept here.
             Ok(PeerRingAction::RemoteAction(n, RemoteAction::FindSuccessor(id))) => {
-                Ok(PeerRingAction::RemoteAction(n, RemoteAction::FindVNode(id)))
+                match self.replicas.get(&vid) {
+                    Some((_, v)) => Ok(PeerRingAction::SomeVNode(v)),
+                    None => Ok(PeerRingAction::RemoteAction(n, RemoteAction::FindVNode(id))),
+                }
             }
             Ok(a) => Err(Error::PeerRingUnexpectedAction(a)),
             Err(e) => Err(e),
         }
     }
 
+    /// Look up VirtualNodes in the clockwise range from `start` (inclusive) to `end` (exclusive).
+    /// Only VirtualNodes stored on current node are returned, at most `limit` of them.
+    /// The range is continued on successor if it's not finished here.
+    /// The range covers the whole ring if `start` equals `end`.
+    async fn vnode_range(&self, start: Did, end: Did, limit: usize) -> Result<PeerRingAction> {
+        if limit == 0 {
+            return Err(Error::VNodeRangeZeroLimit);
+        }
+        let one = Did::from(BigUint::from(1u8));
+        let span = end - start;
+        let in_range = |did: Did| start == end || did - start < span;
+        match self.find_successor(start) {
+            // The range starts from current node.
+            Ok(PeerRingAction::Some(successor)) => {
+                let mut data = self
+                    .storage
+                    .get_all()
+                    .await?
+                    .into_iter()
+                    .map(|(_, vnode): (Did, VirtualNode)| vnode)
+                    .filter(|vnode| in_range(vnode.did))
+                    .collect::<Vec<_>>();
+                data.sort_by_key(|vnode| vnode.did - start);
+
+                let next = if data.len() > limit {
+                    data.truncate(limit);
+                    data.last().map(|vnode| vnode.did + one)
+                } else if successor != self.did
+                    && successor + one != start
+                    && in_range(successor + one)
+                {
+                    // VirtualNodes after successor are stored on successor.
+                    Some(successor + one)
+                } else {
+                    None
+                };
+                Ok(PeerRingAction::SomeVNodeRange(data, next))
+            }
+            // The range starts from other nodes.
+            Ok(PeerRingAction::RemoteAction(n, RemoteAction::FindSuccessor(id))) => Ok(
+                PeerRingAction::RemoteAction(n, RemoteAction::FindVNodeRange(id)),
+            ),
+            Ok(a) => Err(Error::PeerRingUnexpectedAction(a)),
+            Err(e) => Err(e),
+        }
+    }
+
     /// Handle [VNodeOperation] if the target vnode between current node and the
     /// successor of current node, otherwise find the responsible node and return
     /// as Action.
-    async fn vnode_operate(&self, op: VNodeOperation) -> Result<PeerRingAction> {
+    /// The write policy of stored vnode is checked against `operator`. A vnode created
+    /// with write policy is owned by `operator`, whatever owner it claims.
+    async fn vnode_operate(&self, op: VNodeOperation, operator: Did) -> Result<PeerRingAction> {
         let vid = op.did()?;
         let op1 = op.clone();
         match self.find_successor(vid) {
             // `vnode` should be on current node.
             Ok(PeerRingAction::Some(_)) => {
-                let this = if let Ok(Some(this)) = self.storage.get(&vid).await {
-                    Ok(this)
-                } else {
-                    op1.gen_default_vnode()
-                }?;
+                let this = match self.storage.get(&vid).await {
+                    Ok(Some(this)) => {
+                        this.check_permission(&op, operator)?;
+                        this
+                    }
+                    _ => {
+                        // A new subring can only be created by its first member.
+                        if let VNodeOperation::JoinSubring(_, did) = &op {
+                            if *did != operator {
+                                return Err(Error::VNodeWriteForbidden(operator));
+                            }
+                        }
+                        let mut this = op1.clone().gen_default_vnode()?;
+                        if this.owner.is_some() || this.policy != VNodeWritePolicy::Public {
+                            this.owner = Some(operator);
+                        }
+                        this
+                    }
+                };
                 let vnode = this.operate(op)?;
                 self.storage.put(&vid, &vnode).await?;
 
-                Ok(PeerRingAction::None)
+                self.replicate_actions(op1)
             }
             // `vnode` should be on other nodes.
             // Return an action to describe how to store it.
@@ -418,6 +868,47 @@ impl ChordStorage<PeerRingAction> for PeerRing {
         }
     }
 
+    /// Take over a `VirtualNode` handed off by a node which is no longer responsible for it.
+    /// Unlike [VNodeOperation], the `VirtualNode` is kept as it is, including its version,
+    /// owner and policy. Messages parked for a same destination are merged, otherwise the
+    /// newer one is kept. A `VirtualNode` stored already can only be replaced by `operator`
+    /// who is allowed to overwrite it. Only neighbors can hand off, see [PeerRing::may_take_over].
+    async fn vnode_handoff(&self, vnode: VirtualNode, operator: Did) -> Result<()> {
+        if !self.may_take_over(vnode.did, operator)? {
+            return Err(Error::VNodeHandoffRefused(operator, vnode.did));
+        }
+        let vnode = match self.storage.get(&vnode.did).await? {
+            Some(this) if this.kind == VNodeType::RelayMessage => this.extend(vnode)?,
+            Some(this) if this.version > vnode.version => return Ok(()),
+            Some(this) => {
+                this.check_permission(&VNodeOperation::Overwrite(vnode.clone()), operator)?;
+                vnode
+            }
+            None => vnode,
+        };
+        self.storage.put(&vnode.did, &vnode).await
+    }
+
+    /// Apply [VNodeOperation] to the replica kept on current node.
+    /// It's skipped if current node is the `primary`, when replicas are passed around a small ring.
+    async fn vnode_replicate(&self, primary: Did, op: VNodeOperation) -> Result<()> {
+        if primary == self.did {
+            return Ok(());
+        }
+        let vid = op.did()?;
+        // Version is already checked by the primary.
+        let op = match op {
+            VNodeOperation::CompareAndSwap(vnode, _) => VNodeOperation::Overwrite(vnode),
+            op => op,
+        };
+        let this = match self.replicas.get(&vid) {
+            Some((_, this)) => this,
+            None => op.clone().gen_default_vnode()?,
+        };
+        self.replicas.set(&vid, (primary, this.operate(op)?));
+        Ok(())
+    }
+
     /// Cache fetched `vnode` locally.
     fn local_cache_set(&self, vnode: VirtualNode) {
         self.cache.set(&vnode.did.clone(), vnode);
@@ -436,7 +927,12 @@ mod tests {
     use std::str::FromStr;
 
     use super::*;
+    use crate::dht::admission::gen_admitted_key;
+    use crate::dht::subring::Subring;
+    use crate::dht::tests::gen_ordered_dids;
+    use crate::dht::vnode::VNodeType;
     use crate::ecc::SecretKey;
+    use crate::message::Encoder;
 
     #[tokio::test]
     async fn test_chord_finger() -> Result<()> {
@@ -711,4 +1207,493 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_check_predecessor() -> Result<()> {
+        let did1 = SecretKey::random().address().into();
+        let did2 = SecretKey::random().address().into();
+        let did3 = SecretKey::random().address().into();
+
+        let db_path = PersistenceStorage::random_path("./tmp");
+        let db = PersistenceStorage::new_with_path(db_path.as_str())
+            .await
+            .unwrap();
+        let node1 = PeerRing::new_with_storage(did1, 3, db);
+
+        // Nothing to check without predecessor.
+        assert_eq!(node1.check_predecessor()?, PeerRingAction::None);
+
+        node1.notify(did2)?;
+        assert_eq!(*node1.lock_predecessor()?, Some(did2));
+
+        // An alive predecessor is never cleared.
+        for _ in 0..MAX_PREDECESSOR_CHECK_MISSES * 2 {
+            assert_eq!(
+                node1.check_predecessor()?,
+                PeerRingAction::RemoteAction(did2, RemoteAction::CheckPredecessor)
+            );
+            let tx_id = uuid::Uuid::new_v4();
+            node1.start_predecessor_check(tx_id)?;
+            assert!(node1.predecessor_alive(did2, tx_id)?);
+        }
+        assert_eq!(*node1.lock_predecessor()?, Some(did2));
+
+        // Answer from other node, or not to the last check, should not be counted.
+        for _ in 0..MAX_PREDECESSOR_CHECK_MISSES {
+            assert_eq!(
+                node1.check_predecessor()?,
+                PeerRingAction::RemoteAction(did2, RemoteAction::CheckPredecessor)
+            );
+            let tx_id = uuid::Uuid::new_v4();
+            node1.start_predecessor_check(tx_id)?;
+            assert!(!node1.predecessor_alive(did3, tx_id)?);
+            assert!(!node1.predecessor_alive(did2, uuid::Uuid::new_v4())?);
+        }
+
+        // A dead predecessor is cleared after bounded rounds.
+        assert_eq!(node1.check_predecessor()?, PeerRingAction::None);
+        assert!(node1.lock_predecessor()?.is_none());
+        assert_eq!(*node1.lock_predecessor_check_misses()?, 0);
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_vnode_replication() -> Result<()> {
+        let dids = gen_ordered_dids(4);
+        let (did1, vid, did2, did3) = (dids[0], dids[1], dids[2], dids[3]);
+
+        let db_path = PersistenceStorage::random_path("./tmp");
+        let db = PersistenceStorage::new_with_path(db_path.as_str())
+            .await
+            .unwrap();
+        let node1 = PeerRing::new_with_storage(did1, 3, db).with_replica_factor(1);
+        node1.join(did2)?;
+        node1.join(did3)?;
+
+        let vnode = VirtualNode {
+            did: vid,
+            data: vec!["hello".to_string().encode()?],
+            kind: VNodeType::Data,
+            version: 0,
+            owner: None,
+            policy: Default::default(),
+            expires: vec![],
+        };
+        let stored = VirtualNode {
+            version: 1,
+            ..vnode.clone()
+        };
+        let op = VNodeOperation::Overwrite(vnode.clone());
+
+        // Stored on node1 and replicated to the first successor only.
+        assert_eq!(
+            node1.vnode_operate(op.clone(), node1.did).await?,
+            PeerRingAction::MultiActions(vec![PeerRingAction::RemoteAction(
+                did2,
+                RemoteAction::ReplicateVNode(op.clone())
+            )])
+        );
+        assert_eq!(
+            node1.vnode_lookup(vid).await?,
+            PeerRingAction::SomeVNode(stored.clone())
+        );
+
+        // Primary copy is lost, lookup falls back to the replica on successor.
+        node1.storage.remove(&vid).await?;
+        assert_eq!(
+            node1.vnode_lookup(vid).await?,
+            PeerRingAction::RemoteAction(did2, RemoteAction::FindVNodeReplica(vid))
+        );
+
+        // A replica kept locally is used directly.
+        node1.vnode_replicate(did3, op).await?;
+        assert_eq!(
+            node1.vnode_lookup(vid).await?,
+            PeerRingAction::SomeVNode(stored.clone())
+        );
+
+        // A replica of vnode that current node is responsible for is moved to local storage.
+        node1.prune_replicas().await?;
+        assert!(node1.replicas.is_empty());
+        assert_eq!(node1.storage.get(&vid).await?, Some(stored));
+
+        // A replica whose primary has left is removed.
+        let other = VirtualNode { did: did3, ..vnode };
+        let gone = gen_ordered_dids(1)[0];
+        node1
+            .vnode_replicate(did2, VNodeOperation::Overwrite(other.clone()))
+            .await?;
+        node1.prune_replicas().await?;
+        assert!(node1.replicas.get(&did3).is_some());
+        node1
+            .vnode_replicate(gone, VNodeOperation::Overwrite(other))
+            .await?;
+        node1.prune_replicas().await?;
+        assert!(node1.replicas.get(&did3).is_none());
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_vnode_operate_write_policy() -> Result<()> {
+        let dids = gen_ordered_dids(4);
+        let (did1, vid, did2, did3) = (dids[0], dids[1], dids[2], dids[3]);
+
+        let db_path = PersistenceStorage::random_path("./tmp");
+        let db = PersistenceStorage::new_with_path(db_path.as_str())
+            .await
+            .unwrap();
+        let node1 = PeerRing::new_with_storage(did1, 3, db);
+        node1.join(did2)?;
+
+        let gen_vnode = |data: &str| -> Result<VirtualNode> {
+            Ok(VirtualNode {
+                did: vid,
+                data: vec![data.to_string().encode()?],
+                kind: VNodeType::Data,
+                version: 0,
+                owner: None,
+                policy: Default::default(),
+                expires: vec![],
+            })
+        };
+
+        // The writer is recorded as owner, whatever owner is claimed.
+        let vnode = gen_vnode("v1")?.with_owner(did3, VNodeWritePolicy::OwnerOnly);
+        node1
+            .vnode_operate(VNodeOperation::Overwrite(vnode), did2)
+            .await?;
+        let stored = node1.storage.get(&vid).await?.unwrap();
+        assert_eq!(stored.owner, Some(did2));
+
+        // Others cannot write it, neither by operation nor by handoff.
+        let result = node1
+            .vnode_operate(VNodeOperation::Overwrite(gen_vnode("v2")?), did3)
+            .await;
+        assert!(matches!(result, Err(Error::VNodeWriteForbidden(did)) if did == did3));
+        let forged = VirtualNode {
+            version: 10,
+            ..gen_vnode("v2")?
+        };
+        let result = node1.vnode_handoff(forged, did3).await;
+        assert!(matches!(result, Err(Error::VNodeHandoffRefused(did, _)) if did == did3));
+        assert_eq!(node1.storage.get(&vid).await?, Some(stored));
+
+        // The owner can.
+        node1
+            .vnode_operate(VNodeOperation::Overwrite(gen_vnode("v2")?), did2)
+            .await?;
+        let stored = node1.storage.get(&vid).await?.unwrap();
+        assert_eq!(stored.data, gen_vnode("v2")?.data);
+        assert_eq!(stored.owner, Some(did2));
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_vnode_handoff() -> Result<()> {
+        let dids = gen_ordered_dids(7);
+        let (pred, did1, vid1, did2, vid2, did3, stranger) = (
+            dids[0], dids[1], dids[2], dids[3], dids[4], dids[5], dids[6],
+        );
+
+        let db_path = PersistenceStorage::random_path("./tmp");
+        let db = PersistenceStorage::new_with_path(db_path.as_str())
+            .await
+            .unwrap();
+        let node1 = PeerRing::new_with_storage(did1, 3, db);
+        node1.join(did2)?;
+        node1.join(did3)?;
+        *node1.lock_predecessor()? = Some(pred);
+
+        let gen_vnode = |vid: Did| -> Result<VirtualNode> {
+            Ok(VirtualNode {
+                did: vid,
+                data: vec!["data".to_string().encode()?],
+                kind: VNodeType::Data,
+                version: 3,
+                owner: None,
+                policy: Default::default(),
+                expires: vec![],
+            })
+        };
+
+        // Others cannot hand off, even a vid in the range of current node.
+        let result = node1.vnode_handoff(gen_vnode(vid1)?, stranger).await;
+        assert!(matches!(result, Err(Error::VNodeHandoffRefused(did, _)) if did == stranger));
+
+        // The predecessor can hand off vids in (did1, did2], which are kept as they are.
+        let vnode = gen_vnode(vid1)?.with_owner(pred, VNodeWritePolicy::OwnerOnly);
+        node1.vnode_handoff(vnode.clone(), pred).await?;
+        assert_eq!(node1.storage.get(&vid1).await?, Some(vnode));
+        let result = node1.vnode_handoff(gen_vnode(vid2)?, pred).await;
+        assert!(matches!(result, Err(Error::VNodeHandoffRefused(did, _)) if did == pred));
+
+        // A leaving successor can hand off vids in (did1, did3].
+        node1.vnode_handoff(gen_vnode(vid2)?, did2).await?;
+        assert_eq!(node1.storage.get(&vid2).await?, Some(gen_vnode(vid2)?));
+        let result = node1.vnode_handoff(gen_vnode(stranger)?, did2).await;
+        assert!(matches!(result, Err(Error::VNodeHandoffRefused(did, _)) if did == did2));
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_vnode_operate_leave_subring() -> Result<()> {
+        let dids = gen_ordered_dids(4);
+        let (did1, creator, member, other) = (dids[0], dids[1], dids[2], dids[3]);
+
+        let db_path = PersistenceStorage::random_path("./tmp");
+        let db = PersistenceStorage::new_with_path(db_path.as_str())
+            .await
+            .unwrap();
+        let node1 = PeerRing::new_with_storage(did1, 3, db);
+
+        let name = "subring".to_string();
+        let vid = VirtualNode::gen_did(&name)?;
+        let join = |did| VNodeOperation::JoinSubring(name.clone(), did);
+        let leave = |did| VNodeOperation::LeaveSubring(name.clone(), did);
+        let members = |vnode: Option<VirtualNode>| -> Result<Vec<Did>> {
+            let subring: Subring = vnode.unwrap().try_into()?;
+            Ok(subring.members)
+        };
+        // A subring cannot be created on behalf of others.
+        let result = node1.vnode_operate(join(creator), other).await;
+        assert!(matches!(result, Err(Error::VNodeWriteForbidden(did)) if did == other));
+        assert!(node1.storage.get(&vid).await?.is_none());
+
+        node1.vnode_operate(join(creator), creator).await?;
+        node1.vnode_operate(join(member), member).await?;
+
+        // A member cannot be added by others.
+        let result = node1.vnode_operate(join(other), member).await;
+        assert!(matches!(result, Err(Error::VNodeWriteForbidden(did)) if did == member));
+        node1.vnode_operate(join(other), other).await?;
+
+        // A member cannot be removed by others.
+        let result = node1.vnode_operate(leave(member), other).await;
+        assert!(matches!(result, Err(Error::VNodeWriteForbidden(did)) if did == other));
+        assert!(members(node1.storage.get(&vid).await?)?.contains(&member));
+
+        // But by itself or the creator.
+        node1.vnode_operate(leave(member), member).await?;
+        node1.vnode_operate(leave(other), creator).await?;
+        assert_eq!(members(node1.storage.get(&vid).await?)?, vec![creator]);
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_vnode_range() -> Result<()> {
+        let dids = gen_ordered_dids(6);
+        let (did1, vid1, vid2, did2, vid3, did3) =
+            (dids[0], dids[1], dids[2], dids[3], dids[4], dids[5]);
+        let one = Did::from(BigUint::from(1u8));
+
+        let db_path = PersistenceStorage::random_path("./tmp");
+        let db = PersistenceStorage::new_with_path(db_path.as_str())
+            .await
+            .unwrap();
+        let node1 = PeerRing::new_with_storage(did1, 3, db);
+        node1.join(did2)?;
+        node1.join(did3)?;
+
+        let gen_vnode = |did| VirtualNode {
+            did,
+            data: vec![],
+            kind: VNodeType::Data,
+            version: 0,
+            owner: None,
+            policy: Default::default(),
+            expires: vec![],
+        };
+        let (vnode1, vnode2) = (gen_vnode(vid1), gen_vnode(vid2));
+        node1.storage.put(&vid2, &vnode2).await?;
+        node1.storage.put(&vid1, &vnode1).await?;
+
+        // The range is continued on successor.
+        assert_eq!(
+            node1.vnode_range(vid1, did3, 10).await?,
+            PeerRingAction::SomeVNodeRange(vec![vnode1.clone(), vnode2.clone()], Some(did2 + one))
+        );
+        // The range is finished on current node.
+        assert_eq!(
+            node1.vnode_range(vid1, vid2, 10).await?,
+            PeerRingAction::SomeVNodeRange(vec![vnode1.clone()], None)
+        );
+        // The range is paginated by limit.
+        assert_eq!(
+            node1.vnode_range(vid1, did3, 1).await?,
+            PeerRingAction::SomeVNodeRange(vec![vnode1], Some(vid1 + one))
+        );
+        assert_eq!(
+            node1.vnode_range(vid1 + one, did3, 1).await?,
+            PeerRingAction::SomeVNodeRange(vec![vnode2], Some(did2 + one))
+        );
+        // The range starts from other nodes.
+        assert!(matches!(
+            node1.vnode_range(vid3, did1, 10).await?,
+            PeerRingAction::RemoteAction(_, RemoteAction::FindVNodeRange(x)) if x == vid3
+        ));
+        // The range is the whole ring if it starts and ends at the same did.
+        let whole = vec![gen_vnode(vid1), gen_vnode(vid2)];
+        assert_eq!(
+            node1.vnode_range(vid1, vid1, 10).await?,
+            PeerRingAction::SomeVNodeRange(whole, Some(did2 + one))
+        );
+        // A zero limit is rejected.
+        let result = node1.vnode_range(vid1, did3, 0).await;
+        assert!(matches!(result, Err(Error::VNodeRangeZeroLimit)));
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_topic_subscribers() -> Result<()> {
+        let dids = gen_ordered_dids(3);
+        let (did1, vid, did2) = (dids[0], dids[1], dids[2]);
+
+        let db_path = PersistenceStorage::random_path("./tmp");
+        let db = PersistenceStorage::new_with_path(db_path.as_str())
+            .await
+            .unwrap();
+        let node1 = PeerRing::new_with_storage(did1, 3, db);
+
+        node1.add_topic_subscriber(vid, did2, 60 * 1000);
+        // Renewing a subscription will not duplicate the subscriber.
+        node1.add_topic_subscriber(vid, did2, 60 * 1000);
+        assert_eq!(node1.topic_subscribers_of(vid), vec![did2]);
+
+        node1.remove_topic_subscriber(vid, did2);
+        assert!(node1.topic_subscribers_of(vid).is_empty());
+        assert!(node1.topic_subscribers.is_empty());
+
+        // An expired subscriber is skipped, then pruned.
+        node1.add_topic_subscriber(vid, did2, 0);
+        assert!(node1.topic_subscribers_of(vid).is_empty());
+        node1.prune_expired_topic_subscribers();
+        assert!(node1.topic_subscribers.is_empty());
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_may_store() -> Result<()> {
+        let dids = gen_ordered_dids(3);
+        let (did1, vid, did2) = (dids[0], dids[1], dids[2]);
+
+        let db_path = PersistenceStorage::random_path("./tmp");
+        let db = PersistenceStorage::new_with_path(db_path.as_str())
+            .await
+            .unwrap();
+        let node1 = PeerRing::new_with_storage(did1, 3, db);
+        node1.join(did2)?;
+
+        // vid lies in (did1, did2], so it's stored on node1 rather than node2.
+        assert!(node1.may_store(vid, did1)?);
+        assert!(!node1.may_store(vid, did2)?);
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_record_rtt() -> Result<()> {
+        let dids = gen_ordered_dids(2);
+        let (did1, did2) = (dids[0], dids[1]);
+
+        let db_path = PersistenceStorage::random_path("./tmp");
+        let db = PersistenceStorage::new_with_path(db_path.as_str())
+            .await
+            .unwrap();
+        let node1 = PeerRing::new_with_storage(did1, 3, db);
+        node1.join(did2)?;
+        assert_eq!(node1.rtt_of(did2), None);
+
+        node1.record_rtt(did2, 80);
+        assert_eq!(node1.rtt_of(did2), Some(80));
+        node1.record_rtt(did2, 160);
+        assert_eq!(node1.rtt_of(did2), Some(90));
+
+        // RTT is forgotten with the peer.
+        node1.remove(did2)?;
+        assert_eq!(node1.rtt_of(did2), None);
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_route_around_avoided_peers() -> Result<()> {
+        let did = |x: u32| Did::from(BigUint::from(x));
+        let db_path = PersistenceStorage::random_path("./tmp");
+        let db = PersistenceStorage::new_with_path(db_path.as_str())
+            .await
+            .unwrap();
+        let node = PeerRing::new_with_storage(did(0), 3, db);
+        for x in [0x10, 0x20, 0x40, 0x80] {
+            node.join(did(x))?;
+        }
+
+        let next_hop = |target| match node.find_successor(target) {
+            Ok(PeerRingAction::RemoteAction(next, RemoteAction::FindSuccessor(_))) => next,
+            x => panic!("unexpected action {:?}", x),
+        };
+        assert_eq!(next_hop(did(0x100)), did(0x80));
+
+        node.set_reputation(did(0x80), 0.6);
+        assert!(node.is_avoided(did(0x80)));
+        assert_eq!(next_hop(did(0x100)), did(0x40));
+
+        // Still use an avoided peer if there is no alternative.
+        for x in [0x10, 0x20, 0x40] {
+            node.set_reputation(did(x), 0.6);
+        }
+        assert_eq!(next_hop(did(0x100)), did(0x80));
+
+        node.set_reputation(did(0x80), 0.9);
+        assert_eq!(next_hop(did(0x100)), did(0x80));
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_join_with_admission() -> Result<()> {
+        let did1: Did = gen_admitted_key(8).address().into();
+        let did2: Did = gen_admitted_key(8).address().into();
+        let did3: Did = loop {
+            let did: Did = SecretKey::random().address().into();
+            if !is_admitted(did, 8) {
+                break did;
+            }
+        };
+
+        let db_path = PersistenceStorage::random_path("./tmp");
+        let db = PersistenceStorage::new_with_path(db_path.as_str())
+            .await
+            .unwrap();
+        let node1 = PeerRing::new_with_storage(did1, 3, db).with_admission_difficulty(8);
+
+        assert!(matches!(
+            node1.join(did3),
+            Err(Error::DidNotAdmitted(did)) if did == did3
+        ));
+        assert_eq!(node1.notify(did3)?, None);
+        assert!(node1.lock_finger()?.is_empty());
+
+        assert!(node1.join(did2)?.is_remote());
+        assert_eq!(node1.lock_successor()?.list(), vec![did2]);
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
 }
@@ -158,6 +158,43 @@ impl FingerTable {
         self.did
     }
 
+    /// Preceding nodes of did, ordered from the closest one, at most `limit` of them.
+    /// Used as alternatives when the closest one is not responding.
+    pub fn closest_list(&self, did: Did, limit: usize) -> Vec<Did> {
+        let bid: BiasId = did.bias(self.did);
+        let mut ret: Vec<Did> = vec![];
+        for v in self.finger.iter().rev().flatten() {
+            if ret.len() >= limit {
+                break;
+            }
+            if v.bias(self.did) < bid && !ret.contains(v) {
+                ret.push(*v);
+            }
+        }
+        ret
+    }
+
+    /// Closest preceding node of did, preferring the lower-latency one among the fingers
+    /// that make equivalent progress, namely their remaining distances to did have the
+    /// same bit length as the closest one's. Fingers without `rtt` are considered slowest.
+    pub fn closest_by_proximity<F>(&self, did: Did, rtt: F) -> Did
+    where F: Fn(Did) -> Option<u128> {
+        let candidates = self.closest_list(did, self.size);
+        let bits = |v: Did| BigUint::from(did - v).bits();
+        let progress = match candidates.first() {
+            Some(v) => bits(*v),
+            None => return self.did,
+        };
+        candidates
+            .into_iter()
+            .take_while(|v| bits(*v) == progress)
+            .enumerate()
+            // Use the index to keep the closer one when RTTs are equal.
+            .min_by_key(|(i, v)| (rtt(*v).unwrap_or(u128::MAX), *i))
+            .map(|(_, v)| v)
+            .unwrap_or(self.did)
+    }
+
     /// get length of finger
     pub fn len(&self) -> usize {
         self.finger.iter().flatten().count()
@@ -310,6 +347,22 @@ mod test {
         assert_eq!(table.finger.len(), 3);
     }
 
+    #[test]
+    fn test_finger_table_closest_list() {
+        let dids = gen_ordered_dids(5);
+        let (id1, id2, id3, id4) = (dids[1], dids[2], dids[3], dids[4]);
+
+        let mut table = FingerTable::new(dids[0], 4);
+        table.set(0, id1);
+        table.set(1, id1);
+        table.set(2, id2);
+        table.set(3, id4);
+
+        assert_eq!(table.closest_list(id3, 3), vec![id2, id1]);
+        assert_eq!(table.closest_list(id3, 1), vec![table.closest(id3)]);
+        assert!(table.closest_list(id1, 3).is_empty());
+    }
+
     #[test]
     fn test_finger_table_remove_then_fill() {
         let dids = gen_ordered_dids(6);
@@ -395,4 +448,48 @@ mod test {
             None
         ]);
     }
+
+    #[test]
+    fn test_finger_table_closest_by_proximity() {
+        let did = |x: u32| Did::from(BigUint::from(x));
+        let (id1, id2, id3, id4) = (did(0x10), did(0x20), did(0x40), did(0x80));
+
+        let mut table = FingerTable::new(did(0), 160);
+        table.join(id1);
+        table.join(id2);
+        table.join(id3);
+        table.join(id4);
+
+        let rtts = |rtts: Vec<(Did, u128)>| {
+            move |x: Did| rtts.iter().find(|(v, _)| *v == x).map(|(_, rtt)| *rtt)
+        };
+
+        // Same as closest when no RTT is measured.
+        assert_eq!(table.closest_by_proximity(did(0x100), rtts(vec![])), id4);
+        assert_eq!(table.closest(did(0x100)), id4);
+
+        // All fingers are 8 bits away from 0x100, so the fastest one is chosen.
+        assert_eq!(
+            table.closest_by_proximity(did(0x100), rtts(vec![(id2, 10), (id4, 50)])),
+            id2
+        );
+        assert_eq!(
+            table.closest_by_proximity(did(0x100), rtts(vec![(id1, 5), (id2, 10), (id4, 50)])),
+            id1
+        );
+        assert_eq!(
+            table.closest_by_proximity(did(0x100), rtts(vec![(id3, 10)])),
+            id3
+        );
+        assert_eq!(
+            table.closest_by_proximity(did(0x100), rtts(vec![(id3, 10), (id4, 10)])),
+            id4
+        );
+
+        // 0x80 is 7 bits away from 0xc0, but others are 8 bits away.
+        assert_eq!(
+            table.closest_by_proximity(did(0xc0), rtts(vec![(id1, 1), (id3, 1), (id4, 50)])),
+            id4
+        );
+    }
 }
@@ -2,6 +2,8 @@
 //!
 //! which is based on CHORD, ref: <https://pdos.csail.mit.edu/papers/ton:chord/paper-ton.pdf>
 //! With high probability, the number of nodes that must be contacted to find a successor in an N-node network is O(log N).
+/// Admission of Dids with proof of work
+pub mod admission;
 pub mod did;
 pub use did::Did;
 mod chord;
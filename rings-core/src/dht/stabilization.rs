@@ -3,11 +3,14 @@ use std::sync::Arc;
 
 use async_trait::async_trait;
 
+use crate::consts::REPUTATION_EVICT_SCORE;
 use crate::dht::Chord;
 use crate::dht::PeerRing;
 use crate::dht::PeerRingAction;
 use crate::dht::PeerRingRemoteAction;
 use crate::err::Result;
+use crate::message::CheckPredecessorSend;
+use crate::message::FindSubringSuccessorSend;
 use crate::message::FindSuccessorReportHandler;
 use crate::message::FindSuccessorSend;
 use crate::message::FindSuccessorThen;
@@ -15,7 +18,10 @@ use crate::message::Message;
 use crate::message::MessagePayload;
 use crate::message::NotifyPredecessorSend;
 use crate::message::PayloadSender;
+use crate::message::PingSend;
 use crate::swarm::Swarm;
+use crate::transports::manager::TransportManager;
+use crate::utils::get_epoch_ms;
 
 /// A combination contains chord and swarm, use to run stabilize.
 /// - swarm: transports communicate with each others.
@@ -109,6 +115,100 @@ impl Stabilization {
         }
     }
 
+    async fn check_predecessor(&self) -> Result<()> {
+        if let PeerRingAction::RemoteAction(predecessor, PeerRingRemoteAction::CheckPredecessor) =
+            self.chord.check_predecessor()?
+        {
+            let payload = MessagePayload::new_send(
+                Message::CheckPredecessorSend(CheckPredecessorSend),
+                self.swarm.session_manager(),
+                predecessor,
+                predecessor,
+            )?;
+            self.chord.start_predecessor_check(payload.tx_id)?;
+            self.swarm.send_payload(payload).await?;
+        }
+        Ok(())
+    }
+
+    /// Notify successor and fix one finger for each joined subring.
+    async fn stabilize_subrings(&self) -> Result<()> {
+        for chord in self.chord.subrings.values() {
+            // a subring restored from storage knows nobody, look for its successor again.
+            if chord.is_alone()? {
+                self.swarm.subring_find_successor(&chord).await?;
+                continue;
+            }
+            let successor = { chord.lock_successor()?.min() };
+            if successor != chord.member {
+                self.swarm
+                    .notify_subring_predecessor(&chord, successor)
+                    .await?;
+            }
+
+            if let PeerRingAction::RemoteAction(
+                next,
+                PeerRingRemoteAction::FindSuccessorForFix(did),
+            ) = chord.fix_fingers()?
+            {
+                let msg = Message::FindSubringSuccessorSend(FindSubringSuccessorSend {
+                    subring: chord.did,
+                    did,
+                });
+                self.swarm.send_message(msg, next).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Renew subscriptions of topics, so that they will follow the topics moved to other nodes.
+    async fn renew_topic_subscriptions(&self) -> Result<()> {
+        self.chord.prune_expired_topic_subscribers();
+        for vid in self.chord.topic_subscriptions.keys() {
+            self.swarm.renew_topic_subscription(vid).await?;
+        }
+        Ok(())
+    }
+
+    /// Update reputation scores of connected nodes from measure, so that routing can avoid
+    /// the unreliable ones. A node whose score is too low will be evicted.
+    async fn update_reputations(&self) -> Result<()> {
+        let measure = match &self.swarm.measure {
+            Some(measure) => measure,
+            None => return Ok(()),
+        };
+        for (did, _) in self.swarm.get_transports() {
+            let score = measure.get_score(did).await;
+            if score < REPUTATION_EVICT_SCORE {
+                tracing::warn!("evict {:?} for reputation score {}", did, score);
+                self.swarm.disconnect(did).await?;
+            } else {
+                self.chord.set_reputation(did, score);
+            }
+        }
+        Ok(())
+    }
+
+    /// Ping connected nodes to measure round-trip times, which are used to prefer
+    /// lower-latency fingers on routing. A failed ping is logged and skipped.
+    pub async fn probe_rtts(&self) -> Result<()> {
+        for (did, _) in self.swarm.get_transports() {
+            let payload = MessagePayload::new_send(
+                Message::PingSend(PingSend {
+                    sent_at: get_epoch_ms(),
+                }),
+                self.swarm.session_manager(),
+                did,
+                did,
+            )?;
+            self.chord.start_ping(did, payload.tx_id);
+            if let Err(e) = self.swarm.send_payload(payload).await {
+                tracing::warn!("[probe_rtts] Failed to ping {:?}: {}", did, e);
+            }
+        }
+        Ok(())
+    }
+
     pub async fn stabilize(&self) -> Result<()> {
         if let Err(e) = self.notify_predecessor().await {
             tracing::error!("[stabilize] Failed on notify predecessor {:?}", e);
@@ -116,6 +216,24 @@ impl Stabilization {
         if let Err(e) = self.fix_fingers().await {
             tracing::error!("[stabilize] Failed on fix_finger {:?}", e);
         }
+        if let Err(e) = self.check_predecessor().await {
+            tracing::error!("[stabilize] Failed on check_predecessor {:?}", e);
+        }
+        if let Err(e) = self.chord.prune_expired_vnodes().await {
+            tracing::error!("[stabilize] Failed on prune_expired_vnodes {:?}", e);
+        }
+        if let Err(e) = self.chord.prune_replicas().await {
+            tracing::error!("[stabilize] Failed on prune_replicas {:?}", e);
+        }
+        if let Err(e) = self.stabilize_subrings().await {
+            tracing::error!("[stabilize] Failed on stabilize_subrings {:?}", e);
+        }
+        if let Err(e) = self.renew_topic_subscriptions().await {
+            tracing::error!("[stabilize] Failed on renew_topic_subscriptions {:?}", e);
+        }
+        if let Err(e) = self.update_reputations().await {
+            tracing::error!("[stabilize] Failed on update_reputations {:?}", e);
+        }
         Ok(())
     }
 }
@@ -141,10 +259,14 @@ mod stabilizer {
                 let timeout = Delay::new(Duration::from_secs(self.timeout as u64)).fuse();
                 pin_mut!(timeout);
                 select! {
-                    _ = timeout => self
-                        .stabilize()
-                        .await
-                        .unwrap_or_else(|e| tracing::error!("failed to stabilize {:?}", e)),
+                    _ = timeout => {
+                        self.stabilize()
+                            .await
+                            .unwrap_or_else(|e| tracing::error!("failed to stabilize {:?}", e));
+                        self.probe_rtts()
+                            .await
+                            .unwrap_or_else(|e| tracing::error!("failed to probe rtts {:?}", e));
+                    }
                 }
             }
         }
@@ -173,6 +295,10 @@ mod stabilizer {
                         .stabilize()
                         .await
                         .unwrap_or_else(|e| tracing::error!("failed to stabilize {:?}", e));
+                    caller
+                        .probe_rtts()
+                        .await
+                        .unwrap_or_else(|e| tracing::error!("failed to probe rtts {:?}", e));
                 }))
             };
             poll!(func, 25000);
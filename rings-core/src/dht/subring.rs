@@ -1,11 +1,21 @@
 #![warn(missing_docs)]
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
 
+use num_bigint::BigUint;
 use serde::Deserialize;
 use serde::Serialize;
 
+use super::did::BiasId;
+use super::successor::SuccessorSeq;
 use super::vnode::VNodeType;
+use super::vnode::VNodeWritePolicy;
 use super::vnode::VirtualNode;
 use super::FingerTable;
+use super::PeerRingAction;
+use super::PeerRingRemoteAction;
+use crate::consts::SUBRING_MAX_ENTRY_POINTS;
 use crate::dht::Did;
 use crate::err::Error;
 use crate::err::Result;
@@ -14,26 +24,44 @@ use crate::err::Result;
 /// Subring also have two extra fields: `name` and `creator`.
 /// Subring can be stored on the a [super::PeerRing].
 /// The did of a subring is the hash of its name.
+///
+/// The stored Subring is only a registry of the subring. Members form their own Chord
+/// ring, see [SubringChord], so that the size of a subring is not limited by a vnode.
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Subring {
     /// name of subring
     pub name: String,
-    /// finger table
-    pub finger: FingerTable,
     /// creator
     pub creator: Did,
+    /// recently joined members, which are used as entry points of the subring.
+    /// At most [SUBRING_MAX_ENTRY_POINTS] members are kept.
+    #[serde(default)]
+    pub members: Vec<Did>,
 }
 
 impl Subring {
     /// Create a new Subring
     pub fn new(name: &str, creator: Did) -> Result<Self> {
-        let did = VirtualNode::gen_did(name)?;
         Ok(Self {
             name: name.to_string(),
-            finger: FingerTable::new(did, 1),
             creator,
+            members: vec![],
         })
     }
+
+    /// Add a member as the latest entry point, the oldest one will be dropped if full.
+    pub fn join(&mut self, did: Did) {
+        self.members.retain(|m| *m != did);
+        self.members.push(did);
+        if self.members.len() > SUBRING_MAX_ENTRY_POINTS {
+            self.members.remove(0);
+        }
+    }
+
+    /// Remove a member from entry points.
+    pub fn leave(&mut self, did: Did) {
+        self.members.retain(|m| *m != did);
+    }
 }
 
 impl TryFrom<Subring> for VirtualNode {
@@ -44,21 +72,342 @@ impl TryFrom<Subring> for VirtualNode {
             did: Self::gen_did(&ring.name)?,
             data: vec![data.into()],
             kind: VNodeType::Subring,
+            version: 0,
+            owner: None,
+            policy: VNodeWritePolicy::Public,
+            expires: vec![],
         })
     }
 }
 
+/// Subring stored by earlier versions recorded its members in a finger table.
+#[derive(Deserialize)]
+struct LegacySubring {
+    #[serde(default)]
+    finger: Option<FingerTable>,
+}
+
 impl TryFrom<VirtualNode> for Subring {
     type Error = Error;
     fn try_from(vnode: VirtualNode) -> Result<Self> {
         match &vnode.kind {
             VNodeType::Subring => {
                 let decoded: String = vnode.data[0].decode()?;
-                let subring: Subring =
+                let mut subring: Subring =
                     serde_json::from_str(&decoded).map_err(Error::Deserialize)?;
+                if subring.members.is_empty() {
+                    let legacy: LegacySubring =
+                        serde_json::from_str(&decoded).map_err(Error::Deserialize)?;
+                    if let Some(finger) = legacy.finger {
+                        for did in finger.list().iter().flatten() {
+                            subring.join(*did);
+                        }
+                    }
+                }
                 Ok(subring)
             }
             _ => Err(Error::InvalidVNodeType),
         }
     }
 }
+
+/// SubringChord is the Chord ring formed by members of a [Subring], viewed from one
+/// of its members. It works like [super::PeerRing] without storage, but only members
+/// of the subring are recorded in its finger table and successor sequence.
+/// Messages between members are still delivered by [super::PeerRing].
+#[derive(Clone, Debug)]
+pub struct SubringChord {
+    /// name of subring
+    pub name: String,
+    /// did of subring
+    pub did: Did,
+    /// did of current member
+    pub member: Did,
+    /// finger table over other members
+    pub finger: Arc<Mutex<FingerTable>>,
+    /// next members on the subring
+    pub successor_seq: Arc<Mutex<SuccessorSeq>>,
+    /// previous member on the subring
+    pub predecessor: Arc<Mutex<Option<Did>>>,
+}
+
+impl SubringChord {
+    /// Create a subring chord which contains current member only.
+    pub fn new(name: &str, member: Did) -> Result<Self> {
+        Ok(Self {
+            name: name.to_string(),
+            did: VirtualNode::gen_did(name)?,
+            member,
+            // for Eth address, it's 160
+            finger: Arc::new(Mutex::new(FingerTable::new(member, 160))),
+            successor_seq: Arc::new(Mutex::new(SuccessorSeq::new(member, 3))),
+            predecessor: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    /// Lock and return MutexGuard of successor sequence.
+    pub fn lock_successor(&self) -> Result<MutexGuard<SuccessorSeq>> {
+        self.successor_seq
+            .lock()
+            .map_err(|_| Error::DHTSyncLockError)
+    }
+
+    /// Lock and return MutexGuard of finger table.
+    pub fn lock_finger(&self) -> Result<MutexGuard<FingerTable>> {
+        self.finger.lock().map_err(|_| Error::DHTSyncLockError)
+    }
+
+    /// Lock and return MutexGuard of predecessor.
+    pub fn lock_predecessor(&self) -> Result<MutexGuard<Option<Did>>> {
+        self.predecessor.lock().map_err(|_| Error::DHTSyncLockError)
+    }
+
+    /// Calculate bias of the Did on the subring.
+    pub fn bias(&self, did: Did) -> BiasId {
+        BiasId::new(self.member, did)
+    }
+
+    /// Record a member of subring in finger table and successor sequence.
+    /// Return true if it becomes the closest successor, which should be notified.
+    pub fn join(&self, did: Did) -> Result<bool> {
+        if did == self.member {
+            return Ok(false);
+        }
+
+        let mut finger = self.lock_finger()?;
+        let mut successor = self.lock_successor()?;
+
+        finger.join(did);
+        if self.bias(did) < self.bias(successor.max()) || !successor.is_full() {
+            successor.update(did);
+        }
+
+        Ok(successor.min() == did)
+    }
+
+    /// Find the successor of a Did among members.
+    /// May return a remote action for the successor is recorded in another member.
+    pub fn find_successor(&self, did: Did) -> Result<PeerRingAction> {
+        let successor = self.lock_successor()?;
+        let finger = self.lock_finger()?;
+
+        if successor.is_empty() || self.bias(did) <= self.bias(successor.min()) {
+            return Ok(PeerRingAction::Some(successor.min()));
+        }
+
+        match finger.closest(did) {
+            closest if closest == self.member => Ok(PeerRingAction::Some(successor.min())),
+            closest => Ok(PeerRingAction::RemoteAction(
+                closest,
+                PeerRingRemoteAction::FindSuccessor(did),
+            )),
+        }
+    }
+
+    /// Handle notification from a member that thinks it is the predecessor of current member.
+    /// This method will return that did if it is set to the predecessor.
+    pub fn notify(&self, did: Did) -> Result<Option<Did>> {
+        if did == self.member {
+            return Ok(None);
+        }
+
+        let mut predecessor = self.lock_predecessor()?;
+        match *predecessor {
+            Some(pre) if self.bias(pre) >= self.bias(did) => Ok(None),
+            _ => {
+                *predecessor = Some(did);
+                Ok(Some(did))
+            }
+        }
+    }
+
+    /// Fix one finger of finger table by finding its successor.
+    /// According to the paper, this method should be called periodically.
+    pub fn fix_fingers(&self) -> Result<PeerRingAction> {
+        let fix_finger_index = {
+            let mut finger = self.lock_finger()?;
+            finger.fix_finger_index = (finger.fix_finger_index + 1) % 159;
+            finger.fix_finger_index
+        };
+
+        let did: BigUint = (BigUint::from(self.member)
+            + BigUint::from(2u16).pow(fix_finger_index.into()))
+            % BigUint::from(2u16).pow(160);
+
+        match self.find_successor(did.into())? {
+            PeerRingAction::Some(v) => {
+                self.join(v)?;
+                Ok(PeerRingAction::None)
+            }
+            PeerRingAction::RemoteAction(next, PeerRingRemoteAction::FindSuccessor(did)) => Ok(
+                PeerRingAction::RemoteAction(next, PeerRingRemoteAction::FindSuccessorForFix(did)),
+            ),
+            act => Err(Error::PeerRingUnexpectedAction(act)),
+        }
+    }
+
+    /// Remove a member from finger table, successor sequence and predecessor.
+    pub fn remove(&self, did: Did) -> Result<()> {
+        let mut finger = self.lock_finger()?;
+        let mut successor = self.lock_successor()?;
+        let mut predecessor = self.lock_predecessor()?;
+        if *predecessor == Some(did) {
+            *predecessor = None;
+        }
+        finger.remove(did);
+        successor.remove(did);
+        if successor.is_empty() {
+            if let Some(x) = finger.first() {
+                successor.update(x);
+            }
+        }
+        Ok(())
+    }
+
+    /// Check if current member doesn't know any other member, e.g. it's just loaded
+    /// after restart, so it should look for its successor again.
+    pub fn is_alone(&self) -> Result<bool> {
+        Ok(self.lock_successor()?.min() == self.member && self.lock_predecessor()?.is_none())
+    }
+
+    /// Check if a did is known as a member by current member.
+    pub fn is_known(&self, did: Did) -> Result<bool> {
+        Ok(self.neighbors()?.contains(&did))
+    }
+
+    /// List other members known by current member, in clockwise order.
+    pub fn neighbors(&self) -> Result<Vec<Did>> {
+        let mut dids = self
+            .lock_finger()?
+            .list()
+            .iter()
+            .flatten()
+            .copied()
+            .chain(self.lock_successor()?.list())
+            .chain(*self.lock_predecessor()?)
+            .filter(|did| *did != self.member)
+            .collect::<Vec<_>>();
+        dids.sort_by_key(|did| self.bias(*did));
+        dids.dedup();
+        Ok(dids)
+    }
+
+    /// Split the range `(member, limit)` of subring by known members, to forward a broadcast.
+    /// Each member in the range is returned with the limit of the sub-range it is
+    /// responsible for. When `limit` is current member, the range is the whole subring.
+    pub fn broadcast_targets(&self, limit: Did) -> Result<Vec<(Did, Did)>> {
+        let mut targets = self
+            .lock_finger()?
+            .list()
+            .iter()
+            .flatten()
+            .copied()
+            .collect::<Vec<_>>();
+        targets.extend(self.lock_successor()?.list());
+        targets.retain(|did| {
+            *did != self.member && (limit == self.member || self.bias(*did) < self.bias(limit))
+        });
+        targets.sort_by_key(|did| self.bias(*did));
+        targets.dedup();
+
+        let limits = targets.iter().skip(1).copied().chain(Some(limit));
+        Ok(targets.iter().copied().zip(limits).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::dht::tests::gen_ordered_dids;
+
+    /// Deliver a broadcast from `origin` in memory, and count how many times each member receives it.
+    fn simulate_broadcast(chords: &HashMap<Did, SubringChord>, origin: Did) -> HashMap<Did, u32> {
+        let mut received = HashMap::new();
+        let mut queue = chords[&origin].broadcast_targets(origin).unwrap();
+        while let Some((did, limit)) = queue.pop() {
+            *received.entry(did).or_insert(0) += 1;
+            queue.extend(chords[&did].broadcast_targets(limit).unwrap());
+        }
+        received
+    }
+
+    #[test]
+    fn test_subring_entry_points() {
+        let dids = gen_ordered_dids(SUBRING_MAX_ENTRY_POINTS + 2);
+        let mut subring = Subring::new("group", dids[0]).unwrap();
+        for did in dids.iter() {
+            subring.join(*did);
+        }
+        // Rejoin moves a member to the latest.
+        subring.join(dids[2]);
+
+        assert_eq!(subring.members.len(), SUBRING_MAX_ENTRY_POINTS);
+        assert!(!subring.members.contains(&dids[0]));
+        assert!(!subring.members.contains(&dids[1]));
+        assert_eq!(subring.members.last(), Some(&dids[2]));
+
+        subring.leave(dids[2]);
+        assert!(!subring.members.contains(&dids[2]));
+    }
+
+    #[test]
+    fn test_subring_chord_find_successor() {
+        let dids = gen_ordered_dids(5);
+        let chord = SubringChord::new("group", dids[0]).unwrap();
+        assert_eq!(
+            chord.find_successor(dids[3]).unwrap(),
+            PeerRingAction::Some(dids[0])
+        );
+
+        assert!(chord.join(dids[3]).unwrap());
+        assert!(chord.join(dids[1]).unwrap());
+        assert!(!chord.join(dids[2]).unwrap());
+
+        assert_eq!(
+            chord.find_successor(dids[1]).unwrap(),
+            PeerRingAction::Some(dids[1])
+        );
+        assert!(matches!(
+            chord.find_successor(dids[4]).unwrap(),
+            PeerRingAction::RemoteAction(_, PeerRingRemoteAction::FindSuccessor(did)) if did == dids[4]
+        ));
+
+        assert_eq!(chord.notify(dids[3]).unwrap(), Some(dids[3]));
+        assert_eq!(chord.notify(dids[2]).unwrap(), None);
+        assert_eq!(chord.notify(dids[4]).unwrap(), Some(dids[4]));
+
+        chord.remove(dids[1]).unwrap();
+        assert_eq!(chord.lock_successor().unwrap().min(), dids[2]);
+        assert_eq!(chord.neighbors().unwrap(), vec![dids[2], dids[3], dids[4]]);
+    }
+
+    #[test]
+    fn test_subring_chord_broadcast() {
+        let dids = gen_ordered_dids(12);
+        let chords = dids
+            .iter()
+            .map(|did| (*did, SubringChord::new("group", *did).unwrap()))
+            .collect::<HashMap<_, _>>();
+
+        // Every member only knows its successor, so the broadcast goes around the subring.
+        for (i, did) in dids.iter().enumerate() {
+            chords[did].join(dids[(i + 1) % dids.len()]).unwrap();
+        }
+        let received = simulate_broadcast(&chords, dids[5]);
+        assert_eq!(received.len(), dids.len() - 1);
+        assert!(received.values().all(|n| *n == 1));
+        assert!(!received.contains_key(&dids[5]));
+
+        // With full finger tables, every member still receives it exactly once.
+        for a in dids.iter() {
+            for b in dids.iter() {
+                chords[a].join(*b).unwrap();
+            }
+        }
+        let received = simulate_broadcast(&chords, dids[5]);
+        assert_eq!(received.len(), dids.len() - 1);
+        assert!(received.values().all(|n| *n == 1));
+    }
+}
@@ -40,8 +40,6 @@ pub trait Chord<Action> {
     /// According to the paper, only one finger should be fixed at a time.
     fn fix_fingers(&self) -> Result<Action>;
 
-    //TODO: Why `check_predecessor` is not using?
-
     /// Check if predecessor is alive.
     /// According to the paper, this method should be called periodically.
     fn check_predecessor(&self) -> Result<Action>;
@@ -71,13 +69,26 @@ pub trait ChordStorage<Action>: Chord<Action> {
     /// Look up a VirtualNode by its Did.
     /// Always finds resource by DHT, ignoring the local cache.
     async fn vnode_lookup(&self, vid: Did) -> Result<Action>;
+    /// Look up VirtualNodes in the clockwise range from `start` to `end`, at most `limit`
+    /// of them. The range is the whole ring if `start` equals `end`, and a zero `limit` is
+    /// rejected. May return a remote action for the range is continued in another node.
+    async fn vnode_range(&self, start: Did, end: Did, limit: usize) -> Result<Action>;
     /// Store `vnode` if it's between current node and the successor of current node,
     /// otherwise find the responsible node and return as Action.
-    async fn vnode_operate(&self, op: VNodeOperation) -> Result<Action>;
+    /// When stored locally, the returned Action tells which successors should keep a replica.
+    /// The write policy of stored `VirtualNode` is checked against `operator`.
+    async fn vnode_operate(&self, op: VNodeOperation, operator: Did) -> Result<Action>;
     /// When the successor of a node is updated, it needs to check if there are
     /// `VirtualNode`s that are no longer between current node and `new_successor`,
     /// and sync them to the new successor.
     async fn sync_vnode_with_successor(&self, new_successor: Did) -> Result<Action>;
+    /// Take over a `VirtualNode` handed off by a node which is no longer responsible for it.
+    /// It's only taken from a neighbor, for vids in the range of current node.
+    /// A stored one can only be replaced by `operator` who is allowed to overwrite it.
+    async fn vnode_handoff(&self, vnode: VirtualNode, operator: Did) -> Result<()>;
+    /// Apply [VNodeOperation] to the replica of a `VirtualNode` whose `primary` is one of
+    /// the predecessors of current node.
+    async fn vnode_replicate(&self, primary: Did, op: VNodeOperation) -> Result<()>;
     /// Cache fetched resource locally.
     fn local_cache_set(&self, vnode: VirtualNode);
     /// Get local cache.
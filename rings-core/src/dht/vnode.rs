@@ -16,6 +16,7 @@ use crate::err::Result;
 use crate::message::Encoded;
 use crate::message::Encoder;
 use crate::message::MessagePayload;
+use crate::utils::get_epoch_ms;
 
 /// VNode Types
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -29,12 +30,24 @@ pub enum VNodeType {
     RelayMessage,
 }
 
+/// Write policy of a VirtualNode, which is enforced by the storing node.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VNodeWritePolicy {
+    /// Anyone can operate the VirtualNode.
+    #[default]
+    Public,
+    /// Only the owner can operate the VirtualNode.
+    OwnerOnly,
+    /// Anyone can extend or touch the VirtualNode, but only the owner can overwrite it.
+    AppendOnly,
+}
+
 /// VNode Operations
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum VNodeOperation {
     /// Create or update a VirtualNode
     Overwrite(VirtualNode),
-    /// Extend data to a Data type VirtualNode.
+    /// Extend data to a Data or RelayMessage type VirtualNode.
     /// This operation will create VirtualNode if it's not existed.
     Extend(VirtualNode),
     /// Extend data to a Data type VirtualNode uniquely.
@@ -43,6 +56,11 @@ pub enum VNodeOperation {
     Touch(VirtualNode),
     /// Join subring.
     JoinSubring(String, Did),
+    /// Leave subring.
+    LeaveSubring(String, Did),
+    /// Overwrite a Data type VirtualNode only if its current version equals to the given one.
+    /// A stale write will be rejected with [Error::VNodeVersionConflict].
+    CompareAndSwap(VirtualNode, u64),
 }
 
 /// A `VirtualNode` is a piece of data with [VNodeType] and [Did]. You can save it to
@@ -62,6 +80,20 @@ pub struct VirtualNode {
     pub data: Vec<Encoded>,
     /// The type indicates how the data is encoded and how the Did is generated.
     pub kind: VNodeType,
+    /// The version of `VirtualNode`, increased by every successful [VNodeOperation].
+    /// A `VirtualNode` that is not stored yet has version 0.
+    #[serde(default)]
+    pub version: u64,
+    /// The owner of `VirtualNode`, which is recorded when it's created.
+    #[serde(default)]
+    pub owner: Option<Did>,
+    /// The write policy of `VirtualNode`, which is recorded when it's created.
+    #[serde(default)]
+    pub policy: VNodeWritePolicy,
+    /// Expiry timestamps (in milliseconds) of entries in `data`, aligned by index.
+    /// An entry without expiry timestamp will never expire.
+    #[serde(default)]
+    pub expires: Vec<Option<u128>>,
 }
 
 impl VirtualNode {
@@ -70,6 +102,80 @@ impl VirtualNode {
         let hash: HashStr = topic.into();
         Did::from_str(&hash.inner())
     }
+
+    /// Generate did of the [VNodeType::RelayMessage] VirtualNode for a destination.
+    pub fn gen_relay_message_did(destination: Did) -> Did {
+        (BigUint::from(destination) + BigUint::from(1u16)).into()
+    }
+
+    /// Set owner and write policy, which take effect when the VirtualNode is created.
+    /// The storing node records the signed writer as owner instead of the given one.
+    pub fn with_owner(mut self, owner: Did, policy: VNodeWritePolicy) -> Self {
+        self.owner = Some(owner);
+        self.policy = policy;
+        self
+    }
+
+    /// Set all entries in `data` to be expired after `ttl_ms` milliseconds.
+    pub fn with_ttl(mut self, ttl_ms: u128) -> Self {
+        let expires_at = get_epoch_ms() + ttl_ms;
+        self.expires = vec![Some(expires_at); self.data.len()];
+        self
+    }
+
+    /// Get expiry timestamps aligned with `data`.
+    fn aligned_expires(&self) -> Vec<Option<u128>> {
+        let mut expires = self.expires.clone();
+        expires.resize(self.data.len(), None);
+        expires
+    }
+
+    /// Check if there are entries expired at `now`.
+    /// Expiry timestamps beyond the length of `data` are ignored.
+    pub fn has_expired(&self, now: u128) -> bool {
+        self.aligned_expires().iter().flatten().any(|t| *t <= now)
+    }
+
+    /// Remove entries expired at `now`.
+    pub fn prune_expired(&self, now: u128) -> Self {
+        let (data, expires) = self
+            .data
+            .iter()
+            .zip(self.aligned_expires())
+            .filter(|(_, t)| t.map(|t| t > now).unwrap_or(true))
+            .map(|(e, t)| (e.clone(), t))
+            .unzip();
+        Self {
+            data,
+            expires,
+            ..self.clone()
+        }
+    }
+
+    /// Check if `operator` is allowed to apply `op` on current VirtualNode.
+    /// The owner is always allowed.
+    pub fn check_permission(&self, op: &VNodeOperation, operator: Did) -> Result<()> {
+        if self.owner == Some(operator) {
+            return Ok(());
+        }
+        match (self.policy, op) {
+            // A member can only be added or removed by itself or the creator of subring.
+            (_, VNodeOperation::JoinSubring(_, did) | VNodeOperation::LeaveSubring(_, did)) => {
+                let subring: Subring = self.clone().try_into()?;
+                if operator == *did || operator == subring.creator {
+                    Ok(())
+                } else {
+                    Err(Error::VNodeWriteForbidden(operator))
+                }
+            }
+            (VNodeWritePolicy::Public, _) => Ok(()),
+            (
+                VNodeWritePolicy::AppendOnly,
+                VNodeOperation::Extend(_) | VNodeOperation::Touch(_),
+            ) => Ok(()),
+            _ => Err(Error::VNodeWriteForbidden(operator)),
+        }
+    }
 }
 
 impl VNodeOperation {
@@ -80,6 +186,8 @@ impl VNodeOperation {
             VNodeOperation::Extend(vnode) => vnode.did,
             VNodeOperation::Touch(vnode) => vnode.did,
             VNodeOperation::JoinSubring(name, _) => VirtualNode::gen_did(name)?,
+            VNodeOperation::LeaveSubring(name, _) => VirtualNode::gen_did(name)?,
+            VNodeOperation::CompareAndSwap(vnode, _) => vnode.did,
         })
     }
 
@@ -90,17 +198,28 @@ impl VNodeOperation {
             VNodeOperation::Extend(vnode) => vnode.kind,
             VNodeOperation::Touch(vnode) => vnode.kind,
             VNodeOperation::JoinSubring(..) => VNodeType::Subring,
+            VNodeOperation::LeaveSubring(..) => VNodeType::Subring,
+            VNodeOperation::CompareAndSwap(vnode, _) => vnode.kind,
         }
     }
 
     /// Generate a target VirtualNode when it is not existed.
+    /// The owner and write policy are inherited from the operated VirtualNode.
     pub fn gen_default_vnode(self) -> Result<VirtualNode> {
         match self {
             VNodeOperation::JoinSubring(name, did) => Subring::new(&name, did)?.try_into(),
-            _ => Ok(VirtualNode {
-                did: self.did()?,
+            VNodeOperation::LeaveSubring(name, _) => Err(Error::SubringNotFound(name)),
+            VNodeOperation::Overwrite(vnode)
+            | VNodeOperation::Extend(vnode)
+            | VNodeOperation::Touch(vnode)
+            | VNodeOperation::CompareAndSwap(vnode, _) => Ok(VirtualNode {
+                did: vnode.did,
                 data: vec![],
-                kind: self.kind(),
+                kind: vnode.kind,
+                version: 0,
+                owner: vnode.owner,
+                policy: vnode.policy,
+                expires: vec![],
             }),
         }
     }
@@ -111,12 +230,16 @@ where T: Serialize + DeserializeOwned
 {
     type Error = Error;
     fn try_from(msg: MessagePayload<T>) -> Result<Self> {
-        let did = BigUint::from(msg.addr) + BigUint::from(1u16);
+        let did = Self::gen_relay_message_did(msg.relay.destination);
         let data = msg.encode()?;
         Ok(Self {
-            did: did.into(),
+            did,
             data: vec![data],
             kind: VNodeType::RelayMessage,
+            version: 0,
+            owner: None,
+            policy: VNodeWritePolicy::Public,
+            expires: vec![],
         })
     }
 }
@@ -128,6 +251,10 @@ impl TryFrom<(String, Encoded)> for VirtualNode {
             did: Self::gen_did(&topic)?,
             data: vec![e],
             kind: VNodeType::Data,
+            version: 0,
+            owner: None,
+            policy: VNodeWritePolicy::Public,
+            expires: vec![],
         })
     }
 }
@@ -150,13 +277,24 @@ impl TryFrom<String> for VirtualNode {
 impl VirtualNode {
     /// The entry point of [VNodeOperation].
     /// Will dispatch to different operation handlers according to the variant.
+    /// The version of result is always the version of current one plus 1, the version
+    /// carried by the written `VirtualNode` is ignored. A `VirtualNode` handed off from
+    /// another node keeps its version since it's not written by operation.
+    /// The owner and write policy of current one are always kept.
     pub fn operate(&self, op: VNodeOperation) -> Result<Self> {
-        match op {
+        let mut vnode = match op {
             VNodeOperation::Overwrite(vnode) => self.overwrite(vnode),
             VNodeOperation::Extend(vnode) => self.extend(vnode),
             VNodeOperation::Touch(vnode) => self.touch(vnode),
             VNodeOperation::JoinSubring(_, did) => self.join_subring(did),
-        }
+            VNodeOperation::LeaveSubring(_, did) => self.leave_subring(did),
+            VNodeOperation::CompareAndSwap(vnode, version) => self.compare_and_swap(vnode, version),
+        }?;
+        vnode.version = self.version.saturating_add(1);
+        vnode.expires = vnode.aligned_expires();
+        vnode.owner = self.owner;
+        vnode.policy = self.policy;
+        Ok(vnode)
     }
 
     /// Overwrite current data with new data.
@@ -174,10 +312,19 @@ impl VirtualNode {
         Ok(other)
     }
 
-    /// This method is used to extend data to a Data type VirtualNode.
+    /// Overwrite current data with new data if current version is the expected one.
+    /// The handler of [VNodeOperation::CompareAndSwap].
+    pub fn compare_and_swap(&self, other: Self, version: u64) -> Result<Self> {
+        if self.version != version {
+            return Err(Error::VNodeVersionConflict(version, self.version));
+        }
+        self.overwrite(other)
+    }
+
+    /// This method is used to extend data to a Data or RelayMessage type VirtualNode.
     /// The handler of [VNodeOperation::Extend].
     pub fn extend(&self, other: Self) -> Result<Self> {
-        if self.kind != VNodeType::Data {
+        if !matches!(self.kind, VNodeType::Data | VNodeType::RelayMessage) {
             return Err(Error::VNodeNotAppendable);
         }
         if self.kind != other.kind {
@@ -195,10 +342,21 @@ impl VirtualNode {
         let mut data = self.data.iter().skip(trim_num).cloned().collect::<Vec<_>>();
         data.extend_from_slice(&other.data);
 
+        let mut expires = self
+            .aligned_expires()
+            .into_iter()
+            .skip(trim_num)
+            .collect::<Vec<_>>();
+        expires.extend(other.aligned_expires());
+
         Ok(Self {
             did: self.did,
             data,
             kind: self.kind,
+            version: self.version,
+            owner: self.owner,
+            policy: self.policy,
+            expires,
         })
     }
 
@@ -219,7 +377,8 @@ impl VirtualNode {
         let remains = self
             .data
             .iter()
-            .filter(|e| !other.data.contains(e))
+            .zip(self.aligned_expires())
+            .filter(|(e, _)| !other.data.contains(e))
             .collect::<Vec<_>>();
 
         let trim_num = max(
@@ -227,17 +386,22 @@ impl VirtualNode {
             (remains.len() + other.data.len()) as i64 - VNODE_DATA_MAX_LEN as i64,
         ) as usize;
 
-        let mut data = remains
+        let (mut data, mut expires): (Vec<_>, Vec<_>) = remains
             .into_iter()
             .skip(trim_num)
-            .cloned()
-            .collect::<Vec<_>>();
+            .map(|(e, t)| (e.clone(), t))
+            .unzip();
         data.extend_from_slice(&other.data);
+        expires.extend(other.aligned_expires());
 
         Ok(Self {
             did: self.did,
             data,
             kind: self.kind,
+            version: self.version,
+            owner: self.owner,
+            policy: self.policy,
+            expires,
         })
     }
 
@@ -249,7 +413,19 @@ impl VirtualNode {
         }
 
         let mut subring: Subring = self.clone().try_into()?;
-        subring.finger.join(did);
+        subring.join(did);
+        subring.try_into()
+    }
+
+    /// This method is used to leave a subring.
+    /// The handler of [VNodeOperation::LeaveSubring].
+    pub fn leave_subring(&self, did: Did) -> Result<Self> {
+        if self.kind != VNodeType::Subring {
+            return Err(Error::VNodeNotJoinable);
+        }
+
+        let mut subring: Subring = self.clone().try_into()?;
+        subring.leave(did);
         subring.try_into()
     }
 }
@@ -257,6 +433,8 @@ impl VirtualNode {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ecc::SecretKey;
+    use crate::session::SessionManager;
 
     #[test]
     fn test_vnode_extend_over_max_len() {
@@ -299,4 +477,191 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_vnode_compare_and_swap() {
+        let topic = "config".to_string();
+        let v1: VirtualNode = (topic.clone(), "v1".to_string()).try_into().unwrap();
+        let v2: VirtualNode = (topic.clone(), "v2".to_string()).try_into().unwrap();
+        let v3: VirtualNode = (topic, "v3".to_string()).try_into().unwrap();
+
+        let vnode = VNodeOperation::CompareAndSwap(v1.clone(), 0)
+            .gen_default_vnode()
+            .unwrap();
+        assert_eq!(vnode.version, 0);
+
+        let vnode = vnode
+            .operate(VNodeOperation::CompareAndSwap(v1, 0))
+            .unwrap();
+        assert_eq!(vnode.version, 1);
+
+        let vnode = vnode
+            .operate(VNodeOperation::CompareAndSwap(v2.clone(), 1))
+            .unwrap();
+        assert_eq!(vnode.version, 2);
+        assert_eq!(vnode.data, v2.data);
+
+        // A writer who read version 1 is rejected.
+        assert!(matches!(
+            vnode.operate(VNodeOperation::CompareAndSwap(v3.clone(), 1)),
+            Err(Error::VNodeVersionConflict(1, 2))
+        ));
+
+        // The version carried by a writer is ignored.
+        let forged = VirtualNode {
+            version: u64::MAX,
+            ..v3
+        };
+        let vnode = vnode.operate(VNodeOperation::Overwrite(forged)).unwrap();
+        assert_eq!(vnode.version, 3);
+    }
+
+    #[test]
+    fn test_vnode_write_policy() {
+        let owner: Did = SecretKey::random().address().into();
+        let other: Did = SecretKey::random().address().into();
+        let topic = "service".to_string();
+
+        let vnode: VirtualNode = topic.clone().try_into().unwrap();
+        let overwrite = VNodeOperation::Overwrite(vnode.clone());
+        let extend = VNodeOperation::Extend(vnode.clone());
+
+        // Public VirtualNode can be operated by anyone.
+        assert!(vnode.check_permission(&overwrite, other).is_ok());
+
+        // Owner and policy are recorded on creation and kept by later operations.
+        let stored = VNodeOperation::Extend(
+            vnode
+                .clone()
+                .with_owner(owner, VNodeWritePolicy::AppendOnly),
+        )
+        .gen_default_vnode()
+        .unwrap()
+        .operate(extend.clone())
+        .unwrap();
+        assert_eq!(stored.owner, Some(owner));
+        assert_eq!(stored.policy, VNodeWritePolicy::AppendOnly);
+
+        assert!(stored.check_permission(&extend, other).is_ok());
+        assert!(stored.check_permission(&overwrite, owner).is_ok());
+        assert!(matches!(
+            stored.check_permission(&overwrite, other),
+            Err(Error::VNodeWriteForbidden(did)) if did == other
+        ));
+
+        let stored = vnode.with_owner(owner, VNodeWritePolicy::OwnerOnly);
+        assert!(stored.check_permission(&extend, owner).is_ok());
+        assert!(stored.check_permission(&extend, other).is_err());
+    }
+
+    #[test]
+    fn test_vnode_entry_expiry() {
+        let topic = "service".to_string();
+        let forever: VirtualNode = (topic.clone(), "did1".to_string()).try_into().unwrap();
+        let short: VirtualNode = (topic.clone(), "did2".to_string()).try_into().unwrap();
+        let short = short.with_ttl(1000);
+        let expires_at = short.expires[0].unwrap();
+
+        let vnode = forever.touch(short.clone()).unwrap();
+        assert_eq!(vnode.data.len(), 2);
+        assert_eq!(vnode.expires, vec![None, Some(expires_at)]);
+        assert!(!vnode.has_expired(expires_at - 1));
+        assert!(vnode.has_expired(expires_at));
+
+        // Touch again will renew the expiry timestamp.
+        let renewed = short.with_ttl(2000);
+        let vnode = vnode.touch(renewed.clone()).unwrap();
+        assert_eq!(vnode.data.len(), 2);
+        assert_eq!(vnode.expires[1], renewed.expires[0]);
+
+        let pruned = vnode.prune_expired(renewed.expires[0].unwrap());
+        assert_eq!(pruned.data, forever.data);
+        assert_eq!(pruned.expires, vec![None]);
+
+        // Expiry timestamps without entries are dropped instead of being pruned every time.
+        let overlong = VirtualNode {
+            expires: vec![None, Some(0)],
+            ..forever.clone()
+        };
+        assert!(!overlong.has_expired(expires_at));
+        let vnode = forever
+            .operate(VNodeOperation::Overwrite(overlong))
+            .unwrap();
+        assert_eq!(vnode.expires, vec![None]);
+    }
+
+    #[test]
+    fn test_vnode_subring_membership() {
+        let creator: Did = SecretKey::random().address().into();
+        let member: Did = SecretKey::random().address().into();
+        let other: Did = SecretKey::random().address().into();
+        let name = "group".to_string();
+
+        let join = |did| VNodeOperation::JoinSubring(name.clone(), did);
+        let leave = |did| VNodeOperation::LeaveSubring(name.clone(), did);
+
+        assert!(matches!(
+            leave(member).gen_default_vnode(),
+            Err(Error::SubringNotFound(_))
+        ));
+
+        let vnode = join(creator).gen_default_vnode().unwrap();
+        let vnode = vnode.operate(join(creator)).unwrap();
+        let vnode = vnode.operate(join(member)).unwrap();
+        let vnode = vnode.operate(join(member)).unwrap();
+        let subring: Subring = vnode.clone().try_into().unwrap();
+        assert_eq!(subring.creator, creator);
+        assert_eq!(subring.members, vec![creator, member]);
+
+        // A member can be removed by itself or the creator only.
+        assert!(vnode.check_permission(&leave(member), member).is_ok());
+        assert!(vnode.check_permission(&leave(member), creator).is_ok());
+        assert!(vnode.check_permission(&leave(member), other).is_err());
+        assert!(vnode.check_permission(&join(other), other).is_ok());
+        assert!(vnode.check_permission(&join(other), creator).is_ok());
+        assert!(vnode.check_permission(&join(other), member).is_err());
+
+        let vnode = vnode.operate(leave(member)).unwrap();
+        let subring: Subring = vnode.try_into().unwrap();
+        assert_eq!(subring.members, vec![creator]);
+    }
+
+    #[test]
+    fn test_relay_message_vnode() {
+        let session = SessionManager::new_with_seckey(&SecretKey::random(), None).unwrap();
+        let dest1: Did = SecretKey::random().address().into();
+        let dest2: Did = SecretKey::random().address().into();
+
+        let payload1 =
+            MessagePayload::new_send("hello".to_string(), &session, dest1, dest1).unwrap();
+        let payload2 =
+            MessagePayload::new_send("world".to_string(), &session, dest1, dest1).unwrap();
+        let payload3 =
+            MessagePayload::new_send("hello".to_string(), &session, dest2, dest2).unwrap();
+
+        let vnode: VirtualNode = payload1.clone().try_into().unwrap();
+        assert_eq!(vnode.kind, VNodeType::RelayMessage);
+        assert_eq!(vnode.did, VirtualNode::gen_relay_message_did(dest1));
+        assert_eq!(
+            BigUint::from(vnode.did),
+            BigUint::from(dest1) + BigUint::from(1u16)
+        );
+
+        // Messages to different destinations cannot be collected together.
+        let other: VirtualNode = payload3.try_into().unwrap();
+        assert!(matches!(vnode.extend(other), Err(Error::VNodeDidNotEqual)));
+
+        // Messages to the same destination should be collected in one vnode.
+        let other: VirtualNode = payload2.clone().try_into().unwrap();
+        let vnode = vnode.extend(other).unwrap();
+        assert_eq!(vnode.data.len(), 2);
+        assert_eq!(
+            vnode.data[0].decode::<MessagePayload<String>>().unwrap(),
+            payload1
+        );
+        assert_eq!(
+            vnode.data[1].decode::<MessagePayload<String>>().unwrap(),
+            payload2
+        );
+    }
 }
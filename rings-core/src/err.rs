@@ -37,6 +37,54 @@ pub enum Error {
     #[error("The type of VirtualNode is not allowed to be joined as a subring")]
     VNodeNotJoinable,
 
+    #[error("Subring {0} is not existed")]
+    SubringNotFound(String),
+
+    #[error("Current node is not a member of subring {0}")]
+    SubringNotJoined(crate::dht::Did),
+
+    #[error("{0} is not a known member of subring {1}")]
+    SubringMemberUnknown(crate::dht::Did, crate::dht::Did),
+
+    #[error("Current node is not subscribing topic {0}")]
+    TopicNotSubscribed(crate::dht::Did),
+
+    #[error("{0} is not the node storing topic {1}")]
+    TopicNotStoredBy(crate::dht::Did, crate::dht::Did),
+
+    #[error("Iterative lookup for successor of {0} failed, stalled hops: {1:?}")]
+    IterativeLookupFailed(crate::dht::Did, Vec<crate::dht::Did>),
+
+    #[error("Peer {0} is evicted for its reputation score {1}")]
+    PeerEvicted(crate::dht::Did, f64),
+
+    #[error("Peer {0} is blocked")]
+    PeerBlocked(crate::dht::Did),
+
+    #[error("Did {0} is not admitted by the difficulty of network")]
+    DidNotAdmitted(crate::dht::Did),
+
+    #[error("Network id mismatch, expected {0}, but got {1}")]
+    NetworkMismatch(u32, u32),
+
+    #[error("VirtualNode version conflict, expected {0}, but current is {1}")]
+    VNodeVersionConflict(u64, u64),
+
+    #[error("Operator {0} is not allowed to operate the VirtualNode")]
+    VNodeWriteForbidden(crate::dht::Did),
+
+    #[error("VirtualNode {1} handed off by {0} is not taken over by current node")]
+    VNodeHandoffRefused(crate::dht::Did, crate::dht::Did),
+
+    #[error("Timeout on fetching VirtualNode {0}")]
+    VNodeFetchTimeout(crate::dht::Did),
+
+    #[error("Limit of VirtualNode range should be positive")]
+    VNodeRangeZeroLimit,
+
+    #[error("Timeout on waiting for report of request {0}")]
+    RequestTimeout(uuid::Uuid),
+
     #[error("Encode a byte vector into a base58-check string, adds 4 bytes checksum")]
     Encode,
 
@@ -163,6 +211,12 @@ pub enum Error {
     #[error("call lock() failed")]
     SwarmPendingTransTryLockFailed,
 
+    #[error("lock chunk list failed")]
+    SwarmChunkListLockFailed,
+
+    #[error("lock pending requests failed")]
+    SwarmPendingRequestsLockFailed,
+
     #[error("transport not found")]
     SwarmPendingTransNotFound,
 
@@ -218,6 +272,12 @@ pub enum Error {
     #[error("DataChannel state not open")]
     RTCDataChannelStateNotOpen,
 
+    #[error("DataChannel buffer is not drained in time")]
+    RTCDataChannelBufferFull,
+
+    #[error("Transport send queue is not drained in time")]
+    TransportSendQueueFull,
+
     #[cfg(not(feature = "wasm"))]
     #[error("RTC peer_connection add ice candidate error")]
     RTCPeerConnectionAddIceCandidateError(#[source] webrtc::Error),
@@ -317,9 +377,6 @@ pub enum Error {
     #[error("Message decryption failed")]
     MessageDecryptionFailed(ecies::SecpError),
 
-    #[error("message too large, consider use ChunkList")]
-    MessageTooLarge,
-
     #[cfg(feature = "wasm")]
     #[error("Cannot get property {0} from JsValue")]
     FailedOnGetProperty(String),
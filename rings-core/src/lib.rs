@@ -59,6 +59,7 @@
 //! cargo build -p rings-core --target=wasm32-unknown-unknown --features wasm --no-default-features
 //! ```
 
+pub mod blocklist;
 pub mod channels;
 pub mod dht;
 pub mod ecc;
@@ -79,3 +80,5 @@ pub use futures;
 pub mod chunk;
 pub mod consts;
 pub mod measure;
+pub mod rate_limit;
+pub mod scheduler;
@@ -3,6 +3,7 @@
 #![warn(missing_docs)]
 use async_trait::async_trait;
 
+use crate::consts::REPUTATION_MIN_SAMPLES;
 use crate::dht::Did;
 
 /// The tag of counters in measure.
@@ -16,6 +17,8 @@ pub enum MeasureCounter {
     Received,
     /// The number of failed to receive messages.
     FailedToReceive,
+    /// The number of messages dropped for exceeding rate limit.
+    RateLimited,
 }
 
 /// `Measure` is used to assess the reliability of peers by counting their behaviour.
@@ -28,4 +31,39 @@ pub trait Measure {
     async fn incr(&self, did: Did, counter: MeasureCounter);
     /// `get_count` returns the counter of the given peer.
     async fn get_count(&self, did: Did, counter: MeasureCounter) -> u64;
+    /// `get_score` returns the reputation score of the given peer, see [score].
+    async fn get_score(&self, did: Did) -> f64 {
+        score(
+            self.get_count(did, MeasureCounter::Sent).await,
+            self.get_count(did, MeasureCounter::FailedToSend).await,
+            self.get_count(did, MeasureCounter::Received).await,
+            self.get_count(did, MeasureCounter::FailedToReceive).await,
+        )
+    }
+}
+
+/// Calculate reputation score of a peer from its counters, which is the ratio of
+/// succeeded messages in range [0, 1]. A peer without enough messages is trusted,
+/// since there is no evidence against it yet.
+pub fn score(sent: u64, failed_to_send: u64, received: u64, failed_to_receive: u64) -> f64 {
+    let succeeded = sent + received;
+    let total = succeeded + failed_to_send + failed_to_receive;
+    if total < REPUTATION_MIN_SAMPLES {
+        return 1.0;
+    }
+    succeeded as f64 / total as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_score() {
+        assert_eq!(score(0, 0, 0, 0), 1.0);
+        assert_eq!(score(0, 5, 0, 5), 1.0);
+        assert_eq!(score(10, 0, 10, 0), 1.0);
+        assert_eq!(score(5, 5, 5, 5), 0.5);
+        assert_eq!(score(0, 10, 0, 10), 0.0);
+    }
 }
@@ -0,0 +1,38 @@
+use async_trait::async_trait;
+
+use crate::chunk::Chunk;
+use crate::err::Result;
+use crate::message::types::Message;
+use crate::message::HandleMsg;
+use crate::message::MessageHandler;
+use crate::message::MessagePayload;
+use crate::message::PayloadSender;
+use crate::message::RelayMethod;
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<Chunk> for MessageHandler {
+    async fn handle(&self, ctx: &MessagePayload<Message>, msg: &Chunk) -> Result<()> {
+        match ctx.relay.method {
+            RelayMethod::SEND if self.dht.did != ctx.relay.destination => {
+                return self.forward_to_destination(ctx).await;
+            }
+            RelayMethod::REPORT => {
+                let mut relay = ctx.relay.clone();
+                relay.relay(self.dht.did, None)?;
+                if relay.next_hop.is_some() {
+                    return self.forward_payload(ctx, relay).await;
+                }
+            }
+            _ => {}
+        }
+
+        // Self is destination, handle the payload once all its chunks arrived.
+        if let Some(mut payload) = self.swarm.handle_chunk(msg.clone())? {
+            // The payload takes the route of its chunks.
+            payload.relay = ctx.relay.clone();
+            self.handle_payload(&payload).await;
+        }
+        Ok(())
+    }
+}
@@ -2,8 +2,10 @@ use std::str::FromStr;
 
 use async_trait::async_trait;
 
+use crate::consts::ITERATIVE_LOOKUP_CANDIDATES;
+use crate::consts::ITERATIVE_LOOKUP_MAX_HOPS;
 use crate::dht::Chord;
-use crate::dht::ChordStorage;
+use crate::dht::Did;
 use crate::dht::PeerRingAction;
 use crate::dht::PeerRingRemoteAction;
 use crate::err::Error;
@@ -13,9 +15,11 @@ use crate::message::types::ConnectNodeReport;
 use crate::message::types::ConnectNodeSend;
 use crate::message::types::FindSuccessorReport;
 use crate::message::types::FindSuccessorSend;
+use crate::message::types::FindSuccessorStepReport;
+use crate::message::types::FindSuccessorStepSend;
 use crate::message::types::JoinDHT;
 use crate::message::types::Message;
-use crate::message::types::SyncVNodeWithSuccessor;
+use crate::message::Encoded;
 use crate::message::FindSuccessorReportHandler;
 use crate::message::FindSuccessorThen;
 use crate::message::HandleMsg;
@@ -24,14 +28,47 @@ use crate::message::MessageHandler;
 use crate::message::MessagePayload;
 use crate::message::PayloadSender;
 use crate::prelude::RTCSdpType;
+use crate::swarm::Swarm;
 use crate::transports::manager::TransportManager;
 use crate::types::ice_transport::IceTrickleScheme;
 
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
 #[cfg_attr(not(feature = "wasm"), async_trait)]
 impl HandleMsg<LeaveDHT> for MessageHandler {
-    async fn handle(&self, _ctx: &MessagePayload<Message>, msg: &LeaveDHT) -> Result<()> {
-        self.swarm.disconnect(msg.did).await
+    async fn handle(&self, ctx: &MessagePayload<Message>, msg: &LeaveDHT) -> Result<()> {
+        // A node is leaving gracefully, only itself can announce that.
+        // The leaving node will close transports by itself after handing off its data.
+        let origin = ctx.origin_verification.session.authorizer_did();
+        if origin != msg.did || origin == self.dht.did {
+            return Err(Error::InvalidMessage(format!(
+                "{:?} cannot announce leaving of {:?}",
+                origin, msg.did
+            )));
+        }
+        self.dht.remove(msg.did)?;
+
+        // Splice the predecessor and successor of the leaving node to each other.
+        // Both of them are announced by the leaving node, so they are checked for admission.
+        if msg.predecessor == Some(self.dht.did) {
+            if let Some(successor) = msg
+                .successor
+                .filter(|did| *did != self.dht.did && self.dht.admits(*did))
+            {
+                if self
+                    .swarm
+                    .get_and_check_transport(successor)
+                    .await
+                    .is_some()
+                {
+                    self.dht.lock_successor()?.update(successor);
+                } else {
+                    self.swarm.connect(successor).await?;
+                }
+            }
+        } else if let Some(predecessor) = msg.predecessor.filter(|did| *did != self.dht.did) {
+            self.dht.notify(predecessor)?;
+        }
+        Ok(())
     }
 }
 
@@ -39,6 +76,22 @@ impl HandleMsg<LeaveDHT> for MessageHandler {
 #[cfg_attr(not(feature = "wasm"), async_trait)]
 impl HandleMsg<JoinDHT> for MessageHandler {
     async fn handle(&self, ctx: &MessagePayload<Message>, msg: &JoinDHT) -> Result<()> {
+        // JoinDHT is only sent by current node itself once a transport is registered,
+        // whose remote did is verified in handshake.
+        let origin = ctx.origin_verification.session.authorizer_did();
+        if origin != self.dht.did {
+            return Err(Error::InvalidMessage(format!(
+                "{:?} cannot announce joining of {:?}",
+                origin, msg.did
+            )));
+        }
+
+        // A node not admitted by the network should not be joined, neither connected.
+        if !self.dht.admits(msg.did) {
+            self.swarm.disconnect(msg.did).await?;
+            return Err(Error::DidNotAdmitted(msg.did));
+        }
+
         // here is two situation.
         // finger table just have no other node(beside next), it will be a `create` op
         // otherwise, it will be a `send` op
@@ -48,6 +101,9 @@ impl HandleMsg<JoinDHT> for MessageHandler {
                 next,
                 PeerRingRemoteAction::FindSuccessorForConnect(did),
             ) => {
+                // sync data which is no longer between current node and the new node,
+                // including messages parked for the new node while it was offline.
+                self.sync_vnode_with_successor(next).await?;
                 // if there is only two nodes A, B, it may cause recursion
                 // A.successor == B
                 // B.successor == A
@@ -100,10 +156,10 @@ impl HandleMsg<ConnectNodeSend> for MessageHandler {
             relay.relay(self.dht.did, None)?;
             match self.swarm.get_and_check_transport(relay.sender()).await {
                 None => {
+                    let handshake_info: Encoded = msg.handshake_info.to_owned().into();
+                    self.swarm.check_handshake_info(&handshake_info)?;
                     let trans = self.swarm.new_transport().await?;
-                    trans
-                        .register_remote_info(msg.handshake_info.to_owned().into())
-                        .await?;
+                    trans.register_remote_info(handshake_info).await?;
                     let handshake_info = trans
                         .get_handshake_info(self.swarm.session_manager(), RTCSdpType::Answer)
                         .await?
@@ -151,9 +207,9 @@ impl HandleMsg<ConnectNodeReport> for MessageHandler {
                         .map_err(|_| Error::InvalidTransportUuid)?,
                 )?
                 .ok_or(Error::MessageHandlerMissTransportConnectedNode)?;
-            transport
-                .register_remote_info(msg.handshake_info.clone().into())
-                .await?;
+            let handshake_info: Encoded = msg.handshake_info.clone().into();
+            self.swarm.check_handshake_info(&handshake_info)?;
+            transport.register_remote_info(handshake_info).await?;
             Ok(())
         }
     }
@@ -229,6 +285,11 @@ impl HandleMsg<FindSuccessorReport> for MessageHandler {
             return self.forward_payload(ctx, relay).await;
         }
 
+        // The reported successor is recorded below, a node not admitted should be skipped.
+        if !self.dht.admits(msg.did) {
+            return Err(Error::DidNotAdmitted(msg.did));
+        }
+
         match &msg.handler {
             // TODO: how to prevent `fix_finger_index` before got `FixFingerTable`?
             FindSuccessorReportHandler::FixFingerTable => self.dht.lock_finger()?.set_fix(msg.did),
@@ -241,18 +302,7 @@ impl HandleMsg<FindSuccessorReport> for MessageHandler {
             }
             FindSuccessorReportHandler::SyncStorage => {
                 self.dht.lock_successor()?.update(msg.did);
-                if let Ok(PeerRingAction::RemoteAction(
-                    next,
-                    PeerRingRemoteAction::SyncVNodeWithSuccessor(data),
-                )) = self.dht.sync_vnode_with_successor(msg.did).await
-                {
-                    self.send_message(
-                        Message::SyncVNodeWithSuccessor(SyncVNodeWithSuccessor { data }),
-                        next,
-                    )
-                    .await?;
-                    return Ok(());
-                }
+                self.sync_vnode_with_successor(msg.did).await?;
             }
             _ => {}
         }
@@ -261,21 +311,167 @@ impl HandleMsg<FindSuccessorReport> for MessageHandler {
     }
 }
 
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<FindSuccessorStepSend> for MessageHandler {
+    /// Report the successor if it's known, otherwise report the closest preceding nodes,
+    /// so that origin can ask them next.
+    async fn handle(
+        &self,
+        ctx: &MessagePayload<Message>,
+        msg: &FindSuccessorStepSend,
+    ) -> Result<()> {
+        if self.dht.did != ctx.relay.destination {
+            return self.forward_to_destination(ctx).await;
+        }
+
+        let (successor, candidates) = match self.dht.find_successor(msg.did)? {
+            PeerRingAction::Some(did) => (Some(did), vec![]),
+            PeerRingAction::RemoteAction(..) => (
+                None,
+                self.dht
+                    .lock_finger()?
+                    .closest_list(msg.did, ITERATIVE_LOOKUP_CANDIDATES),
+            ),
+            act => return Err(Error::PeerRingUnexpectedAction(act)),
+        };
+
+        let mut relay = ctx.relay.clone();
+        relay.relay(self.dht.did, None)?;
+        self.send_report_message(
+            Message::FindSuccessorStepReport(FindSuccessorStepReport {
+                did: msg.did,
+                successor,
+                candidates,
+            }),
+            ctx.tx_id,
+            relay,
+        )
+        .await
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<FindSuccessorStepReport> for MessageHandler {
+    async fn handle(
+        &self,
+        ctx: &MessagePayload<Message>,
+        _: &FindSuccessorStepReport,
+    ) -> Result<()> {
+        let mut relay = ctx.relay.clone();
+
+        relay.relay(self.dht.did, None)?;
+        if relay.next_hop.is_some() {
+            self.forward_payload(ctx, relay).await
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Swarm {
+    /// Find successor of `did` iteratively. Instead of forwarding the query hop by hop,
+    /// current node asks each hop by itself and waits at most `hop_timeout_ms` for it.
+    /// A stalled hop is logged and skipped, then the next closest node is asked instead.
+    pub async fn find_successor_iterative(&self, did: Did, hop_timeout_ms: u64) -> Result<Did> {
+        self.lookup_iterative(did, hop_timeout_ms)
+            .await
+            .map(|(_, successor)| successor)
+    }
+
+    /// Find the node storing `did` iteratively, it's the hop that knows the successor.
+    pub async fn find_storage_iterative(&self, did: Did, hop_timeout_ms: u64) -> Result<Did> {
+        self.lookup_iterative(did, hop_timeout_ms)
+            .await
+            .map(|(hop, _)| hop)
+    }
+
+    /// Returns the last hop of lookup along with the successor of `did` it knows.
+    /// A hop not connected yet is asked through the ring, and a connection to it is
+    /// started for later lookups.
+    /// Only candidates strictly closer to `did` than the reporting hop are followed.
+    async fn lookup_iterative(&self, did: Did, hop_timeout_ms: u64) -> Result<(Did, Did)> {
+        let mut candidates = match self.dht.find_successor(did)? {
+            PeerRingAction::Some(successor) => return Ok((self.dht.did, successor)),
+            PeerRingAction::RemoteAction(..) => self
+                .dht
+                .lock_finger()?
+                .closest_list(did, ITERATIVE_LOOKUP_CANDIDATES),
+            act => return Err(Error::PeerRingUnexpectedAction(act)),
+        };
+        let mut asked = vec![];
+        let mut stalled = vec![];
+
+        while !candidates.is_empty() && asked.len() < ITERATIVE_LOOKUP_MAX_HOPS {
+            let hop = candidates.remove(0);
+            if hop == self.dht.did || asked.contains(&hop) {
+                continue;
+            }
+            asked.push(hop);
+
+            let msg = Message::FindSuccessorStepSend(FindSuccessorStepSend { did });
+            let result = if self.get_transport(hop).is_some() {
+                self.send_direct_message_and_wait(msg, hop, hop_timeout_ms)
+                    .await
+            } else {
+                tracing::debug!("iterative lookup of {:?} asks unconnected {:?}", did, hop);
+                let result = self.send_message_and_wait(msg, hop, hop_timeout_ms).await;
+                if let Err(e) = self.connect(hop).await {
+                    tracing::warn!("failed to connect {:?} for iterative lookup: {}", hop, e);
+                }
+                result
+            };
+
+            match result.map(|report| report.data) {
+                Ok(Message::FindSuccessorStepReport(x)) => {
+                    if let Some(successor) = x.successor {
+                        return Ok((hop, successor));
+                    }
+                    // Ask the closer nodes first, keep the others as fallback.
+                    candidates = x
+                        .candidates
+                        .into_iter()
+                        .filter(|c| did - *c < did - hop)
+                        .chain(candidates)
+                        .collect();
+                }
+                Ok(x) => {
+                    tracing::warn!("unexpected report of FindSuccessorStepSend: {:?}", x);
+                    stalled.push(hop);
+                }
+                Err(e) => {
+                    tracing::warn!("iterative lookup of {:?} stalled at {:?}: {}", did, hop, e);
+                    stalled.push(hop);
+                }
+            }
+        }
+
+        Err(Error::IterativeLookupFailed(did, stalled))
+    }
+}
+
 #[cfg(not(feature = "wasm"))]
 #[cfg(test)]
 pub mod tests {
     use std::matches;
     use std::sync::Arc;
 
+    use num_bigint::BigUint;
     use tokio::time::sleep;
     use tokio::time::Duration;
 
     use super::*;
+    use crate::dht::subring::Subring;
+    use crate::dht::vnode::VirtualNode;
     use crate::dht::Did;
     use crate::ecc::tests::gen_ordered_keys;
     use crate::ecc::SecretKey;
     use crate::message::handlers::tests::assert_no_more_msg;
+    use crate::message::handlers::tests::wait_for_msgs;
     use crate::message::MessageHandler;
+    use crate::storage::PersistenceStorageOperation;
+    use crate::storage::PersistenceStorageReadAndWrite;
     use crate::swarm::Swarm;
     use crate::tests::default::prepare_node;
     use crate::tests::manually_establish_connection;
@@ -964,9 +1160,9 @@ pub mod tests {
                 break;
             }
         }
-        let ev2 = node2.listen_once().await.unwrap();
-        assert_eq!(ev2.addr, did2);
-        assert!(matches!(ev2.data, Message::LeaveDHT(LeaveDHT{did}) if did == did1));
+        // The closed transport is handled by swarm itself, so that we get no msg either.
+        let ev2 = node2.listen_once().await;
+        assert!(ev2.is_none());
 
         assert_no_more_msg(&node1, &node2, &node3).await;
 
@@ -1036,4 +1232,78 @@ pub mod tests {
         assert!(t3_1.is_connected().await);
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_find_successor_iterative() -> Result<()> {
+        let keys = gen_ordered_keys(2);
+        let (key1, key2) = (keys[0], keys[1]);
+        let (did1, _dht1, swarm1, node1, _path1) = prepare_node(key1).await;
+        let (did2, _dht2, _swarm2, node2, _path2) = prepare_node(key2).await;
+        test_only_two_nodes_establish_connection(&node1, &node2).await?;
+
+        // The successor of target is node1, but only node2 knows it.
+        let target = did2 + Did::from(BigUint::from(1u8));
+        let (result, _) = tokio::join!(swarm1.find_successor_iterative(target, 3000), async {
+            let ev = node2.listen_once().await.unwrap();
+            assert!(matches!(ev.data, Message::FindSuccessorStepSend(x) if x.did == target));
+            let ev = node1.listen_once().await.unwrap();
+            assert!(matches!(
+                ev.data,
+                Message::FindSuccessorStepReport(x) if x.successor == Some(did1)
+            ));
+        });
+        assert_eq!(result?, did1);
+
+        // The stalled hops are reported if no one answers.
+        let result = swarm1.find_successor_iterative(target, 100).await;
+        assert!(matches!(
+            result,
+            Err(Error::IterativeLookupFailed(x, hops)) if x == target && hops == vec![did2]
+        ));
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_leave_and_splice() -> Result<()> {
+        let keys = gen_ordered_keys(3);
+        let (node1, node2, node3) =
+            test_triple_ordered_nodes_connection(keys[0], keys[1], keys[2]).await?;
+        let (did1, did2, did3) = (node1.swarm.did(), node2.swarm.did(), node3.swarm.did());
+        let (dht1, dht2, dht3) = (node1.swarm.dht(), node2.swarm.dht(), node3.swarm.dht());
+        *dht2.lock_predecessor()? = Some(did1);
+        *dht3.lock_predecessor()? = Some(did2);
+
+        // Both Data and Subring type vnodes stored on node2 should be handed off.
+        // They are in (did2, did3], so that the predecessor is taking over them.
+        let data: VirtualNode = "Across the Great Wall".to_string().try_into()?;
+        let data = VirtualNode {
+            did: did2 + Did::from(BigUint::from(1u8)),
+            ..data
+        };
+        let subring: VirtualNode = Subring::new("group", did2)?.try_into()?;
+        let subring = VirtualNode {
+            did: did2 + Did::from(BigUint::from(2u8)),
+            ..subring
+        };
+        for vnode in [&data, &subring] {
+            dht2.storage.put(&vnode.did, vnode).await?;
+        }
+
+        let (result, _) = tokio::join!(node2.swarm.leave(), wait_for_msgs(&node1, &node2, &node3));
+        result?;
+
+        // The predecessor takes over all vnodes of node2.
+        assert_eq!(dht2.storage.count().await?, 0);
+        assert_eq!(dht1.storage.get(&data.did).await?, Some(data));
+        assert_eq!(dht1.storage.get(&subring.did).await?, Some(subring));
+
+        // The predecessor and successor of node2 are spliced to each other.
+        assert_eq!(dht1.lock_successor()?.list(), vec![did3]);
+        assert_eq!(*dht3.lock_predecessor()?, Some(did1));
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
 }
@@ -7,6 +7,7 @@ use crate::err::Result;
 use crate::message::types::CustomMessage;
 use crate::message::types::MaybeEncrypted;
 use crate::message::types::Message;
+use crate::message::ChordStorageInterface;
 use crate::message::HandleMsg;
 use crate::message::MessageHandler;
 use crate::message::MessagePayload;
@@ -29,6 +30,17 @@ impl HandleMsg<MaybeEncrypted<CustomMessage>> for MessageHandler {
                 return self.forward_payload(ctx, relay).await;
             } else {
                 let next_node = match self.dht.find_successor(relay.destination)? {
+                    // The destination should be the successor of current node but it's not
+                    // connected, which means it's offline. Park the message on DHT, it will be
+                    // synced to the destination once the destination joins the ring again.
+                    PeerRingAction::Some(node) if node != relay.destination => {
+                        tracing::info!(
+                            "destination {:?} is unreachable, park message {}",
+                            relay.destination,
+                            ctx.tx_id
+                        );
+                        return self.swarm.storage_store(ctx.clone().try_into()?).await;
+                    }
                     PeerRingAction::Some(node) => Some(node),
                     PeerRingAction::RemoteAction(node, _) => Some(node),
                     _ => None,
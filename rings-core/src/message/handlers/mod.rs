@@ -11,6 +11,7 @@ use std::sync::Arc;
 
 use async_recursion::async_recursion;
 use async_trait::async_trait;
+use futures::channel::oneshot;
 
 use super::CustomMessage;
 use super::MaybeEncrypted;
@@ -18,18 +19,30 @@ use super::Message;
 use super::MessagePayload;
 use super::OriginVerificationGen;
 use super::PayloadSender;
+use super::SyncVNodeWithSuccessor;
+use crate::dht::vnode::VirtualNode;
+use crate::dht::Chord;
+use crate::dht::ChordStorage;
 use crate::dht::Did;
 use crate::dht::PeerRing;
+use crate::dht::PeerRingAction;
+use crate::dht::PeerRingRemoteAction;
 use crate::err::Error;
 use crate::err::Result;
 use crate::measure::MeasureCounter;
 use crate::session::SessionManager;
+use crate::storage::PersistenceStorageReadAndWrite;
 use crate::swarm::Swarm;
+use crate::transports::manager::TransportManager;
 
+/// Handler for Chunk of large payload
+pub mod chunk;
 /// Operator and Handler for Connection
 pub mod connection;
 /// Operator and Handler for CustomMessage
 pub mod custom;
+/// Operator and Handler for topic PubSub
+pub mod pubsub;
 /// Operator and handler for DHT stablization
 pub mod stabilization;
 /// Operator and Handler for Storage
@@ -126,6 +139,20 @@ impl MessageHandler {
                         cb.custom_message(self, payload, msg).await
                     }
                 }
+                Message::SubringBroadcast(ref msg) => {
+                    if self.dht.did == payload.relay.destination {
+                        tracing::debug!("INVOKE SUBRING MESSAGE CALLBACK {}", &payload.tx_id);
+                        cb.custom_message(self, payload, &msg.data).await
+                    }
+                }
+                // Chunks are transparent to callback, the reassembled payload will be passed.
+                Message::Chunk(_) => {}
+                Message::TopicMessages(_) => {
+                    if self.dht.did == payload.relay.destination {
+                        tracing::debug!("INVOKE TOPIC MESSAGE CALLBACK {}", &payload.tx_id);
+                        cb.builtin_message(self, payload).await
+                    }
+                }
                 _ => cb.builtin_message(self, payload).await,
             };
         } else if let Message::CustomMessage(ref msg) = payload.data {
@@ -154,6 +181,65 @@ impl MessageHandler {
         Ok(decrypt_msg)
     }
 
+    /// Forward payload to the next hop toward its destination, which is either
+    /// connected directly or found by finger table.
+    async fn forward_to_destination(&self, ctx: &MessagePayload<Message>) -> Result<()> {
+        let mut relay = ctx.relay.clone();
+        let next_node = if self.swarm.get_transport(relay.destination).is_some() {
+            relay.destination
+        } else {
+            match self.dht.find_successor(relay.destination)? {
+                PeerRingAction::Some(node) => node,
+                PeerRingAction::RemoteAction(node, _) => node,
+                _ => return Err(Error::MessageHandlerMissNextNode),
+            }
+        };
+        relay.relay(self.dht.did, Some(next_node))?;
+        self.forward_payload(ctx, relay).await
+    }
+
+    /// Hand off data which is no longer between current node and `new_successor` to it.
+    /// The data is removed from local storage before sending, so it's put back if the
+    /// sending failed, otherwise it would be lost.
+    async fn sync_vnode_with_successor(&self, new_successor: Did) -> Result<()> {
+        if let PeerRingAction::RemoteAction(
+            target,
+            PeerRingRemoteAction::SyncVNodeWithSuccessor(data),
+        ) = self.dht.sync_vnode_with_successor(new_successor).await?
+        {
+            let msg =
+                Message::SyncVNodeWithSuccessor(SyncVNodeWithSuccessor { data: data.clone() });
+            if let Err(e) = self.send_message(msg, target).await {
+                for vnode in data.iter() {
+                    self.dht.storage.put(&vnode.did, vnode).await?;
+                }
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Replay messages parked in a `RelayMessage` type [VirtualNode] while current
+    /// node was offline. Only custom messages signed by their origin are accepted.
+    /// Since the payloads may be parked for a long time, the ttl is not checked here.
+    async fn replay_relay_messages(&self, vnode: &VirtualNode) -> Result<()> {
+        for encoded in vnode.data.iter() {
+            let payload: MessagePayload<Message> = encoded.decode()?;
+            if !matches!(payload.data, Message::CustomMessage(_))
+                || payload.relay.destination != self.dht.did
+                || !payload.origin_verification.verify(&payload.data)
+            {
+                tracing::warn!("drop invalid relay message {}", &payload.tx_id);
+                continue;
+            }
+            tracing::debug!("REPLAY RELAY MESSAGE {}", &payload.tx_id);
+            if let Err(e) = self.handle_message(&payload).await {
+                tracing::warn!("replay relay message {} error: {}", &payload.tx_id, e);
+            }
+        }
+        Ok(())
+    }
+
     /// Handle builtin message.
     #[cfg_attr(feature = "wasm", async_recursion(?Send))]
     #[cfg_attr(not(feature = "wasm"), async_recursion)]
@@ -165,6 +251,7 @@ impl MessageHandler {
         tracing::debug!("START HANDLE MESSAGE: {} {}", &payload.tx_id, &payload.data);
 
         self.validate(payload).await?;
+        self.swarm.resolve_pending_request(payload);
 
         match &payload.data {
             Message::JoinDHT(ref msg) => self.handle(payload, msg).await,
@@ -174,12 +261,34 @@ impl MessageHandler {
             Message::AlreadyConnected(ref msg) => self.handle(payload, msg).await,
             Message::FindSuccessorSend(ref msg) => self.handle(payload, msg).await,
             Message::FindSuccessorReport(ref msg) => self.handle(payload, msg).await,
+            Message::FindSuccessorStepSend(ref msg) => self.handle(payload, msg).await,
+            Message::FindSuccessorStepReport(ref msg) => self.handle(payload, msg).await,
             Message::NotifyPredecessorSend(ref msg) => self.handle(payload, msg).await,
             Message::NotifyPredecessorReport(ref msg) => self.handle(payload, msg).await,
+            Message::CheckPredecessorSend(ref msg) => self.handle(payload, msg).await,
+            Message::CheckPredecessorReport(ref msg) => self.handle(payload, msg).await,
+            Message::PingSend(ref msg) => self.handle(payload, msg).await,
+            Message::PingReport(ref msg) => self.handle(payload, msg).await,
             Message::SearchVNode(ref msg) => self.handle(payload, msg).await,
             Message::FoundVNode(ref msg) => self.handle(payload, msg).await,
+            Message::SearchVNodeRange(ref msg) => self.handle(payload, msg).await,
+            Message::FoundVNodeRange(ref msg) => self.handle(payload, msg).await,
             Message::OperateVNode(ref msg) => self.handle(payload, msg).await,
+            Message::SyncVNodeWithSuccessor(ref msg) => self.handle(payload, msg).await,
+            Message::SyncVNodeWithSuccessorReport(ref msg) => self.handle(payload, msg).await,
+            Message::ReplicateVNode(ref msg) => self.handle(payload, msg).await,
+            Message::VNodeVersionConflict(ref msg) => self.handle(payload, msg).await,
+            Message::FindSubringSuccessorSend(ref msg) => self.handle(payload, msg).await,
+            Message::FindSubringSuccessorReport(ref msg) => self.handle(payload, msg).await,
+            Message::NotifySubringPredecessorSend(ref msg) => self.handle(payload, msg).await,
+            Message::NotifySubringPredecessorReport(ref msg) => self.handle(payload, msg).await,
+            Message::LeaveSubring(ref msg) => self.handle(payload, msg).await,
+            Message::SubringBroadcast(ref msg) => self.handle(payload, msg).await,
+            Message::SubscribeTopic(ref msg) => self.handle(payload, msg).await,
+            Message::UnsubscribeTopic(ref msg) => self.handle(payload, msg).await,
+            Message::TopicMessages(ref msg) => self.handle(payload, msg).await,
             Message::CustomMessage(ref msg) => self.handle(payload, msg).await,
+            Message::Chunk(ref msg) => self.handle(payload, msg).await,
             Message::MultiCall(ref msg) => {
                 for message in msg.messages.iter().cloned() {
                     let payload = MessagePayload::new(
@@ -215,6 +324,29 @@ impl MessageHandler {
             return None;
         }
 
+        if let Err(e) = payload.check_network_id(self.swarm.network_id()) {
+            tracing::warn!("Drop msg from other network: {:?}, {}", payload.tx_id, e);
+            return None;
+        }
+
+        let origin = payload.origin_verification.session.authorizer_did();
+        if self.swarm.is_blocked(payload.addr) || self.swarm.is_blocked(origin) {
+            tracing::warn!("Drop msg from blocked peer: {:?}", payload.tx_id);
+            return None;
+        }
+
+        if origin != self.swarm.did() && !self.swarm.rate_limiter.check(origin, &payload.data) {
+            tracing::warn!(
+                "Drop msg exceeding rate limit from {:?}: {:?}",
+                origin,
+                payload.tx_id
+            );
+            if let Some(measure) = &self.swarm.measure {
+                measure.incr(origin, MeasureCounter::RateLimited).await
+            }
+            return None;
+        }
+
         if let Err(e) = self.handle_message(payload).await {
             tracing::error!("Error in handle_message: {}", e);
 
@@ -259,6 +391,17 @@ impl PayloadSender<Message> for MessageHandler {
         self.swarm.dht()
     }
 
+    fn register_request(
+        &self,
+        tx_id: uuid::Uuid,
+    ) -> Result<oneshot::Receiver<MessagePayload<Message>>> {
+        self.swarm.register_request(tx_id)
+    }
+
+    fn cancel_request(&self, tx_id: uuid::Uuid) {
+        self.swarm.cancel_request(tx_id)
+    }
+
     async fn do_send_payload(&self, did: Did, payload: MessagePayload<Message>) -> Result<()> {
         self.swarm.do_send_payload(did, payload).await
     }
@@ -321,8 +464,10 @@ pub mod tests {
     use tokio::time::Duration;
 
     use super::*;
+    use crate::consts::PAYLOAD_CHUNK_MTU;
     use crate::dht::Did;
     use crate::ecc::SecretKey;
+    use crate::message::handlers::connection::tests::test_only_two_nodes_establish_connection;
     use crate::message::MessageHandler;
     use crate::tests::default::prepare_node;
     use crate::tests::manually_establish_connection;
@@ -442,6 +587,45 @@ pub mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_park_and_replay_custom_message() -> Result<()> {
+        let key1 = SecretKey::random();
+        let key2 = SecretKey::random();
+
+        let (did1, dht1, swarm1, _node1, _path1) = prepare_node(key1).await;
+        let (did2, _dht2, swarm2, _node2, _path2) = prepare_node(key2).await;
+        let relay_vid = VirtualNode::gen_relay_message_did(did2);
+
+        // node2 is offline, so the message is parked on node1.
+        let msg = MaybeEncrypted::new(CustomMessage("Hello offline 2".as_bytes().to_vec()), None)?;
+        swarm1.send_custom_message(msg, did2).await?;
+        let parked = dht1.storage.get(&relay_vid).await?.unwrap();
+        assert_eq!(parked.data.len(), 1);
+
+        let msg_callback2 = MessageCallbackInstance {
+            handler_messages: Arc::new(Mutex::new(vec![])),
+        };
+        let cb2: CallbackFn = Box::new(msg_callback2.clone());
+        let handler1 = Arc::new(swarm1.create_message_handler(None, None));
+        let handler2 = Arc::new(swarm2.create_message_handler(Some(cb2), None));
+
+        // node2 joins the ring, the parked message is synced to it and replayed.
+        manually_establish_connection(&swarm1, &swarm2).await?;
+        let h1 = handler1.clone();
+        let h2 = handler2.clone();
+        tokio::spawn(async { h1.listen().await });
+        tokio::spawn(async { h2.listen().await });
+        sleep(Duration::from_secs(5)).await;
+
+        let received = msg_callback2.handler_messages.lock().await.clone();
+        let expected = vec![(did1, "Hello offline 2".as_bytes().to_vec())];
+        assert_eq!(received, expected);
+        assert!(dht1.storage.get(&relay_vid).await?.is_none());
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
     pub async fn assert_no_more_msg(
         node1: &MessageHandler,
         node2: &MessageHandler,
@@ -469,4 +653,121 @@ pub mod tests {
             }
         }
     }
+
+    #[tokio::test]
+    async fn test_blocked_peer() -> Result<()> {
+        let key1 = SecretKey::random();
+        let key2 = SecretKey::random();
+
+        let (did1, _dht1, swarm1, node1, _path1) = prepare_node(key1).await;
+        let (did2, _dht2, _swarm2, node2, _path2) = prepare_node(key2).await;
+        test_only_two_nodes_establish_connection(&node1, &node2).await?;
+
+        // Messages from a blocked peer are dropped.
+        swarm1.blocklist.block(did2).await?;
+        node2
+            .send_message(Message::custom("spam".as_bytes(), None)?, did1)
+            .await?;
+        assert!(node1.listen_once().await.is_none());
+
+        // Blocking a peer closes its transport, and it cannot be connected again.
+        swarm1.block_peer(did2).await?;
+        assert!(swarm1.get_transport(did2).is_none());
+        assert!(matches!(
+            swarm1.connect(did2).await,
+            Err(Error::PeerBlocked(did)) if did == did2
+        ));
+        assert_eq!(swarm1.blocked_peers(), vec![did2]);
+
+        swarm1.unblock_peer(did2).await?;
+        assert!(!swarm1.is_blocked(did2));
+        assert!(swarm1.blocked_peers().is_empty());
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_chunked_payload() -> Result<()> {
+        let key1 = SecretKey::random();
+        let key2 = SecretKey::random();
+
+        let (did1, _dht1, _swarm1, node1, _path1) = prepare_node(key1).await;
+        let (did2, _dht2, swarm2, node2, _path2) = prepare_node(key2).await;
+        test_only_two_nodes_establish_connection(&node1, &node2).await?;
+
+        let msg_callback = MessageCallbackInstance {
+            handler_messages: Arc::new(Mutex::new(vec![])),
+        };
+        let cb: CallbackFn = Box::new(msg_callback.clone());
+        let node2 = swarm2.create_message_handler(Some(cb), None);
+
+        // A payload larger than PAYLOAD_CHUNK_MTU is sent as 3 chunks.
+        let data: Vec<u8> = (0..PAYLOAD_CHUNK_MTU * 2)
+            .map(|_| rand::random::<u8>())
+            .collect();
+        node1
+            .send_message(Message::custom(&data, None)?, did2)
+            .await?;
+
+        for _ in 0..3 {
+            let ev = node2.listen_once().await.unwrap();
+            assert!(matches!(ev.data, Message::Chunk(_)));
+        }
+        assert_eq!(msg_callback.handler_messages.lock().await.as_slice(), &[
+            (did1, data)
+        ]);
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_relayed_chunked_payload() -> Result<()> {
+        let key1 = SecretKey::random();
+        let key2 = SecretKey::random();
+        let key3 = SecretKey::random();
+
+        let (did1, _dht1, swarm1, node1, _path1) = prepare_node(key1).await;
+        let (did2, _dht2, swarm2, node2, _path2) = prepare_node(key2).await;
+        let (did3, _dht3, swarm3, _node3, _path3) = prepare_node(key3).await;
+        test_only_two_nodes_establish_connection(&node1, &node2).await?;
+        manually_establish_connection(&swarm3, &swarm2).await?;
+
+        let msg_callback = MessageCallbackInstance {
+            handler_messages: Arc::new(Mutex::new(vec![])),
+        };
+        let cb: CallbackFn = Box::new(msg_callback.clone());
+        let node3 = swarm3.create_message_handler(Some(cb), None);
+        let node2 = Arc::new(node2);
+        tokio::spawn(async move { node2.listen().await });
+
+        // node1 sends the payload to node3 via node2, which forwards chunks as they are.
+        let data: Vec<u8> = (0..PAYLOAD_CHUNK_MTU * 2)
+            .map(|_| rand::random::<u8>())
+            .collect();
+        let payload = MessagePayload::new_send(
+            Message::custom(&data, None)?,
+            swarm1.session_manager(),
+            did2,
+            did3,
+        )?;
+        swarm1.send_payload(payload).await?;
+
+        let mut chunks = 0;
+        while msg_callback.handler_messages.lock().await.is_empty() {
+            if let Some(ev) = node3.listen_once().await {
+                if matches!(ev.data, Message::Chunk(_)) {
+                    assert_eq!(ev.addr, did2);
+                    chunks += 1;
+                }
+            }
+        }
+        assert_eq!(chunks, 3);
+        let received = msg_callback.handler_messages.lock().await.clone();
+        assert_eq!(received, vec![(did1, data)]);
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
 }
@@ -0,0 +1,240 @@
+#![warn(missing_docs)]
+use async_trait::async_trait;
+
+use crate::consts::TOPIC_SUBSCRIPTION_TTL_MS;
+use crate::dht::vnode::VNodeOperation;
+use crate::dht::vnode::VNodeType;
+use crate::dht::vnode::VirtualNode;
+use crate::dht::Chord;
+use crate::dht::Did;
+use crate::dht::PeerRingAction;
+use crate::err::Error;
+use crate::err::Result;
+use crate::message::types::Message;
+use crate::message::types::SubscribeTopic;
+use crate::message::types::TopicMessages;
+use crate::message::types::UnsubscribeTopic;
+use crate::message::HandleMsg;
+use crate::message::MessageHandler;
+use crate::message::MessagePayload;
+use crate::message::PayloadSender;
+use crate::swarm::Swarm;
+
+/// PubSubInterface should imply necessary method for topic subscription.
+/// Data appended to a subscribed topic will be pushed to subscriber as [TopicMessages].
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+pub trait PubSubInterface {
+    /// subscribe a topic
+    async fn topic_subscribe(&self, topic: &str) -> Result<()>;
+    /// unsubscribe a topic
+    async fn topic_unsubscribe(&self, topic: &str) -> Result<()>;
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl PubSubInterface for Swarm {
+    /// register current node as a subscriber on the node storing topic.
+    /// The subscription is renewed during stabilization.
+    async fn topic_subscribe(&self, topic: &str) -> Result<()> {
+        let vid = VirtualNode::gen_did(topic)?;
+        self.dht.topic_subscriptions.set(&vid, topic.to_string());
+        self.renew_topic_subscription(vid).await
+    }
+
+    async fn topic_unsubscribe(&self, topic: &str) -> Result<()> {
+        let vid = VirtualNode::gen_did(topic)?;
+        self.dht.topic_subscriptions.remove(&vid);
+        match self.dht.find_successor(vid)? {
+            PeerRingAction::Some(_) => {
+                self.dht.remove_topic_subscriber(vid, self.dht.did);
+                Ok(())
+            }
+            PeerRingAction::RemoteAction(next, _) => {
+                let msg = UnsubscribeTopic { vid };
+                self.send_message(Message::UnsubscribeTopic(msg), next)
+                    .await?;
+                Ok(())
+            }
+            act => Err(Error::PeerRingUnexpectedAction(act)),
+        }
+    }
+}
+
+impl Swarm {
+    /// Send subscription of topic `vid` to the node storing it.
+    pub(crate) async fn renew_topic_subscription(&self, vid: Did) -> Result<()> {
+        match self.dht.find_successor(vid)? {
+            PeerRingAction::Some(_) => {
+                self.dht
+                    .add_topic_subscriber(vid, self.dht.did, TOPIC_SUBSCRIPTION_TTL_MS);
+                Ok(())
+            }
+            PeerRingAction::RemoteAction(next, _) => {
+                let msg = SubscribeTopic { vid };
+                self.send_message(Message::SubscribeTopic(msg), next)
+                    .await?;
+                Ok(())
+            }
+            act => Err(Error::PeerRingUnexpectedAction(act)),
+        }
+    }
+
+    /// Push data of an applied [VNodeOperation::Extend] to subscribers of the topic.
+    /// Pushing is best effort, failures are only logged.
+    pub(crate) async fn push_topic_messages(&self, op: &VNodeOperation) {
+        let (vid, data) = match op {
+            VNodeOperation::Extend(VirtualNode {
+                did,
+                data,
+                kind: VNodeType::Data,
+                ..
+            }) => (*did, data),
+            _ => return,
+        };
+
+        for subscriber in self.dht.topic_subscribers_of(vid) {
+            let msg = Message::TopicMessages(TopicMessages {
+                vid,
+                data: data.clone(),
+            });
+            let result = if subscriber == self.dht.did {
+                self.send_loopback_message(msg).await
+            } else {
+                self.send_message(msg, subscriber).await.map(|_| ())
+            };
+            if let Err(e) = result {
+                tracing::warn!("failed to push topic {:?} to {:?}: {}", vid, subscriber, e);
+            }
+        }
+    }
+}
+
+impl MessageHandler {
+    /// Forward payload to the node storing topic `vid`.
+    /// Returns false if the topic is stored on current node.
+    async fn forward_to_topic(&self, ctx: &MessagePayload<Message>, vid: Did) -> Result<bool> {
+        match self.dht.find_successor(vid)? {
+            PeerRingAction::Some(_) => Ok(false),
+            PeerRingAction::RemoteAction(next, _) => {
+                let mut relay = ctx.relay.clone();
+                relay.reset_destination(next)?;
+                relay.relay(self.dht.did, Some(next))?;
+                self.forward_payload(ctx, relay).await?;
+                Ok(true)
+            }
+            act => Err(Error::PeerRingUnexpectedAction(act)),
+        }
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<SubscribeTopic> for MessageHandler {
+    /// Register the origin as a subscriber if the topic is stored here,
+    /// otherwise forward to the responsible node.
+    async fn handle(&self, ctx: &MessagePayload<Message>, msg: &SubscribeTopic) -> Result<()> {
+        if !self.forward_to_topic(ctx, msg.vid).await? {
+            let subscriber = ctx.origin_verification.session.authorizer_did();
+            self.dht
+                .add_topic_subscriber(msg.vid, subscriber, TOPIC_SUBSCRIPTION_TTL_MS);
+        }
+        Ok(())
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<UnsubscribeTopic> for MessageHandler {
+    async fn handle(&self, ctx: &MessagePayload<Message>, msg: &UnsubscribeTopic) -> Result<()> {
+        if !self.forward_to_topic(ctx, msg.vid).await? {
+            let subscriber = ctx.origin_verification.session.authorizer_did();
+            self.dht.remove_topic_subscriber(msg.vid, subscriber);
+        }
+        Ok(())
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<TopicMessages> for MessageHandler {
+    /// Forward pushed data to subscriber, it will be delivered to callback as a builtin message.
+    /// Data is only accepted from the node storing the topic, as far as current node knows.
+    /// A stale subscription will be cancelled on the storing node.
+    async fn handle(&self, ctx: &MessagePayload<Message>, msg: &TopicMessages) -> Result<()> {
+        if self.dht.did != ctx.relay.destination {
+            return self.forward_to_destination(ctx).await;
+        }
+
+        let sender = ctx.origin_verification.session.authorizer_did();
+        if !self.dht.may_store(msg.vid, sender)? {
+            return Err(Error::TopicNotStoredBy(sender, msg.vid));
+        }
+
+        if self.dht.topic_subscriptions.get(&msg.vid).is_none() {
+            if sender != self.dht.did {
+                let unsubscribe = UnsubscribeTopic { vid: msg.vid };
+                self.send_message(Message::UnsubscribeTopic(unsubscribe), sender)
+                    .await?;
+            }
+            return Err(Error::TopicNotSubscribed(msg.vid));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ecc::tests::gen_ordered_keys;
+    use crate::message::handlers::connection::tests::test_only_two_nodes_establish_connection;
+    use crate::message::ChordStorageInterface;
+    use crate::message::Encoder;
+    use crate::tests::default::prepare_node;
+
+    #[tokio::test]
+    async fn test_topic_push() -> Result<()> {
+        let keys = gen_ordered_keys(2);
+        let (key1, key2) = (keys[0], keys[1]);
+        let (did1, dht1, swarm1, node1, _path1) = prepare_node(key1).await;
+        let (did2, dht2, swarm2, node2, _path2) = prepare_node(key2).await;
+        test_only_two_nodes_establish_connection(&node1, &node2).await?;
+
+        let topic = "Across the Great Wall we can reach every corner in the world.".to_string();
+        let vid = VirtualNode::gen_did(&topic)?;
+
+        // Make sure the topic is stored on node2.
+        let ((did1, _dht1, swarm1, node1), (_did2, dht2, _swarm2, node2)) =
+            if vid.in_range(did2, did2, did1) {
+                ((did1, dht1, swarm1, node1), (did2, dht2, swarm2, node2))
+            } else {
+                ((did2, dht2, swarm2, node2), (did1, dht1, swarm1, node1))
+            };
+
+        swarm1.topic_subscribe(&topic).await?;
+        let ev = node2.listen_once().await.unwrap();
+        assert!(matches!(ev.data, Message::SubscribeTopic(x) if x.vid == vid));
+        assert_eq!(dht2.topic_subscribers_of(vid), vec![did1]);
+
+        // Appended data is pushed to subscriber once it's stored.
+        swarm1
+            .storage_append_data(&topic, "111".to_string().encode()?)
+            .await?;
+        let ev = node2.listen_once().await.unwrap();
+        assert!(matches!(ev.data, Message::OperateVNode(_)));
+        let ev = node1.listen_once().await.unwrap();
+        assert!(matches!(
+            ev.data,
+            Message::TopicMessages(x) if x.vid == vid && x.data == vec!["111".to_string().encode()?]
+        ));
+
+        swarm1.topic_unsubscribe(&topic).await?;
+        let ev = node2.listen_once().await.unwrap();
+        assert!(matches!(ev.data, Message::UnsubscribeTopic(x) if x.vid == vid));
+        assert!(dht2.topic_subscribers_of(vid).is_empty());
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+}
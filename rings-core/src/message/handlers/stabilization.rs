@@ -1,14 +1,14 @@
 use async_trait::async_trait;
 
 use crate::dht::Chord;
-use crate::dht::ChordStorage;
-use crate::dht::PeerRingAction;
-use crate::dht::PeerRingRemoteAction;
 use crate::err::Result;
+use crate::message::types::CheckPredecessorReport;
+use crate::message::types::CheckPredecessorSend;
 use crate::message::types::Message;
 use crate::message::types::NotifyPredecessorReport;
 use crate::message::types::NotifyPredecessorSend;
-use crate::message::types::SyncVNodeWithSuccessor;
+use crate::message::types::PingReport;
+use crate::message::types::PingSend;
 use crate::message::HandleMsg;
 use crate::message::MessageHandler;
 use crate::message::MessagePayload;
@@ -61,17 +61,88 @@ impl HandleMsg<NotifyPredecessorReport> for MessageHandler {
             {
                 self.dht.lock_successor()?.update(msg.did)
             }
-            if let Ok(PeerRingAction::RemoteAction(
-                next,
-                PeerRingRemoteAction::SyncVNodeWithSuccessor(data),
-            )) = self.dht.sync_vnode_with_successor(msg.did).await
-            {
-                self.send_message(
-                    Message::SyncVNodeWithSuccessor(SyncVNodeWithSuccessor { data }),
-                    next,
-                )
-                .await?;
-            }
+            self.sync_vnode_with_successor(msg.did).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<CheckPredecessorSend> for MessageHandler {
+    async fn handle(
+        &self,
+        ctx: &MessagePayload<Message>,
+        _msg: &CheckPredecessorSend,
+    ) -> Result<()> {
+        let mut relay = ctx.relay.clone();
+
+        relay.relay(self.dht.did, None)?;
+        self.send_report_message(
+            Message::CheckPredecessorReport(CheckPredecessorReport),
+            ctx.tx_id,
+            relay,
+        )
+        .await
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<CheckPredecessorReport> for MessageHandler {
+    async fn handle(
+        &self,
+        ctx: &MessagePayload<Message>,
+        _msg: &CheckPredecessorReport,
+    ) -> Result<()> {
+        let mut relay = ctx.relay.clone();
+
+        relay.relay(self.dht.did, None)?;
+        if relay.next_hop.is_some() {
+            return self.forward_payload(ctx, relay).await;
+        }
+
+        // Only the signed origin answering the last check is counted.
+        let origin = ctx.origin_verification.session.authorizer_did();
+        if !self.dht.predecessor_alive(origin, ctx.tx_id)? {
+            tracing::debug!("unexpected check report {} from {:?}", ctx.tx_id, origin);
+        }
+        Ok(())
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<PingSend> for MessageHandler {
+    async fn handle(&self, ctx: &MessagePayload<Message>, msg: &PingSend) -> Result<()> {
+        let mut relay = ctx.relay.clone();
+
+        relay.relay(self.dht.did, None)?;
+        self.send_report_message(
+            Message::PingReport(PingReport {
+                sent_at: msg.sent_at,
+            }),
+            ctx.tx_id,
+            relay,
+        )
+        .await
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<PingReport> for MessageHandler {
+    async fn handle(&self, ctx: &MessagePayload<Message>, _: &PingReport) -> Result<()> {
+        let mut relay = ctx.relay.clone();
+
+        relay.relay(self.dht.did, None)?;
+        if relay.next_hop.is_some() {
+            return self.forward_payload(ctx, relay).await;
+        }
+
+        // The timestamp in report is not trusted, rtt is measured by local clock.
+        if self.dht.finish_ping(ctx.addr, ctx.tx_id).is_none() {
+            tracing::debug!("unexpected ping report {} from {:?}", ctx.tx_id, ctx.addr);
         }
         Ok(())
     }
@@ -279,6 +350,15 @@ mod test {
             Message::NotifyPredecessorSend(NotifyPredecessorSend{did}) if did == did2
         ));
 
+        // node2 check node1
+        let ev1 = node1.listen_once().await.unwrap();
+        assert_eq!(ev1.addr, did2);
+        assert_eq!(ev1.relay.path, vec![did2]);
+        assert!(matches!(
+            ev1.data,
+            Message::CheckPredecessorSend(CheckPredecessorSend)
+        ));
+
         // node3 notify node1
         let ev1 = node1.listen_once().await.unwrap();
         assert_eq!(ev1.addr, did3);
@@ -297,6 +377,15 @@ mod test {
             Message::NotifyPredecessorSend(NotifyPredecessorSend{did}) if did == did1
         ));
 
+        // node1 check node2
+        let ev2 = node2.listen_once().await.unwrap();
+        assert_eq!(ev2.addr, did1);
+        assert_eq!(ev2.relay.path, vec![did1]);
+        assert!(matches!(
+            ev2.data,
+            Message::CheckPredecessorSend(CheckPredecessorSend)
+        ));
+
         // node3 notify node2
         let ev2 = node2.listen_once().await.unwrap();
         assert_eq!(ev2.addr, did3);
@@ -306,6 +395,15 @@ mod test {
             Message::NotifyPredecessorSend(NotifyPredecessorSend{did}) if did == did3
         ));
 
+        // node3 check node2
+        let ev2 = node2.listen_once().await.unwrap();
+        assert_eq!(ev2.addr, did3);
+        assert_eq!(ev2.relay.path, vec![did3]);
+        assert!(matches!(
+            ev2.data,
+            Message::CheckPredecessorSend(CheckPredecessorSend)
+        ));
+
         // node2 notify node3
         let ev3 = node3.listen_once().await.unwrap();
         assert_eq!(ev3.addr, did2);
@@ -342,6 +440,33 @@ mod test {
             Message::NotifyPredecessorReport(NotifyPredecessorReport{did}) if did == did2
         ));
 
+        // node2 answer check of node1
+        let ev1 = node1.listen_once().await.unwrap();
+        assert_eq!(ev1.addr, did2);
+        assert_eq!(ev1.relay.path, vec![did1, did2]);
+        assert!(matches!(
+            ev1.data,
+            Message::CheckPredecessorReport(CheckPredecessorReport)
+        ));
+
+        // node1 answer check of node2
+        let ev2 = node2.listen_once().await.unwrap();
+        assert_eq!(ev2.addr, did1);
+        assert_eq!(ev2.relay.path, vec![did2, did1]);
+        assert!(matches!(
+            ev2.data,
+            Message::CheckPredecessorReport(CheckPredecessorReport)
+        ));
+
+        // node2 answer check of node3
+        let ev3 = node3.listen_once().await.unwrap();
+        assert_eq!(ev3.addr, did2);
+        assert_eq!(ev3.relay.path, vec![did3, did2]);
+        assert!(matches!(
+            ev3.data,
+            Message::CheckPredecessorReport(CheckPredecessorReport)
+        ));
+
         assert_no_more_msg(&node1, &node2, &node3).await;
 
         println!("=== Check state after second stabilization ===");
@@ -509,6 +634,29 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_probe_rtts() -> Result<()> {
+        let keys = gen_ordered_keys(2);
+        let (key1, key2) = (keys[0], keys[1]);
+        let (_did1, dht1, swarm1, node1, _path1) = prepare_node(key1).await;
+        let (did2, _dht2, _swarm2, node2, _path2) = prepare_node(key2).await;
+        test_only_two_nodes_establish_connection(&node1, &node2).await?;
+        assert_eq!(dht1.rtt_of(did2), None);
+
+        Stabilization::new(swarm1, 5).probe_rtts().await?;
+
+        let ev2 = node2.listen_once().await.unwrap();
+        assert!(matches!(ev2.data, Message::PingSend(_)));
+        let ev1 = node1.listen_once().await.unwrap();
+        assert_eq!(ev1.addr, did2);
+        assert!(matches!(ev1.data, Message::PingReport(_)));
+        assert!(dht1.rtt_of(did2).is_some());
+        assert!(dht1.pings.is_empty());
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
     async fn run_stabilize_once(swarm: Arc<Swarm>) -> Result<()> {
         Stabilization::new(swarm, 5).stabilize().await
     }
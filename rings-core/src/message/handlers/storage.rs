@@ -9,15 +9,21 @@ use crate::dht::PeerRingRemoteAction;
 use crate::err::Error;
 use crate::err::Result;
 use crate::message::types::FoundVNode;
+use crate::message::types::FoundVNodeRange;
 use crate::message::types::Message;
+use crate::message::types::ReplicateVNode;
 use crate::message::types::SearchVNode;
+use crate::message::types::SearchVNodeRange;
 use crate::message::types::SyncVNodeWithSuccessor;
+use crate::message::types::SyncVNodeWithSuccessorReport;
+use crate::message::types::VNodeVersionConflict;
 use crate::message::Encoded;
 use crate::message::HandleMsg;
 use crate::message::MessageHandler;
 use crate::message::MessagePayload;
 use crate::message::PayloadSender;
 use crate::prelude::vnode::VNodeOperation;
+use crate::prelude::vnode::VNodeType;
 use crate::swarm::Swarm;
 
 /// ChordStorageInterface should imply necessary method for DHT storage
@@ -28,12 +34,31 @@ pub trait ChordStorageInterface {
     async fn storage_check_cache(&self, vid: Did) -> Option<VirtualNode>;
     /// fetch virtual node from DHT
     async fn storage_fetch(&self, vid: Did) -> Result<()>;
+    /// fetch virtual node from DHT and wait for the result
+    async fn storage_fetch_and_wait(
+        &self,
+        vid: Did,
+        timeout_ms: u64,
+    ) -> Result<Option<VirtualNode>>;
+    /// fetch at most `limit` virtual nodes in the clockwise range from `start` to `end`,
+    /// returns them with the start of next page
+    async fn storage_fetch_range_and_wait(
+        &self,
+        start: Did,
+        end: Did,
+        limit: usize,
+        timeout_ms: u64,
+    ) -> Result<(Vec<VirtualNode>, Option<Did>)>;
     /// store virtual node on DHT
     async fn storage_store(&self, vnode: VirtualNode) -> Result<()>;
     /// append data to Data type virtual node
     async fn storage_append_data(&self, topic: &str, data: Encoded) -> Result<()>;
     /// append data to Data type virtual node uniquely
     async fn storage_touch_data(&self, topic: &str, data: Encoded) -> Result<()>;
+    /// overwrite Data type virtual node if its version is still the expected one
+    async fn storage_compare_and_swap(&self, vnode: VirtualNode, version: u64) -> Result<()>;
+    /// apply an operation to virtual node on DHT
+    async fn storage_operate(&self, op: VNodeOperation) -> Result<()>;
 }
 
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
@@ -63,29 +88,152 @@ impl ChordStorageInterface for Swarm {
         }
     }
 
+    /// Fetch virtual node like `storage_fetch`, but wait for the matched `FoundVNode`
+    /// until `timeout_ms`. Returns None if the virtual node is not found.
+    async fn storage_fetch_and_wait(
+        &self,
+        vid: Did,
+        timeout_ms: u64,
+    ) -> Result<Option<VirtualNode>> {
+        let next = match self.dht.vnode_lookup(vid).await? {
+            PeerRingAction::None => return Ok(None),
+            PeerRingAction::SomeVNode(v) => {
+                self.dht.local_cache_set(v.clone());
+                return Ok(Some(v));
+            }
+            PeerRingAction::RemoteAction(next, _) if self.iterative_lookup => {
+                self.find_storage_or(vid, next, timeout_ms).await
+            }
+            PeerRingAction::RemoteAction(next, _) => next,
+            act => return Err(Error::PeerRingUnexpectedAction(act)),
+        };
+
+        let report = match self
+            .send_message_and_wait(Message::SearchVNode(SearchVNode { vid }), next, timeout_ms)
+            .await
+        {
+            Ok(report) => report,
+            Err(Error::RequestTimeout(_)) => return Err(Error::VNodeFetchTimeout(vid)),
+            Err(e) => return Err(e),
+        };
+
+        match report.data {
+            Message::FoundVNode(x) => Ok(x.data.into_iter().find(|v| v.did == vid)),
+            x => Err(Error::InvalidMessage(format!(
+                "unexpected report of SearchVNode: {:?}",
+                x
+            ))),
+        }
+    }
+
+    /// Walk successors from `start` to `end` until `limit` virtual nodes are found.
+    /// Each storing node is asked in turn, and `timeout_ms` is applied to each of them.
+    /// Pages are trimmed to the remaining range and limit, and a page that doesn't move
+    /// the cursor forward is rejected, so that a remote node cannot stall or widen the walk.
+    async fn storage_fetch_range_and_wait(
+        &self,
+        start: Did,
+        end: Did,
+        limit: usize,
+        timeout_ms: u64,
+    ) -> Result<(Vec<VirtualNode>, Option<Did>)> {
+        let mut data = vec![];
+        let mut next = Some(start);
+
+        while let Some(cursor) = next {
+            if data.len() >= limit {
+                break;
+            }
+            let remaining = limit - data.len();
+
+            let (mut page, page_next) = match self.dht.vnode_range(cursor, end, remaining).await? {
+                PeerRingAction::SomeVNodeRange(page, next) => (page, next),
+                PeerRingAction::RemoteAction(target, _) => {
+                    let target = if self.iterative_lookup {
+                        self.find_storage_or(cursor, target, timeout_ms).await
+                    } else {
+                        target
+                    };
+                    let msg = Message::SearchVNodeRange(SearchVNodeRange {
+                        start: cursor,
+                        end,
+                        limit: remaining,
+                    });
+                    let report = match self.send_message_and_wait(msg, target, timeout_ms).await {
+                        Ok(report) => report,
+                        Err(Error::RequestTimeout(_)) => {
+                            return Err(Error::VNodeFetchTimeout(cursor))
+                        }
+                        Err(e) => return Err(e),
+                    };
+                    match report.data {
+                        Message::FoundVNodeRange(x) => (x.data, x.next),
+                        x => {
+                            return Err(Error::InvalidMessage(format!(
+                                "unexpected report of SearchVNodeRange: {:?}",
+                                x
+                            )))
+                        }
+                    }
+                }
+                act => return Err(Error::PeerRingUnexpectedAction(act)),
+            };
+
+            page.retain(|vnode| cursor == end || vnode.did - cursor < end - cursor);
+            page.truncate(remaining);
+            data.append(&mut page);
+
+            if let Some(n) = page_next {
+                if n - start <= cursor - start || (start != end && n - start >= end - start) {
+                    return Err(Error::InvalidMessage(format!(
+                        "next {:?} of range page doesn't advance from {:?} to {:?}",
+                        n, cursor, end
+                    )));
+                }
+            }
+            next = page_next;
+        }
+
+        Ok((data, next))
+    }
+
     /// Store VirtualNode, `TryInto<VirtualNode>` is implemented for alot of types
+    /// A RelayMessage type VirtualNode will be extended instead of overwritten,
+    /// so that all messages parked for the same destination are kept.
     async fn storage_store(&self, vnode: VirtualNode) -> Result<()> {
-        let op = VNodeOperation::Overwrite(vnode);
-        match self.dht.vnode_operate(op).await? {
+        let op = match vnode.kind {
+            VNodeType::RelayMessage => VNodeOperation::Extend(vnode),
+            _ => VNodeOperation::Overwrite(vnode),
+        };
+        match self.dht.vnode_operate(op, self.did()).await? {
             PeerRingAction::None => Ok(()),
             PeerRingAction::RemoteAction(target, PeerRingRemoteAction::FindVNodeForOperate(op)) => {
                 self.send_message(Message::OperateVNode(op), target).await?;
                 Ok(())
             }
+            PeerRingAction::MultiActions(acts) => self.replicate_vnode(acts).await,
             act => Err(Error::PeerRingUnexpectedAction(act)),
         }
     }
 
+    /// Append data to topic, subscribers will be pushed once it's applied.
     async fn storage_append_data(&self, topic: &str, data: Encoded) -> Result<()> {
         let vnode = (topic.to_string(), data).try_into()?;
         let op = VNodeOperation::Extend(vnode);
 
-        match self.dht.vnode_operate(op).await? {
-            PeerRingAction::None => Ok(()),
+        match self.dht.vnode_operate(op.clone(), self.did()).await? {
+            PeerRingAction::None => {
+                self.push_topic_messages(&op).await;
+                Ok(())
+            }
             PeerRingAction::RemoteAction(target, PeerRingRemoteAction::FindVNodeForOperate(op)) => {
                 self.send_message(Message::OperateVNode(op), target).await?;
                 Ok(())
             }
+            PeerRingAction::MultiActions(acts) => {
+                self.push_topic_messages(&op).await;
+                self.replicate_vnode(acts).await
+            }
             act => Err(Error::PeerRingUnexpectedAction(act)),
         }
     }
@@ -94,15 +242,88 @@ impl ChordStorageInterface for Swarm {
         let vnode = (topic.to_string(), data).try_into()?;
         let op = VNodeOperation::Touch(vnode);
 
-        match self.dht.vnode_operate(op).await? {
+        match self.dht.vnode_operate(op, self.did()).await? {
             PeerRingAction::None => Ok(()),
             PeerRingAction::RemoteAction(target, PeerRingRemoteAction::FindVNodeForOperate(op)) => {
                 self.send_message(Message::OperateVNode(op), target).await?;
                 Ok(())
             }
+            PeerRingAction::MultiActions(acts) => self.replicate_vnode(acts).await,
             act => Err(Error::PeerRingUnexpectedAction(act)),
         }
     }
+
+    /// Overwrite VirtualNode only if it's not changed since `version`.
+    /// A stale write on local will fail immediately, a stale write on remote
+    /// will be reported back by [VNodeVersionConflict] message.
+    async fn storage_compare_and_swap(&self, vnode: VirtualNode, version: u64) -> Result<()> {
+        let op = VNodeOperation::CompareAndSwap(vnode, version);
+
+        match self.dht.vnode_operate(op, self.did()).await? {
+            PeerRingAction::None => Ok(()),
+            PeerRingAction::RemoteAction(target, PeerRingRemoteAction::FindVNodeForOperate(op)) => {
+                self.send_message(Message::OperateVNode(op), target).await?;
+                Ok(())
+            }
+            PeerRingAction::MultiActions(acts) => self.replicate_vnode(acts).await,
+            act => Err(Error::PeerRingUnexpectedAction(act)),
+        }
+    }
+
+    /// Apply [VNodeOperation] on DHT, use it when the VirtualNode carries owner and
+    /// write policy, see [VirtualNode::with_owner].
+    async fn storage_operate(&self, op: VNodeOperation) -> Result<()> {
+        match self.dht.vnode_operate(op.clone(), self.did()).await? {
+            PeerRingAction::None => {
+                self.push_topic_messages(&op).await;
+                Ok(())
+            }
+            PeerRingAction::RemoteAction(target, PeerRingRemoteAction::FindVNodeForOperate(op)) => {
+                self.send_message(Message::OperateVNode(op), target).await?;
+                Ok(())
+            }
+            PeerRingAction::MultiActions(acts) => {
+                self.push_topic_messages(&op).await;
+                self.replicate_vnode(acts).await
+            }
+            act => Err(Error::PeerRingUnexpectedAction(act)),
+        }
+    }
+}
+
+impl Swarm {
+    /// Send replicas of an operated vnode to successors.
+    /// Replication is best effort, failures are only logged.
+    pub(crate) async fn replicate_vnode(&self, acts: Vec<PeerRingAction>) -> Result<()> {
+        for act in acts {
+            match act {
+                PeerRingAction::RemoteAction(target, PeerRingRemoteAction::ReplicateVNode(op)) => {
+                    let msg = Message::ReplicateVNode(ReplicateVNode {
+                        op,
+                        primary: self.did(),
+                        remaining: self.dht.replica_factor.saturating_sub(1),
+                    });
+                    if let Err(e) = self.send_direct_message(msg, target).await {
+                        tracing::warn!("failed to replicate vnode to {:?}: {}", target, e);
+                    }
+                }
+                act => return Err(Error::PeerRingUnexpectedAction(act)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Find the node storing `vid` iteratively. If that fails, the request is routed
+    /// recursively through `next` instead.
+    async fn find_storage_or(&self, vid: Did, next: Did, timeout_ms: u64) -> Did {
+        match self.find_storage_iterative(vid, timeout_ms).await {
+            Ok(did) => did,
+            Err(e) => {
+                tracing::warn!("iterative lookup of {:?} failed, route it: {}", vid, e);
+                next
+            }
+        }
+    }
 }
 
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
@@ -110,12 +331,22 @@ impl ChordStorageInterface for Swarm {
 impl HandleMsg<SearchVNode> for MessageHandler {
     /// Search VNode via successor
     /// If a VNode is storead local, it will response immediately.
+    /// If the primary is gone, the search will be forwarded to the successor holding replica.
+    /// If nothing found, an empty `FoundVNode` will be reported.
     async fn handle(&self, ctx: &MessagePayload<Message>, msg: &SearchVNode) -> Result<()> {
         let mut relay = ctx.relay.clone();
 
         match self.dht.vnode_lookup(msg.vid).await {
             Ok(action) => match action {
-                PeerRingAction::None => Ok(()),
+                PeerRingAction::None => {
+                    relay.relay(self.dht.did, None)?;
+                    self.send_report_message(
+                        Message::FoundVNode(FoundVNode { data: vec![] }),
+                        ctx.tx_id,
+                        relay,
+                    )
+                    .await
+                }
                 PeerRingAction::SomeVNode(v) => {
                     relay.relay(self.dht.did, None)?;
                     self.send_report_message(
@@ -125,6 +356,18 @@ impl HandleMsg<SearchVNode> for MessageHandler {
                     )
                     .await
                 }
+                PeerRingAction::RemoteAction(next, PeerRingRemoteAction::FindVNodeReplica(_))
+                    if relay.path.contains(&next) =>
+                {
+                    // The replica holder has been asked already, nothing found.
+                    relay.relay(self.dht.did, None)?;
+                    self.send_report_message(
+                        Message::FoundVNode(FoundVNode { data: vec![] }),
+                        ctx.tx_id,
+                        relay,
+                    )
+                    .await
+                }
                 PeerRingAction::RemoteAction(next, _) => {
                     relay.relay(self.dht.did, Some(next))?;
                     self.forward_payload(ctx, relay).await
@@ -155,52 +398,224 @@ impl HandleMsg<FoundVNode> for MessageHandler {
     }
 }
 
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<SearchVNodeRange> for MessageHandler {
+    /// Search VNodes in range via successor.
+    /// The node storing the start of range will report VNodes stored on it.
+    async fn handle(&self, ctx: &MessagePayload<Message>, msg: &SearchVNodeRange) -> Result<()> {
+        let mut relay = ctx.relay.clone();
+
+        match self.dht.vnode_range(msg.start, msg.end, msg.limit).await? {
+            PeerRingAction::SomeVNodeRange(data, next) => {
+                relay.relay(self.dht.did, None)?;
+                self.send_report_message(
+                    Message::FoundVNodeRange(FoundVNodeRange { data, next }),
+                    ctx.tx_id,
+                    relay,
+                )
+                .await
+            }
+            PeerRingAction::RemoteAction(next, _) => {
+                relay.relay(self.dht.did, Some(next))?;
+                self.forward_payload(ctx, relay).await
+            }
+            act => Err(Error::PeerRingUnexpectedAction(act)),
+        }
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<FoundVNodeRange> for MessageHandler {
+    async fn handle(&self, ctx: &MessagePayload<Message>, _: &FoundVNodeRange) -> Result<()> {
+        let mut relay = ctx.relay.clone();
+
+        relay.relay(self.dht.did, None)?;
+        if relay.next_hop.is_some() {
+            self.forward_payload(ctx, relay).await
+        } else {
+            Ok(())
+        }
+    }
+}
+
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
 #[cfg_attr(not(feature = "wasm"), async_trait)]
 impl HandleMsg<VNodeOperation> for MessageHandler {
+    /// Operate VNode if it's stored here, otherwise forward to the responsible node.
+    /// Subscribers of topic will be pushed once the operation is applied here.
+    /// The write policy of stored VNode is checked against the signed origin of message.
     async fn handle(&self, ctx: &MessagePayload<Message>, msg: &VNodeOperation) -> Result<()> {
-        match self.dht.vnode_operate(msg.clone()).await {
+        let operator = ctx.origin_verification.session.authorizer_did();
+        match self.dht.vnode_operate(msg.clone(), operator).await {
             Ok(action) => match action {
-                PeerRingAction::None => Ok(()),
+                PeerRingAction::None => {
+                    self.swarm.push_topic_messages(msg).await;
+                    Ok(())
+                }
                 PeerRingAction::RemoteAction(next, _) => {
                     let mut relay = ctx.relay.clone();
                     relay.reset_destination(next)?;
                     relay.relay(self.dht.did, Some(next))?;
                     self.forward_payload(ctx, relay).await
                 }
+                PeerRingAction::MultiActions(acts) => {
+                    self.swarm.push_topic_messages(msg).await;
+                    self.swarm.replicate_vnode(acts).await
+                }
                 act => Err(Error::PeerRingUnexpectedAction(act)),
             },
+            Err(Error::VNodeVersionConflict(expected, current)) => {
+                let mut relay = ctx.relay.clone();
+                relay.relay(self.dht.did, None)?;
+                self.send_report_message(
+                    Message::VNodeVersionConflict(VNodeVersionConflict {
+                        vid: msg.did()?,
+                        expected,
+                        current,
+                    }),
+                    ctx.tx_id,
+                    relay,
+                )
+                .await?;
+                Err(Error::VNodeVersionConflict(expected, current))
+            }
             Err(e) => Err(e),
         }?;
         Ok(())
     }
 }
 
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<VNodeVersionConflict> for MessageHandler {
+    /// The origin will get this message via callback, then it may fetch and retry.
+    async fn handle(
+        &self,
+        ctx: &MessagePayload<Message>,
+        msg: &VNodeVersionConflict,
+    ) -> Result<()> {
+        let mut relay = ctx.relay.clone();
+
+        relay.relay(self.dht.did, None)?;
+        if relay.next_hop.is_some() {
+            self.forward_payload(ctx, relay).await
+        } else {
+            tracing::warn!(
+                "operation on vnode {:?} is rejected, expected version {}, but current is {}",
+                msg.vid,
+                msg.expected,
+                msg.current
+            );
+            Ok(())
+        }
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<ReplicateVNode> for MessageHandler {
+    /// Keep a replica of vnode stored on predecessor, then pass it to successor if more
+    /// replicas are required. Only the predecessor, which is either the primary or the
+    /// previous one keeping the replica, is trusted.
+    async fn handle(&self, ctx: &MessagePayload<Message>, msg: &ReplicateVNode) -> Result<()> {
+        let origin = ctx.origin_verification.session.authorizer_did();
+        if Some(origin) != *self.dht.lock_predecessor()? {
+            return Err(Error::InvalidMessage(format!(
+                "replica from {:?} which is not the predecessor",
+                origin
+            )));
+        }
+        self.dht
+            .vnode_replicate(msg.primary, msg.op.clone())
+            .await?;
+
+        let successor = self.dht.lock_successor()?.min();
+        if msg.remaining > 0 && successor != self.dht.did && successor != msg.primary {
+            let msg = Message::ReplicateVNode(ReplicateVNode {
+                op: msg.op.clone(),
+                primary: msg.primary,
+                remaining: msg.remaining - 1,
+            });
+            if let Err(e) = self.send_direct_message(msg, successor).await {
+                tracing::warn!("failed to replicate vnode to {:?}: {}", successor, e);
+            }
+        }
+        Ok(())
+    }
+}
+
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
 #[cfg_attr(not(feature = "wasm"), async_trait)]
 impl HandleMsg<SyncVNodeWithSuccessor> for MessageHandler {
-    // received remote sync vnode request
+    /// Take over virtual nodes handed off by a node which is no longer responsible for them,
+    /// then report the taken ones, so that the origin can remove them safely.
+    /// A failed one is skipped, instead of aborting the whole sync.
+    /// Only neighbors can hand off virtual nodes in the range of current node, and stored
+    /// ones are only replaced if the origin is allowed to overwrite them.
     async fn handle(
         &self,
-        _ctx: &MessagePayload<Message>,
+        ctx: &MessagePayload<Message>,
         msg: &SyncVNodeWithSuccessor,
     ) -> Result<()> {
+        let origin = ctx.origin_verification.session.authorizer_did();
+        let relay_vid = VirtualNode::gen_relay_message_did(self.dht.did);
+        let mut vids = vec![];
         for data in msg.data.iter().cloned() {
-            // only simply store here
-            self.swarm.storage_store(data).await?;
+            let vid = data.did;
+            let result = if data.kind == VNodeType::RelayMessage && data.did == relay_vid {
+                // messages parked for current node while it was offline
+                self.replay_relay_messages(&data).await
+            } else {
+                self.dht.vnode_handoff(data, origin).await
+            };
+            match result {
+                Ok(()) => vids.push(vid),
+                Err(e) => tracing::warn!("failed to take over vnode {:?}: {}", vid, e),
+            }
+        }
+
+        let mut relay = ctx.relay.clone();
+        relay.relay(self.dht.did, None)?;
+        self.send_report_message(
+            Message::SyncVNodeWithSuccessorReport(SyncVNodeWithSuccessorReport { vids }),
+            ctx.tx_id,
+            relay,
+        )
+        .await
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<SyncVNodeWithSuccessorReport> for MessageHandler {
+    async fn handle(
+        &self,
+        ctx: &MessagePayload<Message>,
+        _: &SyncVNodeWithSuccessorReport,
+    ) -> Result<()> {
+        let mut relay = ctx.relay.clone();
+
+        relay.relay(self.dht.did, None)?;
+        if relay.next_hop.is_some() {
+            self.forward_payload(ctx, relay).await
+        } else {
+            Ok(())
         }
-        Ok(())
     }
 }
 
 #[cfg(not(feature = "wasm"))]
 #[cfg(test)]
 mod test {
+    use num_bigint::BigUint;
+
     use super::*;
     use crate::ecc::tests::gen_ordered_keys;
     use crate::message::handlers::connection::tests::test_only_two_nodes_establish_connection;
     use crate::message::Encoder;
-    use crate::prelude::vnode::VNodeType;
+    use crate::prelude::vnode::VNodeWritePolicy;
     use crate::storage::PersistenceStorageOperation;
     use crate::tests::default::prepare_node;
 
@@ -266,7 +681,11 @@ mod test {
             Some(VirtualNode {
                 did: vid,
                 data: vec![data.encode()?],
-                kind: VNodeType::Data
+                kind: VNodeType::Data,
+                version: 1,
+                owner: None,
+                policy: VNodeWritePolicy::Public,
+                expires: vec![]
             })
         );
 
@@ -274,6 +693,110 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_fetch_and_wait() -> Result<()> {
+        let keys = gen_ordered_keys(2);
+        let (key1, key2) = (keys[0], keys[1]);
+        let (did1, dht1, swarm1, node1, _path1) = prepare_node(key1).await;
+        let (did2, dht2, swarm2, node2, _path2) = prepare_node(key2).await;
+        test_only_two_nodes_establish_connection(&node1, &node2).await?;
+
+        let data = "Across the Great Wall we can reach every corner in the world.".to_string();
+        let vnode: VirtualNode = data.clone().try_into().unwrap();
+        let vid = vnode.did;
+
+        // Make sure the data is stored on node2.
+        let ((did1, _dht1, swarm1, node1), (did2, _dht2, _swarm2, node2)) =
+            if vid.in_range(did2, did2, did1) {
+                ((did1, dht1, swarm1, node1), (did2, dht2, swarm2, node2))
+            } else {
+                ((did2, dht2, swarm2, node2), (did1, dht1, swarm1, node1))
+            };
+
+        swarm1.storage_store(vnode.clone()).await.unwrap();
+        node2.listen_once().await.unwrap();
+
+        // The result is returned once FoundVNode is handled.
+        let (result, _) = tokio::join!(swarm1.storage_fetch_and_wait(vid, 3000), async {
+            node2.listen_once().await.unwrap();
+            node1.listen_once().await.unwrap();
+        });
+        assert_eq!(result?.map(|v| v.data), Some(vec![data.encode()?]));
+        assert!(swarm1.pending_requests.lock().unwrap().is_empty());
+
+        // An empty FoundVNode is reported when nothing is stored.
+        let missing = (0..)
+            .map(|i| VirtualNode::gen_did(&format!("missing {}", i)).unwrap())
+            .find(|vid| vid.in_range(did2, did2, did1))
+            .unwrap();
+        let (result, _) = tokio::join!(swarm1.storage_fetch_and_wait(missing, 3000), async {
+            node2.listen_once().await.unwrap();
+            let ev = node1.listen_once().await.unwrap();
+            assert!(matches!(ev.data, Message::FoundVNode(x) if x.data.is_empty()));
+        });
+        assert_eq!(result?, None);
+
+        // Timeout if no one answers.
+        let result = swarm1.storage_fetch_and_wait(vid, 100).await;
+        assert!(matches!(result, Err(Error::VNodeFetchTimeout(x)) if x == vid));
+        assert!(swarm1.pending_requests.lock().unwrap().is_empty());
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fetch_range_and_wait() -> Result<()> {
+        let keys = gen_ordered_keys(2);
+        let (key1, key2) = (keys[0], keys[1]);
+        let (did1, dht1, swarm1, node1, _path1) = prepare_node(key1).await;
+        let (did2, dht2, _swarm2, node2, _path2) = prepare_node(key2).await;
+        test_only_two_nodes_establish_connection(&node1, &node2).await?;
+
+        // Store a vnode on each node.
+        let gen_vnode = |a: Did, b: Did| -> VirtualNode {
+            (0..)
+                .map(|i| format!("vnode {}", i).try_into().unwrap())
+                .find(|v: &VirtualNode| v.did.in_range(a, a, b))
+                .unwrap()
+        };
+        let (vnode1, vnode2) = (gen_vnode(did1, did2), gen_vnode(did2, did1));
+        dht1.storage.put(&vnode1.did, &vnode1).await?;
+        dht2.storage.put(&vnode2.did, &vnode2).await?;
+
+        let one = Did::from(BigUint::from(1u8));
+        let end = did1 - one;
+
+        // Walk from node1 to node2.
+        let (result, _) = tokio::join!(
+            swarm1.storage_fetch_range_and_wait(did1, end, 10, 3000),
+            async {
+                node2.listen_once().await.unwrap();
+                node1.listen_once().await.unwrap();
+            }
+        );
+        assert_eq!(result?, (vec![vnode1.clone(), vnode2.clone()], None));
+        assert!(swarm1.pending_requests.lock().unwrap().is_empty());
+
+        // Paginated by limit, the next page starts from node2.
+        let result = swarm1
+            .storage_fetch_range_and_wait(did1, end, 1, 3000)
+            .await;
+        assert_eq!(result?, (vec![vnode1], Some(did2 + one)));
+
+        let (result, _) = tokio::join!(
+            swarm1.storage_fetch_range_and_wait(did2 + one, end, 1, 3000),
+            async {
+                node2.listen_once().await.unwrap();
+                node1.listen_once().await.unwrap();
+            }
+        );
+        assert_eq!(result?, (vec![vnode2], None));
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_extend_data() -> Result<()> {
         let keys = gen_ordered_keys(2);
@@ -308,7 +831,7 @@ mod test {
         let ev = node2.listen_once().await.unwrap();
         assert!(matches!(
             ev.data,
-            Message::OperateVNode(VNodeOperation::Extend(VirtualNode { did, data, kind: VNodeType::Data }))
+            Message::OperateVNode(VNodeOperation::Extend(VirtualNode { did, data, kind: VNodeType::Data, .. }))
             if did == vid && data == vec!["111".to_string().encode()?]
         ));
 
@@ -319,7 +842,7 @@ mod test {
         let ev = node2.listen_once().await.unwrap();
         assert!(matches!(
             ev.data,
-            Message::OperateVNode(VNodeOperation::Extend(VirtualNode { did, data, kind: VNodeType::Data }))
+            Message::OperateVNode(VNodeOperation::Extend(VirtualNode { did, data, kind: VNodeType::Data, .. }))
             if did == vid && data == vec!["222".to_string().encode()?]
         ));
 
@@ -351,7 +874,11 @@ mod test {
             Some(VirtualNode {
                 did: vid,
                 data: vec!["111".to_string().encode()?, "222".to_string().encode()?],
-                kind: VNodeType::Data
+                kind: VNodeType::Data,
+                version: 2,
+                owner: None,
+                policy: VNodeWritePolicy::Public,
+                expires: vec![]
             })
         );
 
@@ -363,7 +890,7 @@ mod test {
         let ev = node2.listen_once().await.unwrap();
         assert!(matches!(
             ev.data,
-            Message::OperateVNode(VNodeOperation::Extend(VirtualNode { did, data, kind: VNodeType::Data }))
+            Message::OperateVNode(VNodeOperation::Extend(VirtualNode { did, data, kind: VNodeType::Data, .. }))
             if did == vid && data == vec!["333".to_string().encode()?]
         ));
 
@@ -394,11 +921,69 @@ mod test {
                     "222".to_string().encode()?,
                     "333".to_string().encode()?
                 ],
-                kind: VNodeType::Data
+                kind: VNodeType::Data,
+                version: 3,
+                owner: None,
+                policy: VNodeWritePolicy::Public,
+                expires: vec![]
             })
         );
 
         tokio::fs::remove_dir_all("./tmp").await.ok();
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_compare_and_swap_conflict() -> Result<()> {
+        let keys = gen_ordered_keys(2);
+        let (key1, key2) = (keys[0], keys[1]);
+        let (did1, dht1, swarm1, node1, _path1) = prepare_node(key1).await;
+        let (did2, dht2, swarm2, node2, _path2) = prepare_node(key2).await;
+        test_only_two_nodes_establish_connection(&node1, &node2).await?;
+
+        let topic = "config".to_string();
+        let gen_vnode =
+            |data: &str| -> VirtualNode { (topic.clone(), data.to_string()).try_into().unwrap() };
+        let vid = gen_vnode("v1").did;
+
+        // Make sure the data is stored on node2.
+        let ((_did1, _dht1, swarm1, node1), (_did2, dht2, _swarm2, node2)) =
+            if vid.in_range(did2, did2, did1) {
+                ((did1, dht1, swarm1, node1), (did2, dht2, swarm2, node2))
+            } else {
+                ((did2, dht2, swarm2, node2), (did1, dht1, swarm1, node1))
+            };
+
+        swarm1.storage_compare_and_swap(gen_vnode("v1"), 0).await?;
+        node2.listen_once().await.unwrap();
+        swarm1.storage_compare_and_swap(gen_vnode("v2"), 1).await?;
+        node2.listen_once().await.unwrap();
+        let stored: VirtualNode = dht2.storage.get(&vid).await?.unwrap();
+        assert_eq!(stored.version, 2);
+
+        // A writer who read version 1 is rejected, and the conflict is reported back.
+        swarm1.storage_compare_and_swap(gen_vnode("v3"), 1).await?;
+        node2.listen_once().await.unwrap();
+        let ev = node1.listen_once().await.unwrap();
+        assert!(matches!(
+            ev.data,
+            Message::VNodeVersionConflict(x) if x.vid == vid && x.expected == 1 && x.current == 2
+        ));
+        let stored: VirtualNode = dht2.storage.get(&vid).await?.unwrap();
+        assert_eq!(stored.data, gen_vnode("v2").data);
+
+        // A writer cannot skip versions by carrying a forged one.
+        let forged = VirtualNode {
+            version: 100,
+            ..gen_vnode("v4")
+        };
+        swarm1.storage_store(forged).await?;
+        node2.listen_once().await.unwrap();
+        let stored: VirtualNode = dht2.storage.get(&vid).await?.unwrap();
+        assert_eq!(stored.version, 3);
+        assert_eq!(stored.data, gen_vnode("v4").data);
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
 }
@@ -1,12 +1,29 @@
 #![warn(missing_docs)]
 use async_trait::async_trait;
+use futures::future::join_all;
 
-use crate::dht::ChordStorage;
+use crate::consts::DEFAULT_FETCH_TIMEOUT_MS;
+use crate::dht::did::BiasId;
+use crate::dht::subring::Subring;
+use crate::dht::subring::SubringChord;
+use crate::dht::vnode::VirtualNode;
+use crate::dht::Did;
 use crate::dht::PeerRingAction;
-use crate::dht::PeerRingRemoteAction;
 use crate::err::Error;
 use crate::err::Result;
+use crate::message::types::CustomMessage;
+use crate::message::types::FindSubringSuccessorReport;
+use crate::message::types::FindSubringSuccessorSend;
+use crate::message::types::LeaveSubring;
+use crate::message::types::MaybeEncrypted;
 use crate::message::types::Message;
+use crate::message::types::NotifySubringPredecessorReport;
+use crate::message::types::NotifySubringPredecessorSend;
+use crate::message::types::SubringBroadcast;
+use crate::message::ChordStorageInterface;
+use crate::message::HandleMsg;
+use crate::message::MessageHandler;
+use crate::message::MessagePayload;
 use crate::message::PayloadSender;
 use crate::prelude::vnode::VNodeOperation;
 use crate::swarm::Swarm;
@@ -17,22 +34,433 @@ use crate::swarm::Swarm;
 pub trait SubringInterface {
     /// join a subring
     async fn subring_join(&self, name: &str) -> Result<()>;
+    /// leave a subring
+    async fn subring_leave(&self, name: &str) -> Result<()>;
+    /// remove a member from subring, only allowed for the creator
+    async fn subring_remove_member(&self, name: &str, member: Did) -> Result<()>;
+    /// list members of a subring
+    async fn subring_members(&self, name: &str) -> Result<Vec<Did>>;
+    /// send message to all other members of a subring, only allowed for members
+    async fn subring_broadcast(
+        &self,
+        name: &str,
+        data: MaybeEncrypted<CustomMessage>,
+    ) -> Result<()>;
 }
 
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
 #[cfg_attr(not(feature = "wasm"), async_trait)]
 impl SubringInterface for Swarm {
-    /// add did into current chord subring.
-    /// send direct message with `JoinSubring` type, which will handled by `next` node.
+    /// register current node as an entry point of subring on DHT,
+    /// then find successor in subring via another entry point.
     async fn subring_join(&self, name: &str) -> Result<()> {
         let op = VNodeOperation::JoinSubring(name.to_string(), self.dht.did);
-        match self.dht.vnode_operate(op).await? {
-            PeerRingAction::None => Ok(()),
-            PeerRingAction::RemoteAction(target, PeerRingRemoteAction::FindVNodeForOperate(op)) => {
-                self.send_message(Message::OperateVNode(op), target).await?;
-                Ok(())
+        self.storage_operate(op).await?;
+
+        let chord = SubringChord::new(name, self.dht.did)?;
+        let chord = self.dht.add_subring(chord).await?;
+        self.subring_find_successor(&chord).await
+    }
+
+    /// remove current node from entry points of subring,
+    /// then splice its predecessor and successor in subring.
+    async fn subring_leave(&self, name: &str) -> Result<()> {
+        self.subring_remove_member(name, self.dht.did).await?;
+
+        let vid = VirtualNode::gen_did(name)?;
+        if let Some(chord) = self.dht.remove_subring(vid).await? {
+            let msg = LeaveSubring {
+                subring: vid,
+                predecessor: *chord.lock_predecessor()?,
+                successor: chord.lock_successor()?.list().first().copied(),
+            };
+            for did in chord.neighbors()? {
+                if let Err(e) = self
+                    .send_message(Message::LeaveSubring(msg.clone()), did)
+                    .await
+                {
+                    tracing::warn!(
+                        "failed to notify subring member {:?} of leaving: {}",
+                        did,
+                        e
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// remove did from entry points of subring.
+    /// the node holding subring will reject it unless it's sent by the member or the creator.
+    async fn subring_remove_member(&self, name: &str, member: Did) -> Result<()> {
+        let op = VNodeOperation::LeaveSubring(name.to_string(), member);
+        self.storage_operate(op).await
+    }
+
+    /// walk through subring by successors, starting from members known by current node if
+    /// it's a member, otherwise from entry points. Members found in a round are asked for
+    /// their successors concurrently in the next round, so the walk is bounded by the largest
+    /// gap between starting members instead of the size of subring.
+    /// A member that doesn't answer is skipped.
+    async fn subring_members(&self, name: &str) -> Result<Vec<Did>> {
+        let vid = VirtualNode::gen_did(name)?;
+        let (start, mut members) = match self.dht.subrings.get(&vid) {
+            Some(chord) => {
+                let mut members = chord.neighbors()?;
+                members.insert(0, chord.member);
+                (chord.member, members)
+            }
+            None => {
+                let members = self.subring_entry_points(name).await?;
+                let start = *members
+                    .last()
+                    .ok_or_else(|| Error::SubringNotFound(name.to_string()))?;
+                (start, members)
+            }
+        };
+
+        let mut asked = vec![];
+        loop {
+            let round = members
+                .iter()
+                .filter(|did| !asked.contains(*did))
+                .copied()
+                .collect::<Vec<_>>();
+            if round.is_empty() {
+                break;
+            }
+            asked.extend(round.iter().copied());
+
+            let results =
+                join_all(round.iter().map(|did| self.subring_successor_of(vid, *did))).await;
+            for (did, result) in round.into_iter().zip(results) {
+                match result {
+                    Ok(next) if !members.contains(&next) => members.push(next),
+                    Ok(_) => {}
+                    Err(e) => {
+                        tracing::warn!("subring member {:?} is unreachable: {}", did, e);
+                        members.retain(|m| *m != did);
+                    }
+                }
+            }
+        }
+        members.sort_by_key(|did| BiasId::new(start, *did));
+        Ok(members)
+    }
+
+    /// send message via finger table of subring.
+    /// members only accept broadcasts from members they know.
+    async fn subring_broadcast(
+        &self,
+        name: &str,
+        data: MaybeEncrypted<CustomMessage>,
+    ) -> Result<()> {
+        let vid = VirtualNode::gen_did(name)?;
+        let chord = self
+            .dht
+            .subrings
+            .get(&vid)
+            .ok_or(Error::SubringNotJoined(vid))?;
+        self.forward_subring_broadcast(&chord, chord.member, data)
+            .await
+    }
+}
+
+impl Swarm {
+    /// Fetch subring from DHT and return its entry points except current node.
+    async fn subring_entry_points(&self, name: &str) -> Result<Vec<Did>> {
+        let vid = VirtualNode::gen_did(name)?;
+        match self
+            .storage_fetch_and_wait(vid, DEFAULT_FETCH_TIMEOUT_MS)
+            .await?
+        {
+            Some(vnode) => {
+                let subring: Subring = vnode.try_into()?;
+                Ok(subring
+                    .members
+                    .into_iter()
+                    .filter(|did| *did != self.dht.did)
+                    .collect())
             }
-            act => Err(Error::PeerRingUnexpectedAction(act)),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Look for the successor of current member via an entry point of subring.
+    pub(crate) async fn subring_find_successor(&self, chord: &SubringChord) -> Result<()> {
+        if let Some(entry) = self.subring_entry_points(&chord.name).await?.pop() {
+            let msg = FindSubringSuccessorSend {
+                subring: chord.did,
+                did: chord.member,
+            };
+            self.send_message(Message::FindSubringSuccessorSend(msg), entry)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Record a member in subring. It's notified if it's new to current member or it
+    /// becomes the successor, so that it also knows current member, which is required
+    /// to accept broadcasts from current member.
+    pub(crate) async fn subring_meet(&self, chord: &SubringChord, did: Did) -> Result<()> {
+        let known = chord.is_known(did)?;
+        if chord.join(did)? || !known {
+            self.notify_subring_predecessor(chord, did).await?;
+        }
+        Ok(())
+    }
+
+    /// Ask a member for its successor in subring.
+    async fn subring_successor_of(&self, subring: Did, member: Did) -> Result<Did> {
+        if let Some(chord) = self.dht.subrings.get(&subring) {
+            if chord.member == member {
+                return Ok(chord.lock_successor()?.min());
+            }
+        }
+
+        let msg = FindSubringSuccessorSend {
+            subring,
+            did: member,
+        };
+        let report = self
+            .send_message_and_wait(
+                Message::FindSubringSuccessorSend(msg),
+                member,
+                DEFAULT_FETCH_TIMEOUT_MS,
+            )
+            .await?;
+        match report.data {
+            Message::FindSubringSuccessorReport(FindSubringSuccessorReport {
+                successor: Some(successor),
+                ..
+            }) => Ok(successor),
+            x => Err(Error::InvalidMessage(format!(
+                "unexpected report of FindSubringSuccessorSend: {:?}",
+                x
+            ))),
+        }
+    }
+
+    /// Forward a broadcast to members between current node and `limit` in subring.
+    /// Broadcast is best effort, failures are only logged.
+    pub(crate) async fn forward_subring_broadcast(
+        &self,
+        chord: &SubringChord,
+        limit: Did,
+        data: MaybeEncrypted<CustomMessage>,
+    ) -> Result<()> {
+        for (did, limit) in chord.broadcast_targets(limit)? {
+            let msg = SubringBroadcast {
+                subring: chord.did,
+                limit,
+                data: data.clone(),
+            };
+            if let Err(e) = self.send_message(Message::SubringBroadcast(msg), did).await {
+                tracing::warn!("failed to broadcast to subring member {:?}: {}", did, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Notify a member that current node is its predecessor in subring.
+    pub(crate) async fn notify_subring_predecessor(
+        &self,
+        chord: &SubringChord,
+        did: Did,
+    ) -> Result<()> {
+        let msg = NotifySubringPredecessorSend {
+            subring: chord.did,
+            did: chord.member,
+        };
+        self.send_message(Message::NotifySubringPredecessorSend(msg), did)
+            .await?;
+        Ok(())
+    }
+}
+
+impl MessageHandler {
+    /// Get the subring joined by current node.
+    fn joined_subring(&self, subring: Did) -> Result<SubringChord> {
+        self.dht
+            .subrings
+            .get(&subring)
+            .ok_or(Error::SubringNotJoined(subring))
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<FindSubringSuccessorSend> for MessageHandler {
+    /// Report the successor if it's known, otherwise report the closest preceding member,
+    /// so that origin can continue the lookup.
+    async fn handle(
+        &self,
+        ctx: &MessagePayload<Message>,
+        msg: &FindSubringSuccessorSend,
+    ) -> Result<()> {
+        if self.dht.did != ctx.relay.destination {
+            return self.forward_to_destination(ctx).await;
+        }
+        let chord = self.joined_subring(msg.subring)?;
+        let (successor, closest) = match chord.find_successor(msg.did)? {
+            PeerRingAction::Some(did) => (Some(did), None),
+            PeerRingAction::RemoteAction(next, _) => (None, Some(next)),
+            act => return Err(Error::PeerRingUnexpectedAction(act)),
+        };
+
+        let mut relay = ctx.relay.clone();
+        relay.relay(self.dht.did, None)?;
+        self.send_report_message(
+            Message::FindSubringSuccessorReport(FindSubringSuccessorReport {
+                subring: msg.subring,
+                did: msg.did,
+                successor,
+                closest,
+            }),
+            ctx.tx_id,
+            relay,
+        )
+        .await
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<FindSubringSuccessorReport> for MessageHandler {
+    async fn handle(
+        &self,
+        ctx: &MessagePayload<Message>,
+        msg: &FindSubringSuccessorReport,
+    ) -> Result<()> {
+        let mut relay = ctx.relay.clone();
+        relay.relay(self.dht.did, None)?;
+        if relay.next_hop.is_some() {
+            return self.forward_payload(ctx, relay).await;
+        }
+
+        // The origin may be listing members without joining.
+        let chord = match self.dht.subrings.get(&msg.subring) {
+            Some(chord) => chord,
+            None => return Ok(()),
+        };
+        match (msg.successor, msg.closest) {
+            (Some(successor), _) => self.swarm.subring_meet(&chord, successor).await?,
+            (None, Some(closest)) if closest != self.dht.did => {
+                let msg = FindSubringSuccessorSend {
+                    subring: msg.subring,
+                    did: msg.did,
+                };
+                self.send_message(Message::FindSubringSuccessorSend(msg), closest)
+                    .await?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<NotifySubringPredecessorSend> for MessageHandler {
+    async fn handle(
+        &self,
+        ctx: &MessagePayload<Message>,
+        msg: &NotifySubringPredecessorSend,
+    ) -> Result<()> {
+        if self.dht.did != ctx.relay.destination {
+            return self.forward_to_destination(ctx).await;
+        }
+        let chord = self.joined_subring(msg.subring)?;
+        let origin = ctx.origin_verification.session.authorizer_did();
+        if origin != msg.did {
+            return Err(Error::InvalidMessage(format!(
+                "{:?} notifies subring on behalf of {:?}",
+                origin, msg.did
+            )));
+        }
+        let predecessor = { *chord.lock_predecessor()? };
+
+        chord.notify(msg.did)?;
+        // The notifier may also be the successor, e.g. a subring with only two members.
+        chord.join(msg.did)?;
+
+        if let Some(did) = predecessor {
+            if did != msg.did {
+                let mut relay = ctx.relay.clone();
+                relay.relay(self.dht.did, None)?;
+                return self
+                    .send_report_message(
+                        Message::NotifySubringPredecessorReport(NotifySubringPredecessorReport {
+                            subring: msg.subring,
+                            did,
+                        }),
+                        ctx.tx_id,
+                        relay,
+                    )
+                    .await;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<NotifySubringPredecessorReport> for MessageHandler {
+    async fn handle(
+        &self,
+        ctx: &MessagePayload<Message>,
+        msg: &NotifySubringPredecessorReport,
+    ) -> Result<()> {
+        let mut relay = ctx.relay.clone();
+        relay.relay(self.dht.did, None)?;
+        if relay.next_hop.is_some() {
+            return self.forward_payload(ctx, relay).await;
+        }
+
+        // if predecessor of successor is between (self, successor], it's the new successor.
+        let chord = self.joined_subring(msg.subring)?;
+        self.swarm.subring_meet(&chord, msg.did).await
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<LeaveSubring> for MessageHandler {
+    async fn handle(&self, ctx: &MessagePayload<Message>, msg: &LeaveSubring) -> Result<()> {
+        if self.dht.did != ctx.relay.destination {
+            return self.forward_to_destination(ctx).await;
+        }
+        let chord = self.joined_subring(msg.subring)?;
+        chord.remove(ctx.origin_verification.session.authorizer_did())?;
+
+        if let Some(successor) = msg.successor {
+            self.swarm.subring_meet(&chord, successor).await?;
+        }
+        if let Some(predecessor) = msg.predecessor {
+            chord.notify(predecessor)?;
+            self.swarm.subring_meet(&chord, predecessor).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<SubringBroadcast> for MessageHandler {
+    /// Forward the broadcast, the message will be delivered to callback as a custom message.
+    /// It's only accepted from a known member, which has been notified by the sender.
+    async fn handle(&self, ctx: &MessagePayload<Message>, msg: &SubringBroadcast) -> Result<()> {
+        if self.dht.did != ctx.relay.destination {
+            return self.forward_to_destination(ctx).await;
+        }
+        let chord = self.joined_subring(msg.subring)?;
+        let origin = ctx.origin_verification.session.authorizer_did();
+        if !chord.is_known(origin)? {
+            return Err(Error::SubringMemberUnknown(origin, msg.subring));
         }
+        self.swarm
+            .forward_subring_broadcast(&chord, msg.limit, msg.data.clone())
+            .await
     }
 }
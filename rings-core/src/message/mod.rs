@@ -17,6 +17,7 @@ mod types;
 pub use types::*;
 
 mod handlers;
+pub use handlers::pubsub::PubSubInterface;
 pub use handlers::storage::ChordStorageInterface;
 pub use handlers::subring::SubringInterface;
 pub use handlers::CallbackFn;
@@ -6,6 +6,9 @@ use bytes::Bytes;
 use flate2::write::GzDecoder;
 use flate2::write::GzEncoder;
 use flate2::Compression;
+use futures::channel::oneshot;
+use futures::future::select;
+use futures::future::Either;
 use serde::de::DeserializeOwned;
 use serde::Deserialize;
 use serde::Serialize;
@@ -28,6 +31,7 @@ use crate::err::Error;
 use crate::err::Result;
 use crate::session::SessionManager;
 use crate::utils::get_epoch_ms;
+use crate::utils::sleep_ms;
 
 pub fn encode_data_gzip(data: &Bytes, level: u8) -> Result<Bytes> {
     let mut ec = GzEncoder::new(Vec::new(), Compression::new(level as u32));
@@ -85,7 +89,8 @@ where T: Serialize + DeserializeOwned
     ) -> Result<Self> {
         let ts_ms = get_epoch_ms();
         let ttl_ms = DEFAULT_TTL_MS;
-        let msg = &MessageVerification::pack_msg(&data, ts_ms, ttl_ms)?;
+        let network_id = session_manager.network_id();
+        let msg = &MessageVerification::pack_msg(&data, ts_ms, ttl_ms, network_id)?;
         let tx_id = uuid::Uuid::new_v4();
         let addr = session_manager.authorizer()?;
         let verification = MessageVerification {
@@ -93,6 +98,7 @@ where T: Serialize + DeserializeOwned
             sig: session_manager.sign(msg)?,
             ttl_ms,
             ts_ms,
+            network_id,
         };
 
         let origin_verification = match origin_verification_gen {
@@ -172,6 +178,19 @@ where T: Serialize + DeserializeOwned
         self.verification.verify(&self.data) && self.origin_verification.verify(&self.data)
     }
 
+    /// Check if both the sender and the origin of payload are in the network.
+    pub fn check_network_id(&self, network_id: u32) -> Result<()> {
+        for got in [
+            self.verification.network_id,
+            self.origin_verification.network_id,
+        ] {
+            if got != network_id {
+                return Err(Error::NetworkMismatch(network_id, got));
+            }
+        }
+        Ok(())
+    }
+
     pub fn origin_session_pubkey(&self) -> Result<PublicKey> {
         self.origin_verification.session_pubkey(&self.data)
     }
@@ -212,6 +231,11 @@ where T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static
     fn session_manager(&self) -> &SessionManager;
     fn dht(&self) -> Arc<PeerRing>;
     async fn do_send_payload(&self, did: Did, payload: MessagePayload<T>) -> Result<()>;
+    /// Register a request waiting for the REPORT with `tx_id`, which will be delivered
+    /// through the returned receiver.
+    fn register_request(&self, tx_id: uuid::Uuid) -> Result<oneshot::Receiver<MessagePayload<T>>>;
+    /// Forget a request that is no longer waited for.
+    fn cancel_request(&self, tx_id: uuid::Uuid);
 
     async fn send_payload(&self, payload: MessagePayload<T>) -> Result<()> {
         if let Some(did) = payload.relay.next_hop {
@@ -240,6 +264,57 @@ where T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static
         Ok(payload.tx_id)
     }
 
+    /// Send a SEND payload and wait for the REPORT with same tx_id until `timeout_ms`.
+    async fn send_payload_and_wait(
+        &self,
+        payload: MessagePayload<T>,
+        timeout_ms: u64,
+    ) -> Result<MessagePayload<T>> {
+        // Register before sending, so that a fast response will not be missed.
+        let tx_id = payload.tx_id;
+        let receiver = self.register_request(tx_id)?;
+        if let Err(e) = self.send_payload(payload).await {
+            self.cancel_request(tx_id);
+            return Err(e);
+        }
+
+        match select(receiver, Box::pin(sleep_ms(timeout_ms))).await {
+            Either::Left((Ok(report), _)) => Ok(report),
+            _ => {
+                self.cancel_request(tx_id);
+                Err(Error::RequestTimeout(tx_id))
+            }
+        }
+    }
+
+    /// Send a message to destination and wait for the REPORT until `timeout_ms`.
+    async fn send_message_and_wait(
+        &self,
+        msg: T,
+        destination: Did,
+        timeout_ms: u64,
+    ) -> Result<MessagePayload<T>> {
+        let next_hop = match self.dht().find_successor(destination)? {
+            PeerRingAction::Some(did) => did,
+            PeerRingAction::RemoteAction(did, _) => did,
+            _ => return Err(Error::NoNextHop),
+        };
+        let payload = MessagePayload::new_send(msg, self.session_manager(), next_hop, destination)?;
+        self.send_payload_and_wait(payload, timeout_ms).await
+    }
+
+    /// Send a message to a connected node directly and wait for the REPORT until `timeout_ms`.
+    async fn send_direct_message_and_wait(
+        &self,
+        msg: T,
+        destination: Did,
+        timeout_ms: u64,
+    ) -> Result<MessagePayload<T>> {
+        let payload =
+            MessagePayload::new_send(msg, self.session_manager(), destination, destination)?;
+        self.send_payload_and_wait(payload, timeout_ms).await
+    }
+
     async fn send_report_message(
         &self,
         msg: T,
@@ -329,6 +404,30 @@ pub mod test {
         assert!(relaied_payload.verify());
     }
 
+    #[test]
+    fn test_check_network_id() {
+        let key = SecretKey::random();
+        let destination = SecretKey::random().address().into();
+        let session = SessionManager::new_with_seckey(&key, None)
+            .unwrap()
+            .with_network_id(1);
+        let payload =
+            MessagePayload::new_send(new_test_payload().data, &session, destination, destination)
+                .unwrap();
+        assert!(payload.verify());
+        assert!(payload.check_network_id(1).is_ok());
+        assert!(matches!(
+            payload.check_network_id(0),
+            Err(Error::NetworkMismatch(0, 1))
+        ));
+
+        // Network id is signed, so it cannot be changed by relays.
+        let mut tampered = payload;
+        tampered.verification.network_id = 0;
+        tampered.origin_verification.network_id = 0;
+        assert!(!tampered.verify());
+    }
+
     #[test]
     fn test_message_payload_from_auto() {
         let payload = new_test_payload();
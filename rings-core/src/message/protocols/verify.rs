@@ -14,6 +14,7 @@ pub struct MessageVerification {
     pub session: Session,
     pub ttl_ms: usize,
     pub ts_ms: u128,
+    pub network_id: u32,
     pub sig: Vec<u8>,
 }
 
@@ -39,15 +40,16 @@ impl MessageVerification {
         signers::default::recover(&msg, &self.sig)
     }
 
-    pub fn pack_msg<T>(data: &T, ts_ms: u128, ttl_ms: usize) -> Result<String>
+    pub fn pack_msg<T>(data: &T, ts_ms: u128, ttl_ms: usize, network_id: u32) -> Result<String>
     where T: Serialize {
         let mut msg = serde_json::to_string(data).map_err(|_| Error::SerializeToString)?;
-        write!(msg, "\n{}\n{}", ts_ms, ttl_ms).map_err(|_| Error::SerializeToString)?;
+        write!(msg, "\n{}\n{}\n{}", ts_ms, ttl_ms, network_id)
+            .map_err(|_| Error::SerializeToString)?;
         Ok(msg)
     }
 
     fn msg<T>(&self, data: &T) -> Result<String>
     where T: Serialize {
-        Self::pack_msg(data, self.ts_ms, self.ttl_ms)
+        Self::pack_msg(data, self.ts_ms, self.ttl_ms, self.network_id)
     }
 }
@@ -2,6 +2,7 @@ use serde::de::DeserializeOwned;
 use serde::Deserialize;
 use serde::Serialize;
 
+use crate::chunk::Chunk;
 use crate::dht::vnode::VNodeOperation;
 use crate::dht::vnode::VirtualNode;
 use crate::dht::Did;
@@ -9,6 +10,7 @@ use crate::ecc::PublicKey;
 use crate::ecc::SecretKey;
 use crate::err::Error;
 use crate::err::Result;
+use crate::message::Encoded;
 
 /// MessageType use to ask for connection, send to remote with transport_uuid and handshake_info.
 #[derive(Debug, PartialEq, Eq, Deserialize, Serialize, Clone)]
@@ -43,6 +45,22 @@ pub struct FindSuccessorReport {
     pub handler: FindSuccessorReportHandler,
 }
 
+/// MessageType use to ask a node for the successor in iterative lookup.
+/// Instead of forwarding, the node will report what it knows to origin.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct FindSuccessorStepSend {
+    pub did: Did,
+}
+
+/// MessageType report to origin the successor if it's known,
+/// otherwise the closest preceding nodes to ask next.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct FindSuccessorStepReport {
+    pub did: Did,
+    pub successor: Option<Did>,
+    pub candidates: Vec<Did>,
+}
+
 /// MessageType use to notify predecessor, ask for update finger tables.
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
 pub struct NotifyPredecessorSend {
@@ -55,6 +73,26 @@ pub struct NotifyPredecessorReport {
     pub did: Did,
 }
 
+/// MessageType use to check if predecessor is still alive.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct CheckPredecessorSend;
+
+/// MessageType report to origin that predecessor is alive.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct CheckPredecessorReport;
+
+/// MessageType use to measure round-trip time to a connected node.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct PingSend {
+    pub sent_at: u128,
+}
+
+/// MessageType report to origin with the timestamp in [PingSend].
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct PingReport {
+    pub sent_at: u128,
+}
+
 /// MessageType use to join chord ring, add did into fingers table.
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
 pub struct JoinDHT {
@@ -62,9 +100,13 @@ pub struct JoinDHT {
 }
 
 /// MessageType use to leave chord ring.
+/// A gracefully leaving node will carry its predecessor and successor,
+/// so that they can be spliced to each other.
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
 pub struct LeaveDHT {
     pub did: Did,
+    pub predecessor: Option<Did>,
+    pub successor: Option<Did>,
 }
 
 /// MessageType use to search virtual node.
@@ -79,6 +121,22 @@ pub struct FoundVNode {
     pub data: Vec<VirtualNode>,
 }
 
+/// MessageType use to search virtual nodes in the clockwise range from `start` to `end`.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct SearchVNodeRange {
+    pub start: Did,
+    pub end: Did,
+    pub limit: usize,
+}
+
+/// MessageType report to origin found virtual nodes in range.
+/// The `next` is the start of range that is not searched yet.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct FoundVNodeRange {
+    pub data: Vec<VirtualNode>,
+    pub next: Option<Did>,
+}
+
 /// MessageType contains multi messages.
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
 pub struct MultiCall {
@@ -91,10 +149,103 @@ pub struct SyncVNodeWithSuccessor {
     pub data: Vec<VirtualNode>,
 }
 
+/// MessageType report to origin that synced virtual nodes are taken over.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct SyncVNodeWithSuccessorReport {
+    pub vids: Vec<Did>,
+}
+
+/// MessageType report to origin that a [VNodeOperation::CompareAndSwap] is rejected
+/// since the virtual node has been changed by others.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct VNodeVersionConflict {
+    pub vid: Did,
+    pub expected: u64,
+    pub current: u64,
+}
+
+/// MessageType use to keep a replica of an operated virtual node on successors.
+/// It's passed along successors, `remaining` tells how many more of them should keep one.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct ReplicateVNode {
+    pub op: VNodeOperation,
+    pub primary: Did,
+    pub remaining: u8,
+}
+
 /// MessageType use to customize message, will be handle by `custom_message` method.
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
 pub struct CustomMessage(pub Vec<u8>);
 
+/// MessageType use to find successor of `did` among members of a subring.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct FindSubringSuccessorSend {
+    pub subring: Did,
+    pub did: Did,
+}
+
+/// MessageType report to origin the successor of `did` in a subring.
+/// If the successor is not found, origin should continue with the `closest` preceding member.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct FindSubringSuccessorReport {
+    pub subring: Did,
+    pub did: Did,
+    pub successor: Option<Did>,
+    pub closest: Option<Did>,
+}
+
+/// MessageType use to notify predecessor in a subring.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct NotifySubringPredecessorSend {
+    pub subring: Did,
+    pub did: Did,
+}
+
+/// MessageType report to origin the predecessor in a subring.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct NotifySubringPredecessorReport {
+    pub subring: Did,
+    pub did: Did,
+}
+
+/// MessageType use to leave a subring, the leaving member is the origin of message.
+/// It will carry its predecessor and successor, so that they can be spliced to each other.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct LeaveSubring {
+    pub subring: Did,
+    pub predecessor: Option<Did>,
+    pub successor: Option<Did>,
+}
+
+/// MessageType use to broadcast custom message to members of a subring.
+/// The receiver will forward it to members between itself and `limit`.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct SubringBroadcast {
+    pub subring: Did,
+    pub limit: Did,
+    pub data: MaybeEncrypted<CustomMessage>,
+}
+
+/// MessageType use to subscribe a topic, the subscriber is the origin of message.
+/// It will be routed to the node storing the topic, and should be renewed periodically.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct SubscribeTopic {
+    pub vid: Did,
+}
+
+/// MessageType use to unsubscribe a topic, the subscriber is the origin of message.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct UnsubscribeTopic {
+    pub vid: Did,
+}
+
+/// MessageType use to push data appended to a topic to its subscribers.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct TopicMessages {
+    pub vid: Did,
+    pub data: Vec<Encoded>,
+}
+
 /// A enum about Encrypted and Plain types.
 #[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
 pub enum MaybeEncrypted<T> {
@@ -137,13 +288,34 @@ pub enum Message {
     ConnectNodeReport(ConnectNodeReport),
     FindSuccessorSend(FindSuccessorSend),
     FindSuccessorReport(FindSuccessorReport),
+    FindSuccessorStepSend(FindSuccessorStepSend),
+    FindSuccessorStepReport(FindSuccessorStepReport),
     NotifyPredecessorSend(NotifyPredecessorSend),
     NotifyPredecessorReport(NotifyPredecessorReport),
+    CheckPredecessorSend(CheckPredecessorSend),
+    CheckPredecessorReport(CheckPredecessorReport),
+    PingSend(PingSend),
+    PingReport(PingReport),
     SearchVNode(SearchVNode),
     FoundVNode(FoundVNode),
+    SearchVNodeRange(SearchVNodeRange),
+    FoundVNodeRange(FoundVNodeRange),
     OperateVNode(VNodeOperation),
     SyncVNodeWithSuccessor(SyncVNodeWithSuccessor),
+    SyncVNodeWithSuccessorReport(SyncVNodeWithSuccessorReport),
+    ReplicateVNode(ReplicateVNode),
+    VNodeVersionConflict(VNodeVersionConflict),
+    FindSubringSuccessorSend(FindSubringSuccessorSend),
+    FindSubringSuccessorReport(FindSubringSuccessorReport),
+    NotifySubringPredecessorSend(NotifySubringPredecessorSend),
+    NotifySubringPredecessorReport(NotifySubringPredecessorReport),
+    LeaveSubring(LeaveSubring),
+    SubringBroadcast(SubringBroadcast),
+    SubscribeTopic(SubscribeTopic),
+    UnsubscribeTopic(UnsubscribeTopic),
+    TopicMessages(TopicMessages),
     CustomMessage(MaybeEncrypted<CustomMessage>),
+    Chunk(Chunk),
 }
 
 impl std::fmt::Display for Message {
@@ -36,6 +36,7 @@ pub use crate::dht::vnode;
 pub use crate::message;
 pub use crate::message::ChordStorageInterface;
 pub use crate::message::MessageRelay;
+pub use crate::message::PubSubInterface;
 pub use crate::message::SubringInterface;
 pub use crate::storage::PersistenceStorage;
 pub use crate::storage::PersistenceStorageReadAndWrite;
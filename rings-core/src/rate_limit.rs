@@ -0,0 +1,255 @@
+//! This module provide the `RateLimiter` struct, which limits the rate of incoming messages.
+//! Each origin has a token bucket for all its messages, and another one for each type of them.
+#![warn(missing_docs)]
+use std::mem::discriminant;
+use std::mem::Discriminant;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use crate::consts::RATE_LIMIT_HEAVY_MESSAGE;
+use crate::consts::RATE_LIMIT_PER_MESSAGE_TYPE;
+use crate::consts::RATE_LIMIT_PER_PEER;
+use crate::consts::RATE_LIMIT_PRUNE_INTERVAL_MS;
+use crate::dht::Did;
+use crate::message::Message;
+use crate::storage::MemStorage;
+use crate::utils::get_epoch_ms;
+
+/// Limit of a token bucket.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    /// The max number of tokens, namely the max burst of messages.
+    pub capacity: f64,
+    /// The number of tokens refilled per second, namely the sustained rate of messages.
+    pub refill_per_sec: f64,
+}
+
+impl RateLimit {
+    /// Create a limit with burst `capacity` and sustained rate `refill_per_sec`.
+    pub const fn new(capacity: f64, refill_per_sec: f64) -> Self {
+        Self {
+            capacity,
+            refill_per_sec,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    updated_at: u128,
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit, now: u128) -> Self {
+        Self {
+            limit,
+            tokens: limit.capacity,
+            updated_at: now,
+        }
+    }
+
+    /// Tokens available at `now`, refilled by elapsed time.
+    fn refilled(&self, now: u128) -> f64 {
+        let elapsed = now.saturating_sub(self.updated_at) as f64 / 1000.0;
+        (self.tokens + elapsed * self.limit.refill_per_sec).min(self.limit.capacity)
+    }
+
+    /// Refill tokens by elapsed time, then take one if there is.
+    fn take(&mut self, now: u128) -> bool {
+        self.tokens = self.refilled(now);
+        self.updated_at = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+
+    /// A full bucket is the same as a new one, so it can be dropped.
+    fn is_full(&self, now: u128) -> bool {
+        self.refilled(now) >= self.limit.capacity
+    }
+}
+
+type Bucket = Arc<Mutex<TokenBucket>>;
+
+/// `RateLimiter` decides whether an incoming message should be handled or dropped.
+#[derive(Debug)]
+pub struct RateLimiter {
+    per_peer: RateLimit,
+    per_message_type: RateLimit,
+    heavy_message: RateLimit,
+    peer_buckets: MemStorage<Did, Bucket>,
+    message_buckets: MemStorage<(Did, Discriminant<Message>), Bucket>,
+    pruned_at: Mutex<u128>,
+}
+
+impl RateLimiter {
+    /// Create a `RateLimiter` with the limits for all messages of a peer, for each type of
+    /// messages of a peer, and for each type of heavy messages of a peer, see [is_heavy].
+    pub fn new(per_peer: RateLimit, per_message_type: RateLimit, heavy_message: RateLimit) -> Self {
+        Self {
+            per_peer,
+            per_message_type,
+            heavy_message,
+            peer_buckets: MemStorage::new(),
+            message_buckets: MemStorage::new(),
+            pruned_at: Mutex::new(get_epoch_ms()),
+        }
+    }
+
+    /// Check if the message from origin is allowed, a token is consumed if it is.
+    pub fn check(&self, origin: Did, msg: &Message) -> bool {
+        let now = get_epoch_ms();
+        self.prune_if_due(now);
+        let limit = if is_heavy(msg) {
+            self.heavy_message
+        } else {
+            self.per_message_type
+        };
+
+        let message_bucket = self.message_buckets.get_or_set(
+            &(origin, discriminant(msg)),
+            Arc::new(Mutex::new(TokenBucket::new(limit, now))),
+        );
+        let peer_bucket = self.peer_buckets.get_or_set(
+            &origin,
+            Arc::new(Mutex::new(TokenBucket::new(self.per_peer, now))),
+        );
+
+        let allowed = match message_bucket.lock() {
+            Ok(mut bucket) => bucket.take(now),
+            Err(_) => return false,
+        };
+        allowed
+            && match peer_bucket.lock() {
+                Ok(mut bucket) => bucket.take(now),
+                Err(_) => false,
+            }
+    }
+
+    /// Evict buckets of idle peers, which are refilled to capacity, so that buckets
+    /// won't pile up with peers ever seen.
+    pub fn prune(&self, now: u128) {
+        for (key, bucket) in self.message_buckets.items() {
+            if bucket.lock().map(|b| b.is_full(now)).unwrap_or(true) {
+                self.message_buckets.remove(&key);
+            }
+        }
+        for (key, bucket) in self.peer_buckets.items() {
+            if bucket.lock().map(|b| b.is_full(now)).unwrap_or(true) {
+                self.peer_buckets.remove(&key);
+            }
+        }
+    }
+
+    fn prune_if_due(&self, now: u128) {
+        let due = match self.pruned_at.lock() {
+            Ok(mut pruned_at) if now.saturating_sub(*pruned_at) >= RATE_LIMIT_PRUNE_INTERVAL_MS => {
+                *pruned_at = now;
+                true
+            }
+            _ => false,
+        };
+        if due {
+            self.prune(now);
+        }
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(
+            RATE_LIMIT_PER_PEER,
+            RATE_LIMIT_PER_MESSAGE_TYPE,
+            RATE_LIMIT_HEAVY_MESSAGE,
+        )
+    }
+}
+
+/// Messages that are expensive to handle, such as searching storage or calling back to user.
+pub fn is_heavy(msg: &Message) -> bool {
+    matches!(
+        msg,
+        Message::SearchVNode(_)
+            | Message::SearchVNodeRange(_)
+            | Message::OperateVNode(_)
+            | Message::CustomMessage(_)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dht::tests::gen_ordered_dids;
+    use crate::message::CheckPredecessorSend;
+    use crate::message::SearchVNode;
+
+    #[test]
+    fn test_token_bucket() {
+        let limit = RateLimit::new(2.0, 1.0);
+        let mut bucket = TokenBucket::new(limit, 0);
+        assert!(bucket.take(0));
+        assert!(bucket.take(0));
+        assert!(!bucket.take(0));
+        assert!(!bucket.take(500));
+        assert!(bucket.take(1000));
+        // Cannot refill more than capacity.
+        assert!(bucket.take(100000));
+        assert!(bucket.take(100000));
+        assert!(!bucket.take(100000));
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let dids = gen_ordered_dids(2);
+        let (did1, did2) = (dids[0], dids[1]);
+        let search = Message::SearchVNode(SearchVNode { vid: did1 });
+        let check = Message::CheckPredecessorSend(CheckPredecessorSend);
+
+        let limiter = RateLimiter::new(
+            RateLimit::new(3.0, 0.0),
+            RateLimit::new(5.0, 0.0),
+            RateLimit::new(1.0, 0.0),
+        );
+
+        // Heavy messages are limited by type.
+        assert!(limiter.check(did1, &search));
+        assert!(!limiter.check(did1, &search));
+        // Then other types of messages are limited by peer.
+        assert!(limiter.check(did1, &check));
+        assert!(limiter.check(did1, &check));
+        assert!(!limiter.check(did1, &check));
+
+        // Other peers are not affected.
+        assert!(limiter.check(did2, &search));
+    }
+
+    #[test]
+    fn test_rate_limiter_prune() {
+        let dids = gen_ordered_dids(2);
+        let (did1, did2) = (dids[0], dids[1]);
+        let check = Message::CheckPredecessorSend(CheckPredecessorSend);
+
+        let limiter = RateLimiter::new(
+            RateLimit::new(2.0, 1.0),
+            RateLimit::new(2.0, 1.0),
+            RateLimit::new(2.0, 1.0),
+        );
+        assert!(limiter.check(did1, &check));
+        assert!(limiter.check(did2, &check));
+        assert!(limiter.check(did2, &check));
+
+        // did1 is refilled after 1 second, but did2 is not.
+        let now = get_epoch_ms() + 1000;
+        limiter.prune(now);
+        assert_eq!(limiter.peer_buckets.keys(), vec![did2]);
+        assert_eq!(limiter.message_buckets.len(), 1);
+
+        limiter.prune(now + 1000);
+        assert!(limiter.peer_buckets.is_empty());
+        assert!(limiter.message_buckets.is_empty());
+    }
+}
@@ -0,0 +1,172 @@
+//! This module provide the `Scheduler` struct, which schedules outgoing payloads of a transport
+//! by priority. Ring maintenance and data of users share the same transport, so without it,
+//! stabilization could be stuck behind large custom payloads and time out.
+#![warn(missing_docs)]
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+use futures::future::select;
+
+use crate::consts::SCHEDULER_MAX_DELAY_MS;
+use crate::message::Message;
+use crate::transports::helper::Notifier;
+use crate::utils::get_epoch_ms;
+use crate::utils::sleep_ms;
+
+/// Priority classes of messages, a lower one is sent first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Messages maintaining the ring and connections.
+    Control = 0,
+    /// Other builtin messages, such as storage and pubsub.
+    Normal = 1,
+    /// Data of users, namely custom messages and chunks of large payloads.
+    Bulk = 2,
+}
+
+impl Priority {
+    /// Priority of a message, a multicall is as high as the highest one in it.
+    pub fn of(msg: &Message) -> Self {
+        match msg {
+            Message::JoinDHT(_)
+            | Message::LeaveDHT(_)
+            | Message::ConnectNodeSend(_)
+            | Message::ConnectNodeReport(_)
+            | Message::AlreadyConnected(_)
+            | Message::FindSuccessorSend(_)
+            | Message::FindSuccessorReport(_)
+            | Message::FindSuccessorStepSend(_)
+            | Message::FindSuccessorStepReport(_)
+            | Message::NotifyPredecessorSend(_)
+            | Message::NotifyPredecessorReport(_)
+            | Message::CheckPredecessorSend(_)
+            | Message::CheckPredecessorReport(_)
+            | Message::PingSend(_)
+            | Message::PingReport(_)
+            | Message::FindSubringSuccessorSend(_)
+            | Message::FindSubringSuccessorReport(_)
+            | Message::NotifySubringPredecessorSend(_)
+            | Message::NotifySubringPredecessorReport(_) => Self::Control,
+            Message::CustomMessage(_) | Message::Chunk(_) => Self::Bulk,
+            Message::MultiCall(multi) => multi
+                .messages
+                .iter()
+                .map(Self::of)
+                .min()
+                .unwrap_or(Self::Normal),
+            _ => Self::Normal,
+        }
+    }
+}
+
+/// A payload holding its turn to be sent, it leaves the queue of its priority when dropped.
+pub struct Turn<'a> {
+    scheduler: &'a Scheduler,
+    priority: Priority,
+}
+
+impl<'a> Turn<'a> {
+    fn new(scheduler: &'a Scheduler, priority: Priority) -> Self {
+        scheduler.queues[priority as usize].fetch_add(1, Ordering::SeqCst);
+        Self {
+            scheduler,
+            priority,
+        }
+    }
+}
+
+impl Drop for Turn<'_> {
+    fn drop(&mut self) {
+        let queue = &self.scheduler.queues[self.priority as usize];
+        if queue.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.scheduler.emptied.notify_all();
+        }
+    }
+}
+
+/// `Scheduler` keeps a queue for each priority, counting the payloads of it waiting or being sent.
+/// A payload waits for its turn until the queues of higher priorities are empty, or it has
+/// waited for [SCHEDULER_MAX_DELAY_MS], so that lower priorities are never starved.
+/// Each transport has its own scheduler, payloads to other peers never wait for it.
+#[derive(Default)]
+pub struct Scheduler {
+    queues: [AtomicUsize; 3],
+    /// notified when a queue becomes empty
+    emptied: Notifier,
+}
+
+impl Scheduler {
+    /// Wait for the turn of a payload with the priority, hold it until the payload is sent.
+    pub async fn wait_turn(&self, priority: Priority) -> Turn<'_> {
+        let turn = Turn::new(self, priority);
+        let deadline = get_epoch_ms() + SCHEDULER_MAX_DELAY_MS as u128;
+        loop {
+            let emptied = self.emptied.listen();
+            if !self.has_higher_than(priority) {
+                break;
+            }
+            let now = get_epoch_ms();
+            if now >= deadline {
+                break;
+            }
+            select(emptied, Box::pin(sleep_ms((deadline - now) as u64))).await;
+        }
+        turn
+    }
+
+    fn has_higher_than(&self, priority: Priority) -> bool {
+        self.queues[..priority as usize]
+            .iter()
+            .any(|queue| queue.load(Ordering::SeqCst) > 0)
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time::timeout;
+
+    use super::*;
+    use crate::message::CheckPredecessorSend;
+    use crate::message::CustomMessage;
+    use crate::message::MaybeEncrypted;
+    use crate::message::MultiCall;
+
+    #[test]
+    fn test_priority_of_message() {
+        let check = Message::CheckPredecessorSend(CheckPredecessorSend);
+        let custom = Message::CustomMessage(MaybeEncrypted::Plain(CustomMessage(vec![])));
+        assert_eq!(Priority::of(&check), Priority::Control);
+        assert_eq!(Priority::of(&custom), Priority::Bulk);
+        assert_eq!(
+            Priority::of(&Message::MultiCall(MultiCall {
+                messages: vec![custom, check]
+            })),
+            Priority::Control
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scheduler() {
+        let scheduler = Scheduler::default();
+        let wait = Duration::from_millis(100);
+
+        // Bulk waits while control is being sent.
+        let control = scheduler.wait_turn(Priority::Control).await;
+        assert!(timeout(wait, scheduler.wait_turn(Priority::Bulk))
+            .await
+            .is_err());
+        drop(control);
+        assert!(timeout(wait, scheduler.wait_turn(Priority::Bulk))
+            .await
+            .is_ok());
+
+        // Control never waits for bulk.
+        let _bulk = scheduler.wait_turn(Priority::Bulk).await;
+        assert!(timeout(wait, scheduler.wait_turn(Priority::Control))
+            .await
+            .is_ok());
+    }
+}
@@ -15,6 +15,7 @@ use std::sync::RwLock;
 use serde::Deserialize;
 use serde::Serialize;
 
+use crate::consts::DEFAULT_NETWORK_ID;
 use crate::consts::DEFAULT_SESSION_TTL_MS;
 use crate::dht::Did;
 use crate::ecc::signers;
@@ -94,12 +95,15 @@ struct SessionWithKey {
 #[derive(Debug)]
 pub struct SessionManager {
     inner: Arc<RwLock<SessionWithKey>>,
+    /// Id of the network that messages signed by this session belong to.
+    network_id: u32,
 }
 
 impl Clone for SessionManager {
     fn clone(&self) -> Self {
         Self {
             inner: Arc::clone(&self.inner),
+            network_id: self.network_id,
         }
     }
 }
@@ -167,6 +171,11 @@ impl Session {
         }
     }
 
+    /// Get did of authorizer from session.
+    pub fn authorizer_did(&self) -> Did {
+        self.auth.authorizer.did
+    }
+
     /// Get public key from session.
     pub fn authorizer_pubkey(&self) -> Result<PublicKey> {
         let auth = self.auth.to_string()?;
@@ -243,9 +252,16 @@ impl SessionManager {
 
         Self {
             inner: Arc::new(RwLock::new(inner)),
+            network_id: DEFAULT_NETWORK_ID,
         }
     }
 
+    /// Set the network id, so that nodes of other networks will reject our messages.
+    pub fn with_network_id(mut self, network_id: u32) -> Self {
+        self.network_id = network_id;
+        self
+    }
+
     /// Generate Session with private key.
     /// Only use it for unittest.
     pub fn new_with_seckey(key: &SecretKey, ttl: Option<Ttl>) -> Result<Self> {
@@ -296,6 +312,11 @@ impl SessionManager {
     pub fn authorizer(&self) -> Result<Did> {
         Ok(self.session()?.auth.authorizer.did)
     }
+
+    /// Get network id of session.
+    pub fn network_id(&self) -> u32 {
+        self.network_id
+    }
 }
 
 #[cfg(test)]
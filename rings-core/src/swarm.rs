@@ -1,18 +1,30 @@
 //! Tranposrt managerment
-use std::fmt;
+use std::collections::HashMap;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::sync::Mutex;
 
 use async_stream::stream;
 use async_trait::async_trait;
+use futures::channel::oneshot;
 use futures::Stream;
-use serde::de::DeserializeOwned;
-use serde::Serialize;
 
+use bytes::Bytes;
+
+use crate::blocklist::Blocklist;
 use crate::channels::Channel;
+use crate::chunk::Chunk;
+use crate::chunk::ChunkList;
+use crate::chunk::ChunkManager;
+use crate::consts::DEFAULT_FETCH_TIMEOUT_MS;
+use crate::consts::DEFAULT_REPLICA_FACTOR;
+use crate::consts::PAYLOAD_CHUNK_MTU;
+use crate::consts::REPUTATION_EVICT_SCORE;
+use crate::dht::vnode::VirtualNode;
+use crate::dht::Chord;
 use crate::dht::Did;
 use crate::dht::PeerRing;
+use crate::dht::PeerRingAction;
 use crate::ecc::SecretKey;
 use crate::err::Error;
 use crate::err::Result;
@@ -20,20 +32,33 @@ use crate::measure::Measure;
 use crate::measure::MeasureCounter;
 use crate::message;
 use crate::message::CallbackFn;
+use crate::message::ChordStorageInterface;
+use crate::message::CustomMessage;
+use crate::message::Encoded;
+use crate::message::MaybeEncrypted;
 use crate::message::Message;
 use crate::message::MessageHandler;
 use crate::message::MessagePayload;
+use crate::message::MessageRelay;
+use crate::message::OriginVerificationGen;
 use crate::message::PayloadSender;
+use crate::message::RelayMethod;
 use crate::message::ValidatorFn;
 use crate::prelude::RTCSdpType;
+use crate::rate_limit::RateLimiter;
+use crate::scheduler::Priority;
 use crate::session::SessionManager;
 use crate::session::Ttl;
 use crate::storage::MemStorage;
 use crate::storage::PersistenceStorage;
+use crate::storage::PersistenceStorageReadAndWrite;
+use crate::storage::PersistenceStorageRemove;
+use crate::transports::helper::TricklePayload;
 use crate::transports::manager::TransportManager;
 use crate::transports::Transport;
 use crate::types::channel::Channel as ChannelTrait;
 use crate::types::channel::Event;
+use crate::types::ice_transport::DataChannelKind;
 use crate::types::ice_transport::IceServer;
 use crate::types::ice_transport::IceTransportInterface;
 use crate::types::ice_transport::IceTrickleScheme;
@@ -51,10 +76,17 @@ pub struct SwarmBuilder {
     external_address: Option<String>,
     dht_did: Option<Did>,
     dht_succ_max: u8,
+    dht_replica_factor: u8,
+    dht_admission_difficulty: u8,
     dht_storage: PersistenceStorage,
+    dht_subring_storage: Option<PersistenceStorage>,
     session_manager: Option<SessionManager>,
     session_ttl: Option<Ttl>,
+    network_id: Option<u32>,
     measure: Option<MeasureImpl>,
+    blocklist: Option<Blocklist>,
+    rate_limiter: Option<RateLimiter>,
+    iterative_lookup: bool,
 }
 
 impl SwarmBuilder {
@@ -71,10 +103,17 @@ impl SwarmBuilder {
             external_address: None,
             dht_did: None,
             dht_succ_max: 3,
+            dht_replica_factor: DEFAULT_REPLICA_FACTOR,
+            dht_admission_difficulty: 0,
             dht_storage,
+            dht_subring_storage: None,
             session_manager: None,
             session_ttl: None,
+            network_id: None,
             measure: None,
+            blocklist: None,
+            rate_limiter: None,
+            iterative_lookup: false,
         }
     }
 
@@ -83,6 +122,22 @@ impl SwarmBuilder {
         self
     }
 
+    pub fn dht_replica_factor(mut self, replica_factor: u8) -> Self {
+        self.dht_replica_factor = replica_factor;
+        self
+    }
+
+    pub fn dht_admission_difficulty(mut self, difficulty: u8) -> Self {
+        self.dht_admission_difficulty = difficulty;
+        self
+    }
+
+    /// Keep names of joined subrings in the storage, see [PeerRing::load_subrings].
+    pub fn dht_subring_storage(mut self, storage: PersistenceStorage) -> Self {
+        self.dht_subring_storage = Some(storage);
+        self
+    }
+
     pub fn external_address(mut self, external_address: Option<String>) -> Self {
         self.external_address = external_address;
         self
@@ -105,11 +160,34 @@ impl SwarmBuilder {
         self
     }
 
+    /// Nodes only talk to nodes with the same network id.
+    pub fn network_id(mut self, network_id: u32) -> Self {
+        self.network_id = Some(network_id);
+        self
+    }
+
     pub fn measure(mut self, implement: MeasureImpl) -> Self {
         self.measure = Some(implement);
         self
     }
 
+    pub fn blocklist(mut self, blocklist: Blocklist) -> Self {
+        self.blocklist = Some(blocklist);
+        self
+    }
+
+    pub fn rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    /// Look up storing nodes of virtual nodes iteratively instead of recursively,
+    /// see [Swarm::find_storage_iterative].
+    pub fn iterative_lookup(mut self, enabled: bool) -> Self {
+        self.iterative_lookup = enabled;
+        self
+    }
+
     pub fn build(self) -> Result<Swarm> {
         let session_manager = {
             if self.session_manager.is_some() {
@@ -122,12 +200,22 @@ impl SwarmBuilder {
                 ))
             }
         }?;
+        let session_manager = match self.network_id {
+            Some(network_id) => session_manager.with_network_id(network_id),
+            None => session_manager,
+        };
 
         let dht_did = self
             .dht_did
             .ok_or_else(|| Error::SwarmBuildFailed("Should set session_manager or key".into()))?;
 
-        let dht = PeerRing::new_with_storage(dht_did, self.dht_succ_max, self.dht_storage);
+        let dht = PeerRing::new_with_storage(dht_did, self.dht_succ_max, self.dht_storage)
+            .with_replica_factor(self.dht_replica_factor)
+            .with_admission_difficulty(self.dht_admission_difficulty);
+        let dht = match self.dht_subring_storage {
+            Some(storage) => dht.with_subring_storage(storage),
+            None => dht,
+        };
 
         Ok(Swarm {
             pending_transports: Mutex::new(vec![]),
@@ -137,6 +225,11 @@ impl SwarmBuilder {
             external_address: self.external_address,
             dht: Arc::new(dht),
             measure: self.measure,
+            blocklist: self.blocklist.unwrap_or_default(),
+            rate_limiter: self.rate_limiter.unwrap_or_default(),
+            pending_requests: Mutex::new(HashMap::new()),
+            chunk_list: Mutex::new(ChunkList::default()),
+            iterative_lookup: self.iterative_lookup,
             session_manager,
         })
     }
@@ -151,6 +244,15 @@ pub struct Swarm {
     pub(crate) external_address: Option<String>,
     pub(crate) dht: Arc<PeerRing>,
     pub(crate) measure: Option<MeasureImpl>,
+    pub(crate) blocklist: Blocklist,
+    pub(crate) rate_limiter: RateLimiter,
+    /// Requests waiting for REPORT, indexed by tx_id.
+    pub(crate) pending_requests:
+        Mutex<HashMap<uuid::Uuid, oneshot::Sender<MessagePayload<Message>>>>,
+    /// Chunks of large payloads waiting for the rest of them.
+    pub(crate) chunk_list: Mutex<ChunkList<PAYLOAD_CHUNK_MTU>>,
+    /// Look up storing nodes of virtual nodes iteratively.
+    pub(crate) iterative_lookup: bool,
     session_manager: SessionManager,
 }
 
@@ -167,6 +269,17 @@ impl Swarm {
         &self.session_manager
     }
 
+    pub fn network_id(&self) -> u32 {
+        self.session_manager.network_id()
+    }
+
+    /// Check if the handshake info of a remote node is from the same network,
+    /// it should be called before `register_remote_info`.
+    pub fn check_handshake_info(&self, data: &Encoded) -> Result<()> {
+        let payload: MessagePayload<TricklePayload> = data.decode()?;
+        payload.check_network_id(self.network_id())
+    }
+
     pub fn create_message_handler(
         self: &Arc<Self>,
         callback: Option<CallbackFn>,
@@ -175,6 +288,99 @@ impl Swarm {
         MessageHandler::new(self.clone(), callback, validator)
     }
 
+    /// Send a custom message to destination. If the destination is offline, the message is
+    /// parked on DHT as a `RelayMessage` type [VirtualNode], and it will be replayed to the
+    /// destination once it joins the ring again.
+    pub async fn send_custom_message(
+        &self,
+        msg: MaybeEncrypted<CustomMessage>,
+        destination: Did,
+    ) -> Result<uuid::Uuid> {
+        let msg = Message::CustomMessage(msg);
+        if self.get_transport(destination).is_some() {
+            return self.send_direct_message(msg, destination).await;
+        }
+        match self.dht.find_successor(destination)? {
+            // The destination should be the successor of current node but it's not
+            // connected, which means it's offline.
+            PeerRingAction::Some(node) if node != destination => {
+                let payload =
+                    MessagePayload::new_send(msg, &self.session_manager, node, destination)?;
+                tracing::info!(
+                    "destination {:?} is unreachable, park message {}",
+                    destination,
+                    payload.tx_id
+                );
+                self.storage_store(payload.clone().try_into()?).await?;
+                Ok(payload.tx_id)
+            }
+            _ => self.send_message(msg, destination).await,
+        }
+    }
+
+    /// Wake up the request waiting for this payload, if it's a REPORT arrived at its destination.
+    pub(crate) fn resolve_pending_request(&self, payload: &MessagePayload<Message>) {
+        if payload.relay.method != RelayMethod::REPORT || payload.relay.destination != self.did() {
+            return;
+        }
+        let sender = match self.pending_requests.lock() {
+            Ok(mut pending) => pending.remove(&payload.tx_id),
+            Err(_) => return,
+        };
+        if let Some(sender) = sender {
+            // The receiver is dropped if the request is timeout.
+            sender.send(payload.clone()).ok();
+        }
+    }
+
+    /// Split the data of a large payload into chunks, and send each of them as a
+    /// [Message::Chunk] along the relay of the payload. See [Swarm::handle_chunk].
+    /// Every chunk waits for its turn with the priority of the payload, so that payloads
+    /// of higher priorities can be sent between them.
+    async fn send_chunks(
+        &self,
+        transport: &Transport,
+        data: &Bytes,
+        relay: &MessageRelay,
+        priority: Priority,
+        kind: DataChannelKind,
+    ) -> Result<()> {
+        for chunk in ChunkList::<PAYLOAD_CHUNK_MTU>::from(data) {
+            let payload = MessagePayload::new(
+                Message::Chunk(chunk),
+                &self.session_manager,
+                OriginVerificationGen::Origin,
+                relay.clone(),
+            )?;
+            let _turn = transport.scheduler().wait_turn(priority).await;
+            transport
+                .send_message_via(&payload.to_bincode()?, kind)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Collect a chunk sent to current node, return the payload once all its chunks arrived.
+    pub(crate) fn handle_chunk(&self, chunk: Chunk) -> Result<Option<MessagePayload<Message>>> {
+        let mut chunk_list = self
+            .chunk_list
+            .lock()
+            .map_err(|_| Error::SwarmChunkListLockFailed)?;
+        chunk_list
+            .handle(chunk)
+            .map(|data| MessagePayload::from_bincode(&data))
+            .transpose()
+    }
+
+    /// Send a message to current node itself through the transport event channel,
+    /// so that it will be handled like a message from remote.
+    pub(crate) async fn send_loopback_message(&self, msg: Message) -> Result<()> {
+        let payload =
+            MessagePayload::new_send(msg, &self.session_manager, self.dht.did, self.dht.did)?;
+        let ev = Event::DataChannelMessage(payload.to_bincode()?.to_vec());
+        Channel::send(&self.transport_event_channel.sender(), ev).await
+    }
+
     async fn load_message(
         &self,
         ev: Result<Option<Event>>,
@@ -216,16 +422,11 @@ impl Swarm {
                     );
                 };
 
+                // Handled here rather than by a message, which could be spoofed by others.
                 if let Some(t) = self.get_transport(did) {
                     if t.id == uuid && self.remove_transport(did).is_some() {
                         tracing::info!("[Swarm::ConnectClosed] transport {:?} closed", uuid);
-                        let payload = MessagePayload::new_send(
-                            Message::LeaveDHT(message::LeaveDHT { did }),
-                            &self.session_manager,
-                            self.dht.did,
-                            self.dht.did,
-                        )?;
-                        return Ok(Some(payload));
+                        self.dht.remove(did)?;
                     }
                 }
                 Ok(None)
@@ -309,6 +510,16 @@ impl Swarm {
     }
 
     pub async fn connect(&self, did: Did) -> Result<Arc<Transport>> {
+        if self.is_blocked(did) {
+            return Err(Error::PeerBlocked(did));
+        }
+        if let Some(measure) = &self.measure {
+            let score = measure.get_score(did).await;
+            if score < REPUTATION_EVICT_SCORE {
+                return Err(Error::PeerEvicted(did, score));
+            }
+        }
+
         if let Some(t) = self.get_and_check_transport(did).await {
             return Ok(t);
         }
@@ -326,13 +537,108 @@ impl Swarm {
         self.send_message(connect_msg, did).await?;
         Ok(transport)
     }
+
+    /// Block a peer, its transport will be closed and its messages will be dropped.
+    pub async fn block_peer(&self, did: Did) -> Result<()> {
+        self.blocklist.block(did).await?;
+        self.disconnect(did).await
+    }
+
+    /// Unblock a peer.
+    pub async fn unblock_peer(&self, did: Did) -> Result<()> {
+        self.blocklist.unblock(did).await
+    }
+
+    /// Check if a peer is blocked.
+    pub fn is_blocked(&self, did: Did) -> bool {
+        self.blocklist.is_blocked(did)
+    }
+
+    /// List blocked peers.
+    pub fn blocked_peers(&self) -> Vec<Did> {
+        self.blocklist.list()
+    }
+
+    /// Leave the ring gracefully.
+    /// Hand off all stored `VirtualNode`s to the node taking over them, notify predecessor
+    /// and successors to splice each other in, then close all transports.
+    /// Since a node stores `VirtualNode`s between itself and its successor, the predecessor
+    /// takes over them. It only accepts them from its successor, so they are handed off
+    /// before leaving is announced. Each `VirtualNode` is removed only after the predecessor
+    /// reported that it's taken over, otherwise it would be lost.
+    pub async fn leave(&self) -> Result<()> {
+        let predecessor = *self.dht.lock_predecessor()?;
+        let (successor, mut neighbors) = {
+            let successor = self.dht.lock_successor()?;
+            if successor.is_empty() {
+                (None, vec![])
+            } else {
+                (Some(successor.min()), successor.list())
+            }
+        };
+        if let Some(did) = predecessor {
+            if !neighbors.contains(&did) {
+                neighbors.push(did);
+            }
+        }
+
+        if let Some(heir) = predecessor.filter(|did| *did != self.did()) {
+            let all_items: Vec<(Did, VirtualNode)> = self.dht.storage.get_all().await?;
+            // Send one by one to avoid exceeding the size limit of a message.
+            for (vid, vnode) in all_items {
+                let msg = Message::SyncVNodeWithSuccessor(message::SyncVNodeWithSuccessor {
+                    data: vec![vnode],
+                });
+                let taken = match self
+                    .send_direct_message_and_wait(msg, heir, DEFAULT_FETCH_TIMEOUT_MS)
+                    .await
+                {
+                    Ok(report) => matches!(
+                        report.data,
+                        Message::SyncVNodeWithSuccessorReport(ref x) if x.vids.contains(&vid)
+                    ),
+                    Err(e) => {
+                        tracing::warn!("[Swarm::leave] failed to hand off {:?}: {}", vid, e);
+                        false
+                    }
+                };
+                if taken {
+                    self.dht.storage.remove(&vid).await?;
+                }
+            }
+        }
+
+        let leave_msg = Message::LeaveDHT(message::LeaveDHT {
+            did: self.did(),
+            predecessor,
+            successor,
+        });
+        for did in neighbors.into_iter().filter(|did| *did != self.did()) {
+            if let Err(e) = self.send_direct_message(leave_msg.clone(), did).await {
+                tracing::warn!("[Swarm::leave] failed to notify {:?}: {}", did, e);
+            }
+        }
+
+        for (did, _) in self.get_transports() {
+            self.disconnect(did).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Bulk payloads are sent through a dedicated data channel, so they won't delay others.
+/// Pings are sent through the unreliable one, a lost ping is only a missed sample of rtt.
+fn data_channel_kind(msg: &Message) -> DataChannelKind {
+    match msg {
+        Message::PingSend(_) | Message::PingReport(_) => DataChannelKind::Unreliable,
+        _ if Priority::of(msg) == Priority::Bulk => DataChannelKind::Bulk,
+        _ => DataChannelKind::Default,
+    }
 }
 
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
 #[cfg_attr(not(feature = "wasm"), async_trait)]
-impl<T> PayloadSender<T> for Swarm
-where T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static + fmt::Debug
-{
+impl PayloadSender<Message> for Swarm {
     fn session_manager(&self) -> &SessionManager {
         Swarm::session_manager(self)
     }
@@ -341,7 +647,25 @@ where T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static + fmt::Deb
         Swarm::dht(self)
     }
 
-    async fn do_send_payload(&self, did: Did, payload: MessagePayload<T>) -> Result<()> {
+    fn register_request(
+        &self,
+        tx_id: uuid::Uuid,
+    ) -> Result<oneshot::Receiver<MessagePayload<Message>>> {
+        let (sender, receiver) = oneshot::channel();
+        self.pending_requests
+            .lock()
+            .map_err(|_| Error::SwarmPendingRequestsLockFailed)?
+            .insert(tx_id, sender);
+        Ok(receiver)
+    }
+
+    fn cancel_request(&self, tx_id: uuid::Uuid) {
+        if let Ok(mut pending) = self.pending_requests.lock() {
+            pending.remove(&tx_id);
+        }
+    }
+
+    async fn do_send_payload(&self, did: Did, payload: MessagePayload<Message>) -> Result<()> {
         #[cfg(test)]
         {
             println!("+++++++++++++++++++++++++++++++++");
@@ -367,13 +691,25 @@ where T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static + fmt::Deb
         tracing::info!("send data len: {}", data.len());
 
         transport.wait_for_data_channel_open().await?;
-        let result = transport.send_message(&data).await;
-
+        let priority = Priority::of(&payload.data);
+        let kind = data_channel_kind(&payload.data);
+        // A relayed chunk is larger than PAYLOAD_CHUNK_MTU for its envelope, but it
+        // should be forwarded as it is rather than chunked again.
+        let is_chunk = matches!(payload.data, Message::Chunk(_));
+        let result = if data.len() > PAYLOAD_CHUNK_MTU && !is_chunk {
+            self.send_chunks(&transport, &data, &payload.relay, priority, kind)
+                .await
+        } else {
+            let _turn = transport.scheduler().wait_turn(priority).await;
+            transport.send_message_via(&data, kind).await
+        };
+
+        // A full local send queue is not a failure of the peer, so it's not measured.
         if let (Some(measure), Some(did)) = (&self.measure, payload.relay.next_hop) {
-            if result.is_ok() {
-                measure.incr(did, MeasureCounter::Sent).await
-            } else {
-                measure.incr(did, MeasureCounter::FailedToSend).await
+            match result {
+                Ok(_) => measure.incr(did, MeasureCounter::Sent).await,
+                Err(Error::TransportSendQueueFull) => {}
+                Err(_) => measure.incr(did, MeasureCounter::FailedToSend).await,
             }
         }
 
@@ -389,6 +725,8 @@ pub mod tests {
 
     use super::*;
     use crate::ecc::SecretKey;
+    use crate::message::CheckPredecessorSend;
+    use crate::message::PingSend;
     #[cfg(not(feature = "dummy"))]
     use crate::transports::default::transport::tests::establish_connection;
     #[cfg(feature = "dummy")]
@@ -482,4 +820,14 @@ pub mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_data_channel_kind() {
+        let ping = Message::PingSend(PingSend { sent_at: 0 });
+        let check = Message::CheckPredecessorSend(CheckPredecessorSend);
+        let custom = Message::CustomMessage(MaybeEncrypted::Plain(CustomMessage(vec![])));
+        assert_eq!(data_channel_kind(&ping), DataChannelKind::Unreliable);
+        assert_eq!(data_channel_kind(&check), DataChannelKind::Default);
+        assert_eq!(data_channel_kind(&custom), DataChannelKind::Bulk);
+    }
 }
@@ -31,7 +31,14 @@ pub async fn prepare_node(key: SecretKey) -> (Did, Arc<PeerRing>, Arc<Swarm>, Me
             .await
             .unwrap();
 
-    let swarm = Arc::new(SwarmBuilder::new(stun, storage).key(key).build().unwrap());
+    // Replication is disabled, so that messages between nodes are predictable.
+    let swarm = Arc::new(
+        SwarmBuilder::new(stun, storage)
+            .key(key)
+            .dht_replica_factor(0)
+            .build()
+            .unwrap(),
+    );
     let dht = swarm.dht();
     let node = swarm.create_message_handler(None, None);
 
@@ -1,13 +1,18 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use async_lock::RwLock as AsyncRwLock;
+use async_lock::Semaphore;
 use async_trait::async_trait;
 use bytes::Bytes;
+use futures::future::select;
 use futures::future::BoxFuture;
+use futures::future::Either;
 use futures::lock::Mutex as FuturesMutex;
 use serde_json;
 use webrtc::api::setting_engine::SettingEngine;
 use webrtc::api::APIBuilder;
+use webrtc::data_channel::data_channel_init::RTCDataChannelInit;
 use webrtc::data_channel::data_channel_message::DataChannelMessage;
 use webrtc::data_channel::data_channel_state::RTCDataChannelState;
 use webrtc::data_channel::RTCDataChannel;
@@ -26,8 +31,12 @@ use crate::channels::Channel as AcChannel;
 use crate::chunk::Chunk;
 use crate::chunk::ChunkList;
 use crate::chunk::ChunkManager;
-use crate::consts::TRANSPORT_MAX_SIZE;
+use crate::consts::TRANSPORT_BUFFERED_AMOUNT_LOW_THRESHOLD;
+use crate::consts::TRANSPORT_BUFFER_TIMEOUT_MS;
+use crate::consts::TRANSPORT_MAX_BUFFERED_AMOUNT;
+use crate::consts::TRANSPORT_MAX_PENDING_MESSAGES;
 use crate::consts::TRANSPORT_MTU;
+use crate::consts::TRANSPORT_SEND_QUEUE_TIMEOUT_MS;
 use crate::dht::Did;
 use crate::ecc::PublicKey;
 use crate::err::Error;
@@ -35,11 +44,14 @@ use crate::err::Result;
 use crate::message::Encoded;
 use crate::message::Encoder;
 use crate::message::MessagePayload;
+use crate::scheduler::Scheduler;
 use crate::session::SessionManager;
+use crate::transports::helper::Notifier;
 use crate::transports::helper::Promise;
 use crate::transports::helper::TricklePayload;
 use crate::types::channel::Channel;
 use crate::types::channel::Event;
+use crate::types::ice_transport::DataChannelKind;
 use crate::types::ice_transport::IceCandidate;
 use crate::types::ice_transport::IceCandidateGathering;
 use crate::types::ice_transport::IceServer;
@@ -47,6 +59,8 @@ use crate::types::ice_transport::IceTransport;
 use crate::types::ice_transport::IceTransportCallback;
 use crate::types::ice_transport::IceTransportInterface;
 use crate::types::ice_transport::IceTrickleScheme;
+use crate::utils::get_epoch_ms;
+use crate::utils::sleep_ms;
 
 type EventSender = <AcChannel<Event> as Channel<Event>>::Sender;
 
@@ -59,13 +73,19 @@ pub struct DefaultTransport {
     connection: Arc<FuturesMutex<Option<Arc<RTCPeerConnection>>>>,
     /// ice candidates will be noticed when connecting
     pending_candidates: Arc<FuturesMutex<Vec<RTCIceCandidate>>>,
-    /// ice protocol message communication channel
-    data_channel: Arc<FuturesMutex<Option<Arc<RTCDataChannel>>>>,
+    /// ice protocol message communication channels, one for each kind
+    data_channels: Arc<FuturesMutex<HashMap<DataChannelKind, Arc<RTCDataChannel>>>>,
     /// channel contains `Sender` and `Receiver` with specific `Event`
     event_sender: EventSender,
     /// node publicKey
     public_key: Arc<AsyncRwLock<Option<PublicKey>>>,
     chunk_list: Arc<FuturesMutex<ChunkList<TRANSPORT_MTU>>>,
+    /// permits of messages being sent, see [TRANSPORT_MAX_PENDING_MESSAGES]
+    send_queue: Arc<Semaphore>,
+    /// notified when the buffered amount of a data channel drops to the low threshold
+    buffer_drained: Notifier,
+    /// schedules outgoing payloads by priority
+    scheduler: Arc<Scheduler>,
 }
 
 impl PartialEq for DefaultTransport {
@@ -96,7 +116,7 @@ impl IceTransport for DefaultTransport {
     }
 
     async fn get_data_channel(&self) -> Option<Arc<RTCDataChannel>> {
-        self.data_channel.lock().await.clone()
+        self.get_data_channel_of(DataChannelKind::Default).await
     }
 
     /// get a RTCConnection offer, use to connect other nodes.
@@ -152,10 +172,13 @@ impl IceTransportInterface<Event, AcChannel<Event>> for DefaultTransport {
             id: uuid::Uuid::new_v4(),
             connection: Arc::new(FuturesMutex::new(None)),
             pending_candidates: Arc::new(FuturesMutex::new(vec![])),
-            data_channel: Arc::new(FuturesMutex::new(None)),
+            data_channels: Arc::new(FuturesMutex::new(HashMap::new())),
             public_key: Arc::new(AsyncRwLock::new(None)),
             event_sender,
             chunk_list: Default::default(),
+            send_queue: Arc::new(Semaphore::new(TRANSPORT_MAX_PENDING_MESSAGES)),
+            buffer_drained: Notifier::default(),
+            scheduler: Default::default(),
         }
     }
 
@@ -188,7 +211,9 @@ impl IceTransportInterface<Event, AcChannel<Event>> for DefaultTransport {
             Err(e) => Err(Error::RTCPeerConnectionCreateFailed(e)),
         }?;
 
-        self.setup_channel("rings").await?;
+        for kind in DataChannelKind::ALL {
+            self.setup_channel(kind).await?;
+        }
         Ok(self)
     }
 
@@ -245,14 +270,26 @@ impl IceTransportInterface<Event, AcChannel<Event>> for DefaultTransport {
     }
 
     async fn send_message(&self, msg: &Bytes) -> Result<()> {
-        if msg.len() > TRANSPORT_MAX_SIZE {
-            return Err(Error::MessageTooLarge);
-        }
+        self.send_message_via(msg, DataChannelKind::Default).await
+    }
 
+    async fn send_message_via(&self, msg: &Bytes, kind: DataChannelKind) -> Result<()> {
+        let _permit = match select(
+            Box::pin(self.send_queue.acquire()),
+            Box::pin(sleep_ms(TRANSPORT_SEND_QUEUE_TIMEOUT_MS)),
+        )
+        .await
+        {
+            Either::Left((permit, _)) => permit,
+            Either::Right(_) => return Err(Error::TransportSendQueueFull),
+        };
         let dc = self
-            .get_data_channel()
+            .get_data_channel_of(kind)
             .await
             .ok_or(Error::RTCDataChannelNotReady)?;
+        if dc.ready_state() != RTCDataChannelState::Open {
+            return Err(Error::RTCDataChannelStateNotOpen);
+        }
 
         let chunks = ChunkList::<TRANSPORT_MTU>::from(msg);
 
@@ -262,6 +299,7 @@ impl IceTransportInterface<Event, AcChannel<Event>> for DefaultTransport {
             tracing::debug!("Transport chunk len: {}", bytes.len());
 
             let size = bytes.len();
+            wait_buffer_drained(&dc, &self.buffer_drained, size).await?;
             match dc.send(&bytes).await {
                 Ok(s) => {
                     if !s == size {
@@ -282,6 +320,30 @@ impl IceTransportInterface<Event, AcChannel<Event>> for DefaultTransport {
     }
 }
 
+/// Wait until the data channel has room to buffer `size` more bytes, so that a fast sender
+/// cannot fill up memory or get the channel closed.
+async fn wait_buffer_drained(dc: &RTCDataChannel, drained: &Notifier, size: usize) -> Result<()> {
+    let deadline = get_epoch_ms() + TRANSPORT_BUFFER_TIMEOUT_MS as u128;
+    loop {
+        let notified = drained.listen();
+        let buffered = dc.buffered_amount().await;
+        if buffered == 0 || buffered + size <= TRANSPORT_MAX_BUFFERED_AMOUNT {
+            return Ok(());
+        }
+        if dc.ready_state() != RTCDataChannelState::Open {
+            return Err(Error::RTCDataChannelStateNotOpen);
+        }
+        let now = get_epoch_ms();
+        if now >= deadline {
+            return Err(Error::RTCDataChannelBufferFull);
+        }
+        let timeout = Box::pin(sleep_ms((deadline - now) as u64));
+        if let Either::Right(_) = select(notified, timeout).await {
+            return Err(Error::RTCDataChannelBufferFull);
+        }
+    }
+}
+
 #[async_trait]
 impl IceTransportCallback for DefaultTransport {
     type OnLocalCandidateHdlrFn =
@@ -509,14 +571,36 @@ impl DefaultTransport {
             .map(|pc| pc.ice_gathering_state())
     }
 
-    pub async fn setup_channel(&mut self, name: &str) -> Result<()> {
+    pub fn scheduler(&self) -> &Scheduler {
+        &self.scheduler
+    }
+
+    pub async fn get_data_channel_of(&self, kind: DataChannelKind) -> Option<Arc<RTCDataChannel>> {
+        self.data_channels.lock().await.get(&kind).cloned()
+    }
+
+    pub async fn setup_channel(&mut self, kind: DataChannelKind) -> Result<()> {
         match self.get_peer_connection().await {
             Some(peer_connection) => {
-                let channel = peer_connection.create_data_channel(name, None).await;
+                let init = RTCDataChannelInit {
+                    ordered: Some(kind.ordered()),
+                    max_retransmits: kind.max_retransmits(),
+                    ..Default::default()
+                };
+                let channel = peer_connection
+                    .create_data_channel(kind.label(), Some(init))
+                    .await;
                 match channel {
                     Ok(ch) => {
-                        let mut channel = self.data_channel.lock().await;
-                        *channel = Some(ch);
+                        let threshold = TRANSPORT_BUFFERED_AMOUNT_LOW_THRESHOLD;
+                        ch.set_buffered_amount_low_threshold(threshold).await;
+                        let drained = self.buffer_drained.clone();
+                        ch.on_buffered_amount_low(Box::new(move || {
+                            let drained = drained.clone();
+                            Box::pin(async move { drained.notify_all() })
+                        }))
+                        .await;
+                        self.data_channels.lock().await.insert(kind, ch);
                         Ok(())
                     }
                     Err(_) => Err(Error::RTCDataChannelNotReady),
@@ -526,8 +610,16 @@ impl DefaultTransport {
         }
     }
 
+    /// Wait until data channels of all kinds are open.
     pub async fn wait_for_data_channel_open(&self) -> Result<()> {
-        match self.get_data_channel().await {
+        for kind in DataChannelKind::ALL {
+            self.wait_for_data_channel_open_of(kind).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn wait_for_data_channel_open_of(&self, kind: DataChannelKind) -> Result<()> {
+        match self.get_data_channel_of(kind).await {
             Some(dc) => {
                 if dc.ready_state() == RTCDataChannelState::Open {
                     Ok(())
@@ -606,6 +698,7 @@ impl DefaultTransport {
 #[cfg(test)]
 pub mod tests {
     use std::str::FromStr;
+    use std::time::Duration;
 
     use async_channel::Receiver;
 
@@ -792,30 +885,74 @@ pub mod tests {
         ));
 
         // Check send long message
-        let long_message1: Bytes = (0..TRANSPORT_MAX_SIZE - 1)
+        let long_message1: Bytes = (0..TRANSPORT_MTU * 16 - 1)
             .map(|_| rand::random::<u8>())
             .collect();
-        assert_eq!(long_message1.len(), TRANSPORT_MAX_SIZE - 1);
+        assert_eq!(long_message1.len(), TRANSPORT_MTU * 16 - 1);
         transport1.send_message(&long_message1).await.unwrap();
         assert!(matches!(
             receiver2.recv().await.unwrap(),
             Event::DataChannelMessage(msg) if msg == long_message1.to_vec()
         ));
-        let long_message2: Bytes = (0..TRANSPORT_MAX_SIZE)
+        let long_message2: Bytes = (0..TRANSPORT_MTU * 16)
             .map(|_| rand::random::<u8>())
             .collect();
-        assert_eq!(long_message2.len(), TRANSPORT_MAX_SIZE);
+        assert_eq!(long_message2.len(), TRANSPORT_MTU * 16);
         transport2.send_message(&long_message2).await.unwrap();
         assert!(matches!(
             receiver1.recv().await.unwrap(),
             Event::DataChannelMessage(msg) if msg == long_message2.to_vec()
         ));
 
-        // Check send over sized message
-        let oversize_message: Bytes = (0..TRANSPORT_MAX_SIZE + 1)
+        // Check send message without size limit
+        let long_message3: Bytes = (0..TRANSPORT_MTU * 16 + 1)
             .map(|_| rand::random::<u8>())
             .collect();
-        assert_eq!(oversize_message.len(), TRANSPORT_MAX_SIZE + 1);
-        assert!(transport1.send_message(&oversize_message).await.is_err());
+        assert_eq!(long_message3.len(), TRANSPORT_MTU * 16 + 1);
+        transport1.send_message(&long_message3).await.unwrap();
+        assert!(matches!(
+            receiver2.recv().await.unwrap(),
+            Event::DataChannelMessage(msg) if msg == long_message3.to_vec()
+        ));
+
+        // Check send message through other kinds of data channels
+        for kind in [DataChannelKind::Bulk, DataChannelKind::Unreliable] {
+            transport1
+                .wait_for_data_channel_open_of(kind)
+                .await
+                .unwrap();
+            transport1
+                .send_message_via(&kind.label().into(), kind)
+                .await
+                .unwrap();
+            assert!(matches!(
+                receiver2.recv().await.unwrap(),
+                Event::DataChannelMessage(msg) if msg == kind.label().as_bytes()
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_queue_full() -> Result<()> {
+        let (transport, _) = prepare_transport().await?;
+        let permits: Vec<_> = (0..TRANSPORT_MAX_PENDING_MESSAGES)
+            .filter_map(|_| transport.send_queue.try_acquire())
+            .collect();
+        assert_eq!(permits.len(), TRANSPORT_MAX_PENDING_MESSAGES);
+
+        // A message waits in queue while all permits are taken.
+        let msg = Bytes::from("hello");
+        let sending = transport.send_message(&msg);
+        tokio::pin!(sending);
+        let waited = tokio::time::timeout(Duration::from_millis(100), &mut sending).await;
+        assert!(waited.is_err());
+
+        // It's sent once a permit is released, and the permit is released after sending,
+        // no matter it's succeeded or not.
+        drop(permits);
+        let sent = sending.await;
+        assert!(!matches!(sent, Err(Error::TransportSendQueueFull)));
+        assert!(transport.send_queue.try_acquire().is_some());
+        Ok(())
     }
 }
@@ -17,12 +17,14 @@ use crate::err::Result;
 use crate::message::Encoded;
 use crate::message::Encoder;
 use crate::message::MessagePayload;
+use crate::scheduler::Scheduler;
 use crate::session::SessionManager;
 use crate::transports::helper::Promise;
 use crate::transports::helper::State;
 use crate::transports::helper::TricklePayload;
 use crate::types::channel::Channel;
 use crate::types::channel::Event;
+use crate::types::ice_transport::DataChannelKind;
 use crate::types::ice_transport::IceServer;
 use crate::types::ice_transport::IceTransportInterface;
 use crate::types::ice_transport::IceTrickleScheme;
@@ -47,6 +49,7 @@ pub struct DummyTransport {
     event_sender: EventSender,
     ice_connection_state: Arc<Mutex<Option<RTCIceConnectionState>>>,
     public_key: Arc<AsyncRwLock<Option<PublicKey>>>,
+    scheduler: Arc<Scheduler>,
 }
 
 impl PartialEq for DummyTransport {
@@ -66,6 +69,7 @@ impl IceTransportInterface<Event, AcChannel<Event>> for DummyTransport {
             event_sender,
             ice_connection_state: Arc::new(Mutex::new(None)),
             public_key: Arc::new(AsyncRwLock::new(None)),
+            scheduler: Default::default(),
         }
     }
 
@@ -132,6 +136,10 @@ impl IceTransportInterface<Event, AcChannel<Event>> for DummyTransport {
             .unwrap();
         Ok(())
     }
+
+    async fn send_message_via(&self, msg: &Bytes, _kind: DataChannelKind) -> Result<()> {
+        self.send_message(msg).await
+    }
 }
 
 #[async_trait]
@@ -212,6 +220,10 @@ impl DummyTransport {
         Ok(())
     }
 
+    pub fn scheduler(&self) -> &Scheduler {
+        &self.scheduler
+    }
+
     pub fn remote_id(&self) -> uuid::Uuid {
         self.remote_id.lock().unwrap().unwrap()
     }
@@ -5,6 +5,7 @@ use std::sync::Mutex;
 use std::task::Context;
 use std::task::Poll;
 
+use futures::channel::oneshot;
 use serde::Deserialize;
 use serde::Serialize;
 
@@ -52,3 +53,28 @@ impl Future for Promise {
         }
     }
 }
+
+/// Notifier wakes up every listener when an event happens, such as the draining of a
+/// data channel buffer.
+#[derive(Default, Clone)]
+pub struct Notifier(Arc<Mutex<Vec<oneshot::Sender<()>>>>);
+
+impl Notifier {
+    /// Listen to the next notification. Listen before checking the condition you are
+    /// waiting for, so that a notification right after the check will not be missed.
+    pub fn listen(&self) -> oneshot::Receiver<()> {
+        let (sender, receiver) = oneshot::channel();
+        let mut listeners = self.0.lock().unwrap();
+        listeners.retain(|s| !s.is_canceled());
+        listeners.push(sender);
+        receiver
+    }
+
+    /// Wake up all current listeners.
+    pub fn notify_all(&self) {
+        let listeners = std::mem::take(&mut *self.0.lock().unwrap());
+        for s in listeners {
+            s.send(()).ok();
+        }
+    }
+}
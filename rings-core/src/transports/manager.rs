@@ -50,6 +50,10 @@ impl TransportManager for Swarm {
         if trans.is_disconnected().await {
             return Err(Error::InvalidTransport);
         }
+        if self.is_blocked(did) {
+            trans.close().await?;
+            return Err(Error::PeerBlocked(did));
+        }
 
         tracing::info!("register transport {:?}", trans.id.clone());
         #[cfg(test)]
@@ -1,9 +1,13 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::sync::RwLock;
 
+use async_lock::Semaphore;
 use async_trait::async_trait;
 use bytes::Bytes;
+use futures::future::select;
+use futures::future::Either;
 use js_sys::Uint8Array;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
@@ -14,6 +18,7 @@ use web_sys::MessageEvent;
 use web_sys::RtcConfiguration;
 use web_sys::RtcDataChannel;
 use web_sys::RtcDataChannelEvent;
+use web_sys::RtcDataChannelInit;
 use web_sys::RtcDataChannelState;
 use web_sys::RtcIceCandidate;
 use web_sys::RtcIceCandidateInit;
@@ -30,8 +35,12 @@ use crate::channels::Channel as CbChannel;
 use crate::chunk::Chunk;
 use crate::chunk::ChunkList;
 use crate::chunk::ChunkManager;
-use crate::consts::TRANSPORT_MAX_SIZE;
+use crate::consts::TRANSPORT_BUFFERED_AMOUNT_LOW_THRESHOLD;
+use crate::consts::TRANSPORT_BUFFER_TIMEOUT_MS;
+use crate::consts::TRANSPORT_MAX_BUFFERED_AMOUNT;
+use crate::consts::TRANSPORT_MAX_PENDING_MESSAGES;
 use crate::consts::TRANSPORT_MTU;
+use crate::consts::TRANSPORT_SEND_QUEUE_TIMEOUT_MS;
 use crate::dht::Did;
 use crate::ecc::PublicKey;
 use crate::err::Error;
@@ -39,11 +48,14 @@ use crate::err::Result;
 use crate::message::Encoded;
 use crate::message::Encoder;
 use crate::message::MessagePayload;
+use crate::scheduler::Scheduler;
 use crate::session::SessionManager;
+use crate::transports::helper::Notifier;
 use crate::transports::helper::Promise;
 use crate::transports::helper::TricklePayload;
 use crate::types::channel::Channel;
 use crate::types::channel::Event;
+use crate::types::ice_transport::DataChannelKind;
 use crate::types::ice_transport::IceCandidate;
 use crate::types::ice_transport::IceCandidateGathering;
 use crate::types::ice_transport::IceServer;
@@ -51,7 +63,9 @@ use crate::types::ice_transport::IceTransport;
 use crate::types::ice_transport::IceTransportCallback;
 use crate::types::ice_transport::IceTransportInterface;
 use crate::types::ice_transport::IceTrickleScheme;
+use crate::utils::get_epoch_ms;
 use crate::utils::js_value;
+use crate::utils::sleep_ms;
 
 type EventSender = <CbChannel<Event> as Channel<Event>>::Sender;
 
@@ -61,10 +75,13 @@ pub struct WasmTransport {
     pub id: uuid::Uuid,
     connection: Option<Arc<RtcPeerConnection>>,
     pending_candidates: Arc<Mutex<Vec<RtcIceCandidate>>>,
-    channel: Option<Arc<RtcDataChannel>>,
+    channels: HashMap<DataChannelKind, Arc<RtcDataChannel>>,
     event_sender: EventSender,
     public_key: Arc<RwLock<Option<PublicKey>>>,
     chunk_list: Arc<Mutex<ChunkList<TRANSPORT_MTU>>>,
+    send_queue: Arc<Semaphore>,
+    buffer_drained: Notifier,
+    scheduler: Arc<Scheduler>,
 }
 
 impl PartialEq for WasmTransport {
@@ -146,7 +163,7 @@ impl IceTransport for WasmTransport {
     }
 
     async fn get_data_channel(&self) -> Option<Arc<RtcDataChannel>> {
-        self.channel.as_ref().map(Arc::clone)
+        self.get_data_channel_of(DataChannelKind::Default)
     }
 }
 
@@ -159,10 +176,13 @@ impl IceTransportInterface<Event, CbChannel<Event>> for WasmTransport {
             id: uuid::Uuid::new_v4(),
             connection: None,
             pending_candidates: Arc::new(Mutex::new(vec![])),
-            channel: None,
+            channels: HashMap::new(),
             public_key: Arc::new(RwLock::new(None)),
             event_sender,
             chunk_list: Default::default(),
+            send_queue: Arc::new(Semaphore::new(TRANSPORT_MAX_PENDING_MESSAGES)),
+            buffer_drained: Notifier::default(),
+            scheduler: Default::default(),
         }
     }
 
@@ -193,7 +213,9 @@ impl IceTransportInterface<Event, CbChannel<Event>> for WasmTransport {
             .ok()
             .as_ref()
             .map(|c| Arc::new(c.to_owned()));
-        self.setup_channel("rings").await;
+        for kind in DataChannelKind::ALL {
+            self.setup_channel(kind).await;
+        }
         return Ok(self);
     }
 
@@ -258,19 +280,31 @@ impl IceTransportInterface<Event, CbChannel<Event>> for WasmTransport {
     }
 
     async fn send_message(&self, msg: &Bytes) -> Result<()> {
-        if msg.len() > TRANSPORT_MAX_SIZE {
-            return Err(Error::MessageTooLarge);
-        }
+        self.send_message_via(msg, DataChannelKind::Default).await
+    }
 
+    async fn send_message_via(&self, msg: &Bytes, kind: DataChannelKind) -> Result<()> {
+        let _permit = match select(
+            Box::pin(self.send_queue.acquire()),
+            Box::pin(sleep_ms(TRANSPORT_SEND_QUEUE_TIMEOUT_MS)),
+        )
+        .await
+        {
+            Either::Left((permit, _)) => permit,
+            Either::Right(_) => return Err(Error::TransportSendQueueFull),
+        };
         let dc = self
-            .get_data_channel()
-            .await
+            .get_data_channel_of(kind)
             .ok_or(Error::RTCDataChannelNotReady)?;
+        if dc.ready_state() != RtcDataChannelState::Open {
+            return Err(Error::RTCDataChannelStateNotOpen);
+        }
 
         let chunks = ChunkList::<TRANSPORT_MTU>::from(msg);
 
         for c in chunks {
             let bytes = c.to_bincode()?;
+            wait_buffer_drained(&dc, &self.buffer_drained, bytes.len()).await?;
             dc.send_with_u8_array(&bytes)
                 .map_err(|e| Error::RTCDataChannelSendTextFailed(format!("{:?}", e)))?
         }
@@ -279,11 +313,54 @@ impl IceTransportInterface<Event, CbChannel<Event>> for WasmTransport {
     }
 }
 
+/// Wait until the data channel has room to buffer `size` more bytes.
+async fn wait_buffer_drained(dc: &RtcDataChannel, drained: &Notifier, size: usize) -> Result<()> {
+    let deadline = get_epoch_ms() + TRANSPORT_BUFFER_TIMEOUT_MS as u128;
+    loop {
+        let notified = drained.listen();
+        let buffered = dc.buffered_amount() as usize;
+        if buffered == 0 || buffered + size <= TRANSPORT_MAX_BUFFERED_AMOUNT {
+            return Ok(());
+        }
+        if dc.ready_state() != RtcDataChannelState::Open {
+            return Err(Error::RTCDataChannelStateNotOpen);
+        }
+        let now = get_epoch_ms();
+        if now >= deadline {
+            return Err(Error::RTCDataChannelBufferFull);
+        }
+        let timeout = Box::pin(sleep_ms((deadline - now) as u64));
+        if let Either::Right(_) = select(notified, timeout).await {
+            return Err(Error::RTCDataChannelBufferFull);
+        }
+    }
+}
+
 impl WasmTransport {
-    pub async fn setup_channel(&mut self, name: &str) {
+    pub fn scheduler(&self) -> &Scheduler {
+        &self.scheduler
+    }
+
+    pub fn get_data_channel_of(&self, kind: DataChannelKind) -> Option<Arc<RtcDataChannel>> {
+        self.channels.get(&kind).map(Arc::clone)
+    }
+
+    pub async fn setup_channel(&mut self, kind: DataChannelKind) {
         if let Some(conn) = &self.connection {
-            let channel = conn.create_data_channel(name);
-            self.channel = Some(Arc::new(channel));
+            let mut init = RtcDataChannelInit::new();
+            init.ordered(kind.ordered());
+            if let Some(max_retransmits) = kind.max_retransmits() {
+                init.max_retransmits(max_retransmits);
+            }
+            let channel = conn.create_data_channel_with_data_channel_dict(kind.label(), &init);
+            let threshold = TRANSPORT_BUFFERED_AMOUNT_LOW_THRESHOLD as u32;
+            channel.set_buffered_amount_low_threshold(threshold);
+            let drained = self.buffer_drained.clone();
+            let callback =
+                Closure::wrap(Box::new(move || drained.notify_all()) as Box<dyn FnMut()>);
+            channel.set_onbufferedamountlow(Some(callback.as_ref().unchecked_ref()));
+            callback.forget();
+            self.channels.insert(kind, Arc::new(channel));
         }
     }
 }
@@ -586,9 +663,16 @@ impl IceTrickleScheme for WasmTransport {
 }
 
 impl WasmTransport {
+    /// Wait until data channels of all kinds are open.
     pub async fn wait_for_data_channel_open(&self) -> Result<()> {
-        let dc = self.get_data_channel().await;
-        match dc {
+        for kind in DataChannelKind::ALL {
+            self.wait_for_data_channel_open_of(kind).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn wait_for_data_channel_open_of(&self, kind: DataChannelKind) -> Result<()> {
+        match self.get_data_channel_of(kind) {
             Some(dc) => {
                 if dc.ready_state() == RtcDataChannelState::Open {
                     return Ok(());
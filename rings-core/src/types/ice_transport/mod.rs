@@ -32,6 +32,44 @@ pub struct IceCandidate {
     pub username_fragment: Option<String>,
 }
 
+/// Kinds of data channels opened by each transport, a message is sent through one of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DataChannelKind {
+    /// Reliable and ordered, for most messages.
+    Default,
+    /// Reliable and ordered, dedicated to bulk data so that it won't delay other messages.
+    Bulk,
+    /// Unordered and unreliable, for data can be lost, such as telemetry.
+    Unreliable,
+}
+
+impl DataChannelKind {
+    /// All kinds of data channels.
+    pub const ALL: [Self; 3] = [Self::Default, Self::Bulk, Self::Unreliable];
+
+    /// Label of the data channel.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Default => "rings",
+            Self::Bulk => "rings-bulk",
+            Self::Unreliable => "rings-unreliable",
+        }
+    }
+
+    /// If messages are delivered in order.
+    pub fn ordered(&self) -> bool {
+        !matches!(self, Self::Unreliable)
+    }
+
+    /// Max times of retransmitting a message, `None` means it's reliable.
+    pub fn max_retransmits(&self) -> Option<u16> {
+        match self {
+            Self::Unreliable => Some(0),
+            _ => None,
+        }
+    }
+}
+
 /// A useful trait implement by IceTransport that we use.
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
 #[cfg_attr(not(feature = "wasm"), async_trait)]
@@ -63,6 +101,8 @@ pub trait IceTransportInterface<E: Send, Ch: Channel<E>> {
     async fn is_disconnected(&self) -> bool;
     async fn pubkey(&self) -> PublicKey;
     async fn send_message(&self, msg: &Bytes) -> Result<()>;
+    /// Send message through the data channel of `kind`, fail if that's not open.
+    async fn send_message_via(&self, msg: &Bytes, kind: DataChannelKind) -> Result<()>;
 }
 
 /// A IceTransportCallback use to register a callback..
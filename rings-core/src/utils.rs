@@ -6,6 +6,18 @@ pub fn get_epoch_ms() -> u128 {
     Utc::now().timestamp_millis() as u128
 }
 
+/// Sleep for given milliseconds.
+#[cfg(not(feature = "wasm"))]
+pub async fn sleep_ms(millis: u64) {
+    futures_timer::Delay::new(std::time::Duration::from_millis(millis)).await
+}
+
+/// Sleep for given milliseconds.
+#[cfg(feature = "wasm")]
+pub async fn sleep_ms(millis: u64) {
+    js_utils::window_sleep(millis as i32).await.ok();
+}
+
 #[cfg(feature = "wasm")]
 /// Toolset for wasm
 pub mod js_value {
@@ -4,7 +4,6 @@ use std::str::FromStr;
 use std::sync::Arc;
 
 use bytes::Bytes;
-use rings_core::chunk::ChunkList;
 use serde::Deserialize;
 use serde::Serialize;
 
@@ -13,7 +12,6 @@ use super::backend::types::HttpRequest;
 use super::backend::types::HttpResponse;
 use super::backend::MessageEndpoint;
 use super::backend::MessageType;
-use crate::consts::BACKEND_MTU;
 use crate::error::Error;
 use crate::error::Result;
 use crate::prelude::*;
@@ -143,14 +141,6 @@ impl MessageEndpoint for HttpServer {
         .into();
         tracing::debug!("resp_bytes gzip_data len: {}", resp_bytes.len());
 
-        let chunks = ChunkList::<BACKEND_MTU>::from(&resp_bytes);
-        for c in chunks {
-            tracing::debug!("Chunk data len: {}", c.data.len());
-            let bytes = c.to_bincode().map_err(|_| Error::SerializeError)?;
-            tracing::debug!("Chunk len: {}", bytes.len());
-            super::utils::send_chunk_report_message(handler, ctx, relay, bytes.to_vec().as_slice())
-                .await?;
-        }
-        Ok(())
+        super::utils::send_report_message(handler, ctx, relay, &resp_bytes).await
     }
 }
@@ -1,4 +1,3 @@
-#![allow(clippy::ptr_offset_with_cast)]
 #![warn(missing_docs)]
 //! An Backend HTTP service handle custom message from `MessageHandler` as CallbackFn.
 pub mod http_server;
@@ -7,7 +6,6 @@ pub mod utils;
 
 use std::sync::Arc;
 
-use arrayref::array_refs;
 use async_trait::async_trait;
 use serde::Deserialize;
 use serde::Serialize;
@@ -78,14 +76,7 @@ impl MessageCallback for Backend {
         }
         let msg = msg.unwrap().0;
 
-        let (left, msg) = array_refs![&msg, 4; ..;];
-        let (&[flag], _) = array_refs![left, 1, 3];
-        if flag != 0 {
-            tracing::warn!("invalid custom_message flag");
-            return;
-        }
-
-        let msg = BackendMessage::try_from(msg);
+        let msg = BackendMessage::try_from(msg.as_slice());
         if let Err(e) = msg {
             tracing::error!("decode custom_message failed: {}", e);
             return;
@@ -120,5 +111,20 @@ impl MessageCallback for Backend {
         }
     }
 
-    async fn builtin_message(&self, _handler: &MessageHandler, _ctx: &MessagePayload<Message>) {}
+    /// `builtin_message` in Backend only handle the `Message::TopicMessages` pushed
+    /// to current node, and broadcast it as a [MessageType::TopicMessage].
+    async fn builtin_message(&self, _handler: &MessageHandler, ctx: &MessagePayload<Message>) {
+        if let Message::TopicMessages(ref msg) = ctx.data {
+            let msg = match BackendMessage::try_from((MessageType::TopicMessage, msg)) {
+                Ok(msg) => msg,
+                Err(e) => {
+                    tracing::error!("encode topic_message failed: {}", e);
+                    return;
+                }
+            };
+            if let Err(e) = self.sender.send(msg) {
+                tracing::error!("broadcast backend_message failed, {}", e);
+            }
+        }
+    }
 }
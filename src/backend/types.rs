@@ -24,6 +24,10 @@ pub enum MessageType {
     HttpRequest,
     /// http response
     HttpResponse,
+    /// data pushed from a subscribed topic
+    TopicMessage,
+    /// streamed transfer of a file
+    FileTransfer,
 }
 
 impl From<&[u8; 2]> for MessageType {
@@ -39,6 +43,8 @@ impl From<u16> for MessageType {
             2 => MessageType::SimpleText,
             3 => MessageType::HttpRequest,
             4 => MessageType::HttpResponse,
+            5 => MessageType::TopicMessage,
+            6 => MessageType::FileTransfer,
             _ => MessageType::Unknown,
         }
     }
@@ -52,6 +58,8 @@ impl From<MessageType> for u16 {
             MessageType::SimpleText => 2,
             MessageType::HttpRequest => 3,
             MessageType::HttpResponse => 4,
+            MessageType::TopicMessage => 5,
+            MessageType::FileTransfer => 6,
         }
     }
 }
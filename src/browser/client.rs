@@ -1,11 +1,9 @@
-#![allow(non_snake_case, non_upper_case_globals, clippy::ptr_offset_with_cast)]
+#![allow(non_snake_case, non_upper_case_globals)]
 use std::convert::TryFrom;
 use std::str::FromStr;
 use std::sync::Arc;
-use std::sync::Mutex;
 
 use anyhow::anyhow;
-use arrayref::array_refs;
 use bytes::Bytes;
 use serde::Deserialize;
 use serde::Serialize;
@@ -13,10 +11,6 @@ use serde::Serialize;
 use crate::backend::types::BackendMessage;
 use crate::backend::types::HttpResponse;
 use crate::backend::types::MessageType;
-use crate::consts::BACKEND_MTU;
-use crate::prelude::chunk::Chunk;
-use crate::prelude::chunk::ChunkList;
-use crate::prelude::chunk::ChunkManager;
 use crate::prelude::js_sys;
 use crate::prelude::message;
 use crate::prelude::rings_core::async_trait;
@@ -36,7 +30,7 @@ use crate::prelude::rings_core::prelude::web3::ethabi::Token;
 use crate::prelude::rings_core::transports::manager::TransportManager;
 use crate::prelude::rings_core::transports::Transport;
 use crate::prelude::rings_core::types::ice_transport::IceTransportInterface;
-use crate::prelude::rings_core::utils::js_utils;
+use crate::prelude::rings_core::utils::get_epoch_ms;
 use crate::prelude::rings_core::utils::js_value;
 use crate::prelude::wasm_bindgen;
 use crate::prelude::wasm_bindgen::prelude::*;
@@ -147,6 +141,18 @@ impl UnsignedInfo {
         let s = self.inner.auth().map_err(JsError::from)?;
         Ok(s)
     }
+
+    /// Set id of the network to join, nodes of other networks are rejected.
+    #[wasm_bindgen(setter)]
+    pub fn set_network_id(&mut self, network_id: u32) {
+        self.inner = self.inner.clone().with_network_id(network_id);
+    }
+
+    /// Set difficulty of admission for Dids of the network, 0 means disabled.
+    #[wasm_bindgen(setter)]
+    pub fn set_admission_difficulty(&mut self, difficulty: u8) {
+        self.inner = self.inner.clone().with_admission_difficulty(difficulty);
+    }
 }
 
 /// rings-node browser client
@@ -632,11 +638,10 @@ impl Client {
         future_to_promise(async move {
             let rid = VirtualNode::gen_did(&name).map_err(JsError::from)?;
 
-            p.storage_fetch(rid).await.map_err(JsError::from)?;
-            js_utils::window_sleep(500).await?;
-            let result = p.storage_check_cache(rid).await;
+            let result = p.storage_fetch_and_wait(rid).await.map_err(JsError::from)?;
 
             if let Some(vnode) = result {
+                let vnode = vnode.prune_expired(get_epoch_ms());
                 let dids = vnode
                     .data
                     .iter()
@@ -657,7 +662,6 @@ pub struct MessageCallbackInstance {
     custom_message: Arc<js_sys::Function>,
     http_response_message: Arc<js_sys::Function>,
     builtin_message: Arc<js_sys::Function>,
-    chunk_list: Arc<Mutex<ChunkList<BACKEND_MTU>>>,
 }
 
 #[wasm_bindgen]
@@ -672,7 +676,6 @@ impl MessageCallbackInstance {
             custom_message: Arc::new(custom_message.clone()),
             http_response_message: Arc::new(http_response_message.clone()),
             builtin_message: Arc::new(builtin_message.clone()),
-            chunk_list: Default::default(),
         })
     }
 }
@@ -756,34 +759,6 @@ impl MessageCallbackInstance {
         };
         Ok(())
     }
-
-    fn handle_chunk_data(&self, data: &[u8]) -> anyhow::Result<Option<Bytes>> {
-        let c_lock = self.chunk_list.try_lock();
-        if c_lock.is_err() {
-            return Err(anyhow!("lock chunklist failed"));
-        }
-        let mut chunk_list = c_lock.unwrap();
-
-        let chunk_item =
-            Chunk::from_bincode(data).map_err(|_| anyhow!("BincodeDeserialize failed"))?;
-
-        log::debug!(
-            "before handle chunk, chunk list len: {}",
-            chunk_list.as_vec().len()
-        );
-        log::debug!(
-            "chunk id: {}, total size: {}",
-            chunk_item.meta.id,
-            chunk_item.chunk[1]
-        );
-        let data = chunk_list.handle(chunk_item);
-        log::debug!(
-            "after handle chunk, chunk list len: {}",
-            chunk_list.as_vec().len()
-        );
-
-        Ok(data)
-    }
 }
 
 #[async_trait(?Send)]
@@ -801,33 +776,7 @@ impl MessageCallback for MessageCallbackInstance {
         } else {
             r.unwrap()
         };
-        if msg.0.len() < 2 {
-            return;
-        }
-
-        let (left, right) = array_refs![&msg.0, 4; ..;];
-        let (&[tag], _) = array_refs![left, 1, 3];
-
-        let data = if tag == 1 {
-            let data = self.handle_chunk_data(right);
-            if let Err(e) = data {
-                log::error!("handle chunk data failed: {}", e);
-                return;
-            }
-            let data = data.unwrap();
-            log::debug!("chunk message of {:?} received", relay.tx_id);
-            if let Some(data) = data {
-                data
-            } else {
-                log::info!("chunk message of {:?} not complete", relay.tx_id);
-                return;
-            }
-        } else if tag == 0 {
-            Bytes::from(right.to_vec())
-        } else {
-            log::error!("invalid message tag: {}", tag);
-            return;
-        };
+        let data = Bytes::from(msg.0);
         if let Err(e) = self.handle_message_data(relay, &data).await {
             log::error!("handle http_server_msg failed, {}", e);
         }
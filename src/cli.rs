@@ -21,9 +21,6 @@ use std::time::Duration;
 
 use async_stream::stream;
 use bytes::Bytes;
-use futures::pin_mut;
-use futures::select;
-use futures::FutureExt;
 use futures::Stream;
 use futures_timer::Delay;
 use http::header;
@@ -39,6 +36,7 @@ use crate::jsonrpc::response::Peer;
 use crate::jsonrpc::response::TransportAndIce;
 use crate::jsonrpc_client::SimpleClient;
 use crate::prelude::reqwest;
+use crate::prelude::vnode::VirtualNode;
 use crate::seed::Seed;
 use crate::util::loader::ResourceLoader;
 
@@ -224,6 +222,42 @@ impl Client {
         ClientOutput::ok("Done.".into(), ())
     }
 
+    /// Blocks the peer with the specified DID, its connection will be closed.
+    pub async fn block_peer(&mut self, did: &str) -> Output<()> {
+        self.client
+            .call_method(Method::BlockPeer.as_str(), Params::Array(vec![json!(did)]))
+            .await
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+
+        ClientOutput::ok("Done.".into(), ())
+    }
+
+    /// Unblocks the peer with the specified DID.
+    pub async fn unblock_peer(&mut self, did: &str) -> Output<()> {
+        self.client
+            .call_method(
+                Method::UnblockPeer.as_str(),
+                Params::Array(vec![json!(did)]),
+            )
+            .await
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+
+        ClientOutput::ok("Done.".into(), ())
+    }
+
+    /// Lists all blocked peers.
+    pub async fn list_blocked_peers(&mut self) -> Output<()> {
+        let resp = self
+            .client
+            .call_method(Method::ListBlockedPeers.as_str(), Params::Array(vec![]))
+            .await
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+        let dids: Vec<String> =
+            serde_json::from_value(resp).map_err(|e| anyhow::anyhow!("{}", e))?;
+
+        ClientOutput::ok(dids.join("\n"), ())
+    }
+
     /// Lists all pending transports and their status.
     pub async fn list_pendings(&self) -> Output<()> {
         let resp = self
@@ -320,12 +354,14 @@ impl Client {
     }
 
     /// Registers a new service with the given name.
-    pub async fn register_service(&self, name: &str) -> Output<()> {
+    /// With `ttl_ms`, the registration expires unless it's renewed in time.
+    pub async fn register_service(&self, name: &str, ttl_ms: Option<u64>) -> Output<()> {
+        let mut params = vec![json!(name)];
+        if let Some(ttl_ms) = ttl_ms {
+            params.push(json!(ttl_ms));
+        }
         self.client
-            .call_method(
-                Method::RegisterService.as_str(),
-                Params::Array(vec![json!(name)]),
-            )
+            .call_method(Method::RegisterService.as_str(), Params::Array(params))
             .await
             .map_err(|e| anyhow::anyhow!("{}", e))?;
         ClientOutput::ok("Done.".into(), ())
@@ -348,6 +384,61 @@ impl Client {
         ClientOutput::ok(dids.join("\n"), ())
     }
 
+    /// Joins the subring with the given name.
+    pub async fn join_subring(&self, name: &str) -> Output<()> {
+        self.client
+            .call_method(
+                Method::JoinSubring.as_str(),
+                Params::Array(vec![json!(name)]),
+            )
+            .await
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+        ClientOutput::ok("Done.".into(), ())
+    }
+
+    /// Leaves the subring with the given name.
+    /// With `member`, removes that member instead, which is only allowed for the creator.
+    pub async fn leave_subring(&self, name: &str, member: Option<&str>) -> Output<()> {
+        let mut params = vec![json!(name)];
+        if let Some(member) = member {
+            params.push(json!(member));
+        }
+        self.client
+            .call_method(Method::LeaveSubring.as_str(), Params::Array(params))
+            .await
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+        ClientOutput::ok("Done.".into(), ())
+    }
+
+    /// Lists the DIDs of members of the subring with the given name.
+    pub async fn list_subring_members(&self, name: &str) -> Output<()> {
+        let resp = self
+            .client
+            .call_method(
+                Method::ListSubringMembers.as_str(),
+                Params::Array(vec![json!(name)]),
+            )
+            .await
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+
+        let dids: Vec<String> =
+            serde_json::from_value(resp).map_err(|e| anyhow::anyhow!("{}", e))?;
+
+        ClientOutput::ok(dids.join("\n"), ())
+    }
+
+    /// Sends a simple text message to all other members of the subring with the given name.
+    pub async fn broadcast_to_subring(&self, name: &str, text: &str) -> Output<()> {
+        self.client
+            .call_method(
+                Method::BroadcastToSubring.as_str(),
+                Params::Array(vec![json!(name), json!(text)]),
+            )
+            .await
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+        ClientOutput::ok("Done.".into(), ())
+    }
+
     /// Publishes a message to the specified topic.
     pub async fn publish_message_to_topic(&self, topic: &str, data: &str) -> Output<()> {
         self.client
@@ -360,7 +451,22 @@ impl Client {
         ClientOutput::ok("Done.".into(), ())
     }
 
+    /// Fetches virtual nodes in the clockwise range from `start` to `end`, at most `limit` of them.
+    pub async fn fetch_vnode_range(&self, start: &str, end: &str, limit: usize) -> Output<()> {
+        let resp = self
+            .client
+            .call_method(
+                Method::FetchVNodeRange.as_str(),
+                Params::Array(vec![json!(start), json!(end), json!(limit)]),
+            )
+            .await
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+        ClientOutput::ok(serde_json::to_string_pretty(&resp)?, ())
+    }
+
     /// Subscribes to the specified topic and returns a stream of messages published to the topic.
+    /// Messages published before are fetched once, then new messages are pushed by the node
+    /// storing the topic, and received by polling messages of the topic on the local node.
     pub async fn subscribe_topic<'a, 'b>(
         &'a self,
         topic: String,
@@ -368,40 +474,63 @@ impl Client {
     where
         'a: 'b,
     {
-        let mut index = 0;
-
         stream! {
-        loop {
-            let timeout = Delay::new(Duration::from_secs(5)).fuse();
-            pin_mut!(timeout);
-
-            select! {
-                _ = timeout => {
-                    let result = self
-                        .client
-                        .call_method(
-                            Method::FetchMessagesOfTopic.as_str(),
-                            Params::Array(vec![json!(topic), json!(index)]),
-                        )
-                        .await;
-
-                    if let Err(e) = result {
-                        tracing::error!("Failed to fetch messages of topic: {}, {}", topic, e);
-                        continue;
-                    }
-                    let resp = result.unwrap();
+            if let Err(e) = VirtualNode::gen_did(&topic) {
+                tracing::error!("Invalid topic: {}, {}", topic, e);
+                return;
+            }
 
-                    let messages = serde_json::from_value(resp);
-                    if let Err(e) = messages {
-                        tracing::error!("Failed to parse messages of topic: {}, {}", topic, e);
-                        continue;
-                    }
-                    let messages: Vec<String> = messages.unwrap();
+            // Subscribe before fetching, so that no message will be missed.
+            if let Err(e) = self
+                .client
+                .call_method(
+                    Method::SubscribeTopic.as_str(),
+                    Params::Array(vec![json!(topic)]),
+                )
+                .await
+            {
+                tracing::error!("Failed to subscribe topic: {}, {}", topic, e);
+                return;
+            }
 
-                    for msg in messages.iter().cloned() {
+            match self
+                .client
+                .call_method(
+                    Method::FetchMessagesOfTopic.as_str(),
+                    Params::Array(vec![json!(topic), json!(0)]),
+                )
+                .await
+                .map(serde_json::from_value::<Vec<String>>)
+            {
+                Ok(Ok(messages)) => {
+                    for msg in messages {
                         yield msg
                     }
-                    index += messages.len();
+                }
+                Ok(Err(e)) => tracing::error!("Failed to parse messages of topic: {}, {}", topic, e),
+                Err(e) => tracing::error!("Failed to fetch messages of topic: {}, {}", topic, e),
+            }
+
+            loop {
+                let result = self
+                    .client
+                    .call_method(
+                        Method::PollTopicMessage.as_str(),
+                        Params::Array(vec![json!(topic), json!(true)]),
+                    )
+                    .await
+                    .map(|resp| serde_json::from_value::<Vec<String>>(resp["messages"].clone()));
+
+                match result {
+                    Ok(Ok(messages)) => {
+                        for msg in messages {
+                            yield msg
+                        }
+                    }
+                    Ok(Err(e)) => tracing::error!("Failed to parse messages of topic: {}, {}", topic, e),
+                    Err(e) => {
+                        tracing::error!("Failed to poll messages of topic: {}, {}", topic, e);
+                        Delay::new(Duration::from_secs(1)).await;
                     }
                 }
             }
@@ -9,6 +9,7 @@ use serde::Serialize;
 use crate::backend::service::http_server::HiddenServerConfig;
 use crate::error::Error;
 use crate::error::Result;
+use crate::prelude::rings_core::consts;
 use crate::prelude::rings_core::ecc::SecretKey;
 
 lazy_static::lazy_static! {
@@ -20,6 +21,14 @@ lazy_static::lazy_static! {
     path: get_storage_location(".rings", "measure"),
     capacity: DEFAULT_STORAGE_CAPACITY,
   };
+  static ref DEFAULT_BLOCKLIST_STORAGE_CONFIG: StorageConfig = StorageConfig {
+    path: get_storage_location(".rings", "blocklist"),
+    capacity: DEFAULT_STORAGE_CAPACITY,
+  };
+  static ref DEFAULT_SUBRING_STORAGE_CONFIG: StorageConfig = StorageConfig {
+    path: get_storage_location(".rings", "subring"),
+    capacity: DEFAULT_STORAGE_CAPACITY,
+  };
 }
 
 pub const DEFAULT_BIND_ADDRESS: &str = "127.0.0.1:50000";
@@ -27,6 +36,19 @@ pub const DEFAULT_ENDPOINT_URL: &str = "http://127.0.0.1:50000";
 pub const DEFAULT_ICE_SERVERS: &str = "stun://stun.l.google.com:19302";
 pub const DEFAULT_STABILIZE_TIMEOUT: usize = 20;
 pub const DEFAULT_STORAGE_CAPACITY: usize = 200000000;
+pub const DEFAULT_REPLICA_FACTOR: u8 = consts::DEFAULT_REPLICA_FACTOR;
+
+fn default_replica_factor() -> u8 {
+    DEFAULT_REPLICA_FACTOR
+}
+
+fn default_blocklist_storage() -> StorageConfig {
+    DEFAULT_BLOCKLIST_STORAGE_CONFIG.clone()
+}
+
+fn default_subring_storage() -> StorageConfig {
+    DEFAULT_SUBRING_STORAGE_CONFIG.clone()
+}
 
 pub fn get_storage_location<P>(prefix: P, path: P) -> String
 where P: AsRef<std::path::Path> {
@@ -46,10 +68,25 @@ pub struct Config {
     pub ecdsa_key: SecretKey,
     pub ice_servers: String,
     pub stabilize_timeout: usize,
+    #[serde(default = "default_replica_factor")]
+    pub replica_factor: u8,
+    /// Difficulty of admission for Dids of the network, 0 means disabled.
+    #[serde(default)]
+    pub admission_difficulty: u8,
+    /// Id of the network, nodes of different networks reject each other.
+    #[serde(default)]
+    pub network_id: u32,
+    /// Look up storing nodes of data iteratively instead of recursively.
+    #[serde(default)]
+    pub iterative_lookup: bool,
     pub external_ip: Option<String>,
     pub backend: Vec<HiddenServerConfig>,
     pub data_storage: StorageConfig,
     pub measure_storage: StorageConfig,
+    #[serde(default = "default_blocklist_storage")]
+    pub blocklist_storage: StorageConfig,
+    #[serde(default = "default_subring_storage")]
+    pub subring_storage: StorageConfig,
 }
 
 impl Config {
@@ -60,6 +97,10 @@ impl Config {
             ecdsa_key: key,
             ice_servers: DEFAULT_ICE_SERVERS.to_string(),
             stabilize_timeout: DEFAULT_STABILIZE_TIMEOUT,
+            replica_factor: DEFAULT_REPLICA_FACTOR,
+            admission_difficulty: 0,
+            network_id: 0,
+            iterative_lookup: false,
             external_ip: None,
             backend: vec![HiddenServerConfig {
                 name: "ipfs".to_string(),
@@ -67,6 +108,8 @@ impl Config {
             }],
             data_storage: DEFAULT_DATA_STORAGE_CONFIG.clone(),
             measure_storage: DEFAULT_MEASURE_STORAGE_CONFIG.clone(),
+            blocklist_storage: DEFAULT_BLOCKLIST_STORAGE_CONFIG.clone(),
+            subring_storage: DEFAULT_SUBRING_STORAGE_CONFIG.clone(),
         }
     }
 
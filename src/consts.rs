@@ -1,3 +0,0 @@
-use crate::prelude::rings_core::consts::*;
-
-pub const BACKEND_MTU: usize = TRANSPORT_MAX_SIZE - TRANSPORT_MTU;
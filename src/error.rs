@@ -83,6 +83,12 @@ pub enum Error {
     CreateFileError(String),
     #[error("Open File Error: {0}")]
     OpenFileError(String),
+    #[error("subring action error: {0}")]
+    SubringError(rings_core::err::Error),
+    #[error("topic subscription error: {0}")]
+    TopicSubscriptionError(rings_core::err::Error),
+    #[error("file transfer error: {0}")]
+    FileTransferError(String),
 }
 
 impl Error {
@@ -126,6 +132,9 @@ impl Error {
             Error::Swarm(_) => 35,
             Error::CreateFileError(_) => 36,
             Error::OpenFileError(_) => 37,
+            Error::SubringError(_) => 38,
+            Error::TopicSubscriptionError(_) => 39,
+            Error::FileTransferError(_) => 40,
         };
         -32000 - code
     }
@@ -0,0 +1,550 @@
+#![warn(missing_docs)]
+//! Streamed transfer of large files between nodes.
+//!
+//! The sender offers a file with its size and hash, and the receiver accepts it with the offset
+//! to resume from, which is the length of the part it has received in an interrupted transfer.
+//! The id of a transfer is derived from the name and hash of the file, so that a transfer
+//! resumed by the same sender is recognized.
+//! Then the sender reads the file chunk by chunk from that offset, and sends a window of chunks
+//! at a time, each of them is acknowledged by the receiver. When all chunks are sent, the
+//! receiver checks the hash of the whole file before moving it into place.
+//!
+//! All messages are carried as [MessageType::FileTransfer] in [BackendMessage], so a file is
+//! never loaded into memory as a whole, on either side.
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::io::SeekFrom;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::future::join_all;
+use serde::Deserialize;
+use serde::Serialize;
+use tokio::fs;
+use tokio::fs::File;
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncSeekExt;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+use crate::backend::service::utils::send_report_message;
+use crate::backend::types::BackendMessage;
+use crate::backend::types::MessageType;
+use crate::error::Error;
+use crate::error::Result;
+use crate::prelude::chunk::Chunk;
+use crate::prelude::chunk::ChunkMeta;
+use crate::prelude::rings_core::dht::Did;
+use crate::prelude::rings_core::utils::get_epoch_ms;
+use crate::prelude::uuid::Uuid;
+use crate::prelude::web3::signing::keccak256;
+use crate::prelude::*;
+
+/// Size of each chunk of a file, every chunk is sent as a message.
+pub const FILE_CHUNK_SIZE: usize = 1024 * 1024;
+/// Number of chunks sent before waiting for their acknowledgements.
+pub const FILE_WINDOW_SIZE: usize = 4;
+/// Times of resending a chunk which is not acknowledged.
+pub const FILE_CHUNK_RETRIES: usize = 3;
+/// Timeout of waiting for the reply of a file message.
+pub const FILE_TRANSFER_TIMEOUT_MS: u64 = 30 * 1000;
+/// Max size of a file to be received.
+pub const FILE_MAX_SIZE: u64 = 4 * 1024 * 1024 * 1024;
+/// Max number of files being received at the same time.
+pub const FILE_MAX_RECEIVINGS: usize = 16;
+/// A file being received is dropped if no chunk of it arrives for this long.
+pub const FILE_RECEIVING_TTL_MS: u128 = 5 * 60 * 1000;
+
+/// Offer of a file, sent before its chunks.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileOffer {
+    /// id of the transfer, which is also the id in [ChunkMeta] of its chunks.
+    pub id: Uuid,
+    /// name of the file.
+    pub name: String,
+    /// size of the file in bytes.
+    pub size: u64,
+    /// size of each chunk, except the last one.
+    pub chunk_size: usize,
+    /// hash of the file, see [file_hash].
+    pub hash: [u8; 32],
+}
+
+/// Messages of a file transfer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum FileMessage {
+    /// Offer a file to the receiver.
+    Offer(FileOffer),
+    /// Accept an offer.
+    Accept {
+        /// id of the transfer.
+        id: Uuid,
+        /// offset of the file to start from.
+        offset: u64,
+    },
+    /// Reject an offer.
+    Reject {
+        /// id of the transfer.
+        id: Uuid,
+        /// reason of rejection.
+        reason: String,
+    },
+    /// A chunk of the file, its `chunk` is `[index, total]`.
+    Chunk(Chunk),
+    /// Acknowledge a chunk.
+    Ack {
+        /// id of the transfer.
+        id: Uuid,
+        /// index of the chunk.
+        index: usize,
+    },
+    /// All chunks are sent.
+    Complete {
+        /// id of the transfer.
+        id: Uuid,
+    },
+    /// Result of checking the hash of the received file.
+    Verified {
+        /// id of the transfer.
+        id: Uuid,
+        /// whether the hash matches the offer, and the file is saved.
+        ok: bool,
+    },
+}
+
+impl TryFrom<&BackendMessage> for FileMessage {
+    type Error = Error;
+
+    fn try_from(msg: &BackendMessage) -> Result<Self> {
+        if !matches!(
+            MessageType::from(msg.message_type),
+            MessageType::FileTransfer
+        ) {
+            return Err(Error::NotSupportMessage);
+        }
+        bincode::deserialize(&msg.data).map_err(|_| Error::DeserializeError)
+    }
+}
+
+fn io_error(e: std::io::Error) -> Error {
+    Error::FileTransferError(e.to_string())
+}
+
+fn unexpected(msg: FileMessage) -> Error {
+    Error::FileTransferError(format!("unexpected reply: {:?}", msg))
+}
+
+fn chunk_count(size: u64, chunk_size: usize) -> usize {
+    ((size + chunk_size as u64 - 1) / chunk_size as u64) as usize
+}
+
+/// Read a chunk from the current position of file, or `None` at the end of it.
+async fn read_chunk(file: &mut File, chunk_size: usize) -> Result<Option<Bytes>> {
+    let mut buf = Vec::with_capacity(chunk_size);
+    file.take(chunk_size as u64)
+        .read_to_end(&mut buf)
+        .await
+        .map_err(io_error)?;
+    if buf.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(Bytes::from(buf)))
+}
+
+/// Id of transfer of a file, derived from its name and hash.
+pub(crate) fn transfer_id(name: &str, hash: &[u8; 32]) -> Uuid {
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&keccak256(&[name.as_bytes(), hash].concat())[..16]);
+    Uuid::from_bytes(bytes)
+}
+
+/// Size and hash of a file. The hash is keccak256 of the keccak256 hashes of its chunks,
+/// so that it is computed by reading the file chunk by chunk.
+pub async fn file_hash(path: &Path, chunk_size: usize) -> Result<(u64, [u8; 32])> {
+    let mut file = File::open(path)
+        .await
+        .map_err(|e| Error::OpenFileError(e.to_string()))?;
+    let mut size = 0;
+    let mut hashes = vec![];
+    while let Some(data) = read_chunk(&mut file, chunk_size).await? {
+        size += data.len() as u64;
+        hashes.extend_from_slice(&keccak256(&data));
+    }
+    Ok((size, keccak256(&hashes)))
+}
+
+/// Send a file message to destination and wait for its reply.
+async fn request(swarm: &Swarm, destination: Did, msg: &FileMessage) -> Result<FileMessage> {
+    let data: Vec<u8> = BackendMessage::try_from((MessageType::FileTransfer, msg))?.into();
+    let msg = Message::custom(&data, None).map_err(Error::SendMessage)?;
+    let reply = swarm
+        .send_message_and_wait(msg, destination, FILE_TRANSFER_TIMEOUT_MS)
+        .await
+        .map_err(Error::SendMessage)?;
+    match reply.data {
+        Message::CustomMessage(ref msg) => {
+            let msg = msg.plain_or_error().map_err(|_| Error::InvalidMessage)?;
+            FileMessage::try_from(&BackendMessage::try_from(msg.0.as_slice())?)
+        }
+        _ => Err(Error::InvalidMessage),
+    }
+}
+
+/// Send a chunk and wait for its acknowledgement, it's resent if that is not received in time.
+async fn send_chunk(swarm: &Swarm, destination: Did, chunk: Chunk) -> Result<()> {
+    let (id, index) = (chunk.meta.id, chunk.chunk[0]);
+    let msg = FileMessage::Chunk(chunk);
+    let mut retries = 0;
+    loop {
+        match request(swarm, destination, &msg).await {
+            Ok(FileMessage::Ack {
+                id: ack_id,
+                index: ack_index,
+            }) if ack_id == id && ack_index == index => return Ok(()),
+            Ok(reply) => return Err(unexpected(reply)),
+            Err(e) if retries < FILE_CHUNK_RETRIES => {
+                tracing::warn!("send chunk {} of file {} failed: {}, retry", index, id, e);
+                retries += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Send the file at `path` to destination, and wait until the receiver has checked its hash.
+/// If the receiver has a part of the file from an interrupted transfer, it's resumed from there.
+/// Returns the id of the transfer.
+pub async fn send_file(swarm: &Swarm, destination: Did, path: &str) -> Result<Uuid> {
+    let path = Path::new(path);
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| Error::OpenFileError(format!("invalid file name: {}", path.display())))?
+        .to_string();
+    let (size, hash) = file_hash(path, FILE_CHUNK_SIZE).await?;
+    let id = transfer_id(&name, &hash);
+    let offer = FileOffer {
+        id,
+        name,
+        size,
+        chunk_size: FILE_CHUNK_SIZE,
+        hash,
+    };
+
+    let offset = match request(swarm, destination, &FileMessage::Offer(offer)).await? {
+        FileMessage::Accept { offset, .. } => offset,
+        FileMessage::Reject { reason, .. } => {
+            return Err(Error::FileTransferError(format!(
+                "offer rejected: {}",
+                reason
+            )))
+        }
+        reply => return Err(unexpected(reply)),
+    };
+
+    let total = chunk_count(size, FILE_CHUNK_SIZE);
+    let mut index = (offset / FILE_CHUNK_SIZE as u64) as usize;
+    let mut file = File::open(path)
+        .await
+        .map_err(|e| Error::OpenFileError(e.to_string()))?;
+    file.seek(SeekFrom::Start(index as u64 * FILE_CHUNK_SIZE as u64))
+        .await
+        .map_err(io_error)?;
+    tracing::info!("send file {} to {} from chunk {}", id, destination, index);
+
+    while index < total {
+        let mut window = vec![];
+        while window.len() < FILE_WINDOW_SIZE && index < total {
+            let data = read_chunk(&mut file, FILE_CHUNK_SIZE)
+                .await?
+                .ok_or_else(|| Error::FileTransferError("file is changed".to_string()))?;
+            window.push(Chunk {
+                chunk: [index, total],
+                data,
+                meta: ChunkMeta {
+                    id,
+                    ..Default::default()
+                },
+            });
+            index += 1;
+        }
+        join_all(
+            window
+                .into_iter()
+                .map(|chunk| send_chunk(swarm, destination, chunk)),
+        )
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>>>()?;
+    }
+
+    match request(swarm, destination, &FileMessage::Complete { id }).await? {
+        FileMessage::Verified { ok: true, .. } => Ok(id),
+        FileMessage::Verified { ok: false, .. } => Err(Error::FileTransferError(
+            "received file is broken or not saved".to_string(),
+        )),
+        reply => Err(unexpected(reply)),
+    }
+}
+
+/// A file being received.
+struct Receiving {
+    offer: FileOffer,
+    file: File,
+    part: PathBuf,
+    target: PathBuf,
+    /// index of the next chunk to be written.
+    next: usize,
+    /// chunks received ahead of the next one.
+    pending: BTreeMap<usize, Bytes>,
+    /// time of the last message of the transfer.
+    updated_at: u128,
+}
+
+/// `FileTransfer` receives files offered by other nodes, see [send_file] for sending them.
+/// A file is written to `<sender>-<id>.part` in the receiving directory, and moved to its name
+/// after the hash is checked. An existing file is never overwritten.
+#[derive(Default)]
+pub struct FileTransfer {
+    dir: Mutex<Option<PathBuf>>,
+    receivings: Mutex<HashMap<(Did, Uuid), Receiving>>,
+}
+
+impl FileTransfer {
+    /// Receive files into `dir`, which is created if not exists.
+    /// Offers are rejected until this is called.
+    pub async fn receive_into(&self, dir: &str) -> Result<()> {
+        fs::create_dir_all(dir)
+            .await
+            .map_err(|e| Error::CreateFileError(e.to_string()))?;
+        *self.dir.lock().await = Some(PathBuf::from(dir));
+        Ok(())
+    }
+
+    /// Handle a file message sent by `from`, returns the reply to it if any.
+    pub async fn handle_message(&self, from: Did, msg: FileMessage) -> Result<Option<FileMessage>> {
+        match msg {
+            FileMessage::Offer(offer) => self.handle_offer(from, offer).await.map(Some),
+            FileMessage::Chunk(chunk) => self.handle_chunk(from, chunk).await.map(Some),
+            FileMessage::Complete { id } => self.handle_complete(from, id).await.map(Some),
+            // Replies are taken by the sender waiting for them.
+            _ => Ok(None),
+        }
+    }
+
+    async fn handle_offer(&self, from: Did, offer: FileOffer) -> Result<FileMessage> {
+        let id = offer.id;
+        let reject = |reason: &str| -> Result<FileMessage> {
+            Ok(FileMessage::Reject {
+                id,
+                reason: reason.to_string(),
+            })
+        };
+        let dir = match self.dir.lock().await.clone() {
+            Some(dir) => dir,
+            None => return reject("not receiving files"),
+        };
+        // Only the file name is taken, to keep the file in the receiving directory.
+        let name = match Path::new(&offer.name).file_name() {
+            Some(name) => name.to_owned(),
+            None => return reject("invalid file name"),
+        };
+        if offer.chunk_size == 0 || offer.chunk_size > FILE_CHUNK_SIZE {
+            return reject("invalid chunk size");
+        }
+        if offer.size > FILE_MAX_SIZE {
+            return reject("file is too large");
+        }
+        let target = dir.join(name);
+        if fs::metadata(&target).await.is_ok() {
+            return reject("file exists");
+        }
+
+        // Receivings idle for long are dropped, which closes their files.
+        let now = get_epoch_ms();
+        let mut receivings = self.receivings.lock().await;
+        receivings.retain(|_, r| now.saturating_sub(r.updated_at) < FILE_RECEIVING_TTL_MS);
+        if !receivings.contains_key(&(from, id)) && receivings.len() >= FILE_MAX_RECEIVINGS {
+            return reject("too many files being received");
+        }
+
+        let part = dir.join(format!("{}-{}.part", from, id));
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .open(&part)
+            .await
+            .map_err(|e| Error::OpenFileError(e.to_string()))?;
+        let len = file.metadata().await.map_err(io_error)?.len();
+        let offset = len.min(offer.size) / offer.chunk_size as u64 * offer.chunk_size as u64;
+        file.set_len(offset).await.map_err(io_error)?;
+        file.seek(SeekFrom::Start(offset)).await.map_err(io_error)?;
+        tracing::info!(
+            "receive file {} of {} bytes from {}, resume at {}",
+            offer.name,
+            offer.size,
+            from,
+            offset
+        );
+
+        receivings.insert(
+            (from, id),
+            Receiving {
+                next: (offset / offer.chunk_size as u64) as usize,
+                offer,
+                file,
+                part,
+                target,
+                pending: BTreeMap::new(),
+                updated_at: now,
+            },
+        );
+        Ok(FileMessage::Accept { id, offset })
+    }
+
+    async fn handle_chunk(&self, from: Did, chunk: Chunk) -> Result<FileMessage> {
+        let (id, index) = (chunk.meta.id, chunk.chunk[0]);
+        let mut receivings = self.receivings.lock().await;
+        let receiving = receivings
+            .get_mut(&(from, id))
+            .ok_or_else(|| Error::FileTransferError(format!("unknown file {}", id)))?;
+
+        let (size, chunk_size) = (receiving.offer.size, receiving.offer.chunk_size);
+        let total = chunk_count(size, chunk_size);
+        if index >= total || index >= receiving.next + FILE_WINDOW_SIZE {
+            return Err(Error::FileTransferError(format!(
+                "chunk {} of file {} is out of window",
+                index, id
+            )));
+        }
+        // Every chunk is of the offered size, except the last one.
+        let expected = (size - index as u64 * chunk_size as u64).min(chunk_size as u64);
+        if chunk.data.len() as u64 != expected {
+            return Err(Error::FileTransferError(format!(
+                "chunk {} of file {} has {} bytes, expected {}",
+                index,
+                id,
+                chunk.data.len(),
+                expected
+            )));
+        }
+        receiving.updated_at = get_epoch_ms();
+        // A chunk is resent if its acknowledgement is lost, which is written already.
+        if index >= receiving.next {
+            receiving.pending.insert(index, chunk.data);
+        }
+        while let Some(data) = receiving.pending.remove(&receiving.next) {
+            receiving.file.write_all(&data).await.map_err(io_error)?;
+            receiving.next += 1;
+        }
+        Ok(FileMessage::Ack { id, index })
+    }
+
+    async fn handle_complete(&self, from: Did, id: Uuid) -> Result<FileMessage> {
+        let receiving = self.receivings.lock().await.remove(&(from, id));
+        let Receiving {
+            offer,
+            mut file,
+            part,
+            target,
+            ..
+        } = receiving.ok_or_else(|| Error::FileTransferError(format!("unknown file {}", id)))?;
+        file.flush().await.map_err(io_error)?;
+        drop(file);
+
+        let (size, hash) = file_hash(&part, offer.chunk_size).await?;
+        let mut ok = size == offer.size && hash == offer.hash;
+        if ok {
+            // Linking fails if the target exists, which is kept as it is.
+            if let Err(e) = fs::hard_link(&part, &target).await {
+                tracing::warn!("failed to move received file {}: {}", id, e);
+                ok = false;
+            }
+        } else {
+            tracing::warn!("received file {} is broken, removed", id);
+        }
+        // The sender is always replied, even if the part file is not removed.
+        if let Err(e) = fs::remove_file(&part).await {
+            tracing::warn!("failed to remove part of file {}: {}", id, e);
+        }
+        if ok {
+            tracing::info!("received file {} to {}", id, target.display());
+        }
+        Ok(FileMessage::Verified { id, ok })
+    }
+}
+
+/// `FileTransferCallback` takes file messages to [FileTransfer], and passes others to `inner`.
+pub struct FileTransferCallback {
+    file_transfer: Arc<FileTransfer>,
+    inner: Option<CallbackFn>,
+}
+
+impl FileTransferCallback {
+    /// Create a callback wrapping `inner`.
+    pub fn new(file_transfer: Arc<FileTransfer>, inner: Option<CallbackFn>) -> Self {
+        Self {
+            file_transfer,
+            inner,
+        }
+    }
+
+    async fn handle_file_message(
+        &self,
+        handler: &MessageHandler,
+        ctx: &MessagePayload<Message>,
+        msg: FileMessage,
+    ) -> Result<()> {
+        let from = ctx.origin_verification.session.authorizer_did();
+        let reply = match self.file_transfer.handle_message(from, msg).await? {
+            Some(reply) => reply,
+            None => return Ok(()),
+        };
+        let mut relay = ctx.relay.clone();
+        relay
+            .relay(relay.destination, None)
+            .map_err(Error::SendMessage)?;
+        let data: Vec<u8> = BackendMessage::try_from((MessageType::FileTransfer, &reply))?.into();
+        send_report_message(handler, ctx, &relay, &data).await
+    }
+}
+
+/// Decode a custom message as a file message, `None` if it's not.
+fn decode_file_message(
+    handler: &MessageHandler,
+    msg: &MaybeEncrypted<CustomMessage>,
+) -> Option<FileMessage> {
+    let msg = handler.decrypt_msg(msg).ok()?;
+    let msg = BackendMessage::try_from(msg.0.as_slice()).ok()?;
+    FileMessage::try_from(&msg).ok()
+}
+
+#[async_trait]
+impl MessageCallback for FileTransferCallback {
+    async fn custom_message(
+        &self,
+        handler: &MessageHandler,
+        ctx: &MessagePayload<Message>,
+        msg: &MaybeEncrypted<CustomMessage>,
+    ) {
+        if let Some(file_msg) = decode_file_message(handler, msg) {
+            if let Err(e) = self.handle_file_message(handler, ctx, file_msg).await {
+                tracing::error!("handle file message failed: {}", e);
+            }
+            return;
+        }
+        if let Some(inner) = &self.inner {
+            inner.custom_message(handler, ctx, msg).await;
+        }
+    }
+
+    async fn builtin_message(&self, handler: &MessageHandler, ctx: &MessagePayload<Message>) {
+        if let Some(inner) = &self.inner {
+            inner.builtin_message(handler, ctx).await;
+        }
+    }
+}
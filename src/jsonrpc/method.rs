@@ -24,6 +24,12 @@ pub enum Method {
     SendTo,
     /// Disconnect a peer
     Disconnect,
+    /// Block a peer
+    BlockPeer,
+    /// Unblock a peer
+    UnblockPeer,
+    /// List blocked peers
+    ListBlockedPeers,
     /// List all pending connections
     ListPendings,
     /// Close pending connect
@@ -38,12 +44,28 @@ pub enum Method {
     PublishMessageToTopic,
     /// Fetch data of topic
     FetchMessagesOfTopic,
+    /// Fetch virtual nodes in a range of DHT
+    FetchVNodeRange,
+    /// Subscribe topic, new data will be pushed as message
+    SubscribeTopic,
+    /// Unsubscribe topic
+    UnsubscribeTopic,
     /// Register service
     RegisterService,
     /// Lookup service
     LookupService,
     /// Poll message
     PollMessage,
+    /// Poll messages pushed from a subscribed topic
+    PollTopicMessage,
+    /// Join subring
+    JoinSubring,
+    /// Leave subring, or remove a member from subring
+    LeaveSubring,
+    /// List members of subring
+    ListSubringMembers,
+    /// Broadcast message to subring
+    BroadcastToSubring,
 }
 
 impl Method {
@@ -58,6 +80,9 @@ impl Method {
             Method::AnswerOffer => "answerOffer",
             Method::SendTo => "sendTo",
             Method::Disconnect => "disconnect",
+            Method::BlockPeer => "blockPeer",
+            Method::UnblockPeer => "unblockPeer",
+            Method::ListBlockedPeers => "listBlockedPeers",
             Method::AcceptAnswer => "acceptAnswer",
             Method::ListPendings => "listPendings",
             Method::ClosePendingTransport => "closePendingTransport",
@@ -66,9 +91,17 @@ impl Method {
             Method::SendCustomMessage => "sendCustomMessage",
             Method::PublishMessageToTopic => "publishMessageToTopic",
             Method::FetchMessagesOfTopic => "fetchMessagesOfTopic",
+            Method::FetchVNodeRange => "fetchVNodeRange",
+            Method::SubscribeTopic => "subscribeTopic",
+            Method::UnsubscribeTopic => "unsubscribeTopic",
             Method::RegisterService => "registerService",
             Method::LookupService => "lookupService",
             Method::PollMessage => "pollMessage",
+            Method::PollTopicMessage => "pollTopicMessage",
+            Method::JoinSubring => "joinSubring",
+            Method::LeaveSubring => "leaveSubring",
+            Method::ListSubringMembers => "listSubringMembers",
+            Method::BroadcastToSubring => "broadcastToSubring",
         }
     }
 }
@@ -92,6 +125,9 @@ impl TryFrom<&str> for Method {
             "answerOffer" => Self::AnswerOffer,
             "sendTo" => Self::SendTo,
             "disconnect" => Self::Disconnect,
+            "blockPeer" => Self::BlockPeer,
+            "unblockPeer" => Self::UnblockPeer,
+            "listBlockedPeers" => Self::ListBlockedPeers,
             "acceptAnswer" => Self::AcceptAnswer,
             "listPendings" => Self::ListPendings,
             "closePendingTransport" => Self::ClosePendingTransport,
@@ -100,9 +136,17 @@ impl TryFrom<&str> for Method {
             "sendCustomMessage" => Self::SendCustomMessage,
             "publishMessageToTopic" => Method::PublishMessageToTopic,
             "fetchMessagesOfTopic" => Method::FetchMessagesOfTopic,
+            "fetchVNodeRange" => Method::FetchVNodeRange,
+            "subscribeTopic" => Method::SubscribeTopic,
+            "unsubscribeTopic" => Method::UnsubscribeTopic,
             "registerService" => Method::RegisterService,
             "lookupService" => Method::LookupService,
             "pollMessage" => Method::PollMessage,
+            "pollTopicMessage" => Method::PollTopicMessage,
+            "joinSubring" => Method::JoinSubring,
+            "leaveSubring" => Method::LeaveSubring,
+            "listSubringMembers" => Method::ListSubringMembers,
+            "broadcastToSubring" => Method::BroadcastToSubring,
             _ => return Err(Error::InvalidMethod),
         })
     }
@@ -1,6 +1,7 @@
 #![warn(missing_docs)]
 //! A jsonrpc-server of rings-node
 /// [JSON-RPC]: https://www.jsonrpc.org/specification
+use std::collections::HashMap;
 use std::collections::HashSet;
 use std::str::FromStr;
 use std::sync::Arc;
@@ -13,7 +14,10 @@ use jsonrpc_core::Metadata;
 use jsonrpc_core::Params;
 use jsonrpc_core::Result;
 use jsonrpc_core::Value;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::error::TryRecvError;
 use tokio::sync::broadcast::Receiver;
+use tokio::sync::broadcast::Sender;
 use tokio::sync::Mutex;
 
 use super::method::Method;
@@ -26,10 +30,13 @@ use crate::backend::types::HttpRequest;
 use crate::backend::MessageType;
 use crate::error::Error as ServerError;
 use crate::prelude::rings_core::dht::Did;
+use crate::prelude::rings_core::message::Decoder;
 use crate::prelude::rings_core::message::Encoder;
+use crate::prelude::rings_core::message::TopicMessages;
 use crate::prelude::rings_core::prelude::vnode::VirtualNode;
 use crate::prelude::rings_core::transports::manager::TransportManager;
 use crate::prelude::rings_core::types::ice_transport::IceTransportInterface;
+use crate::prelude::rings_core::utils::get_epoch_ms;
 use crate::processor;
 use crate::processor::Processor;
 use crate::seed::Seed;
@@ -42,6 +49,7 @@ use crate::util::from_rtc_ice_connection_state;
 pub struct RpcMeta {
     processor: Arc<Processor>,
     receiver: Arc<Mutex<Receiver<BackendMessage>>>,
+    topic_receivers: TopicReceivers,
     is_auth: bool,
 }
 
@@ -57,22 +65,64 @@ impl RpcMeta {
 /// `MetaIoHandler<T>, T: Metadata`
 impl Metadata for RpcMeta {}
 
-impl From<(Arc<Processor>, Arc<Mutex<Receiver<BackendMessage>>>, bool)> for RpcMeta {
+impl
+    From<(
+        Arc<Processor>,
+        Arc<Mutex<Receiver<BackendMessage>>>,
+        TopicReceivers,
+        bool,
+    )> for RpcMeta
+{
     fn from(
-        (processor, receiver, is_auth): (
+        (processor, receiver, topic_receivers, is_auth): (
             Arc<Processor>,
             Arc<Mutex<Receiver<BackendMessage>>>,
+            TopicReceivers,
             bool,
         ),
     ) -> Self {
         Self {
             processor,
             receiver,
+            topic_receivers,
             is_auth,
         }
     }
 }
 
+/// Receivers of backend messages, one for each subscribed topic.
+/// Polling a topic neither takes messages from [Method::PollMessage] nor blocks it.
+#[derive(Clone)]
+pub struct TopicReceivers {
+    sender: Sender<BackendMessage>,
+    receivers: Arc<Mutex<HashMap<Did, Arc<Mutex<Receiver<BackendMessage>>>>>>,
+}
+
+impl TopicReceivers {
+    /// Create receivers of topics from the sender of backend messages.
+    pub fn new(sender: Sender<BackendMessage>) -> Self {
+        Self {
+            sender,
+            receivers: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    async fn subscribe(&self, vid: Did) {
+        let mut receivers = self.receivers.lock().await;
+        receivers
+            .entry(vid)
+            .or_insert_with(|| Arc::new(Mutex::new(self.sender.subscribe())));
+    }
+
+    async fn unsubscribe(&self, vid: Did) {
+        self.receivers.lock().await.remove(&vid);
+    }
+
+    async fn get(&self, vid: Did) -> Option<Arc<Mutex<Receiver<BackendMessage>>>> {
+        self.receivers.lock().await.get(&vid).cloned()
+    }
+}
+
 /// Build handler add method with metadata.
 pub(crate) async fn build_handler(handler: &mut MetaIoHandler<RpcMeta>) {
     handler.add_method_with_meta(Method::ConnectPeerViaHttp.as_str(), connect_peer_via_http);
@@ -83,6 +133,9 @@ pub(crate) async fn build_handler(handler: &mut MetaIoHandler<RpcMeta>) {
     handler.add_method_with_meta(Method::AcceptAnswer.as_str(), accept_answer);
     handler.add_method_with_meta(Method::ListPeers.as_str(), list_peers);
     handler.add_method_with_meta(Method::Disconnect.as_str(), close_connection);
+    handler.add_method_with_meta(Method::BlockPeer.as_str(), block_peer);
+    handler.add_method_with_meta(Method::UnblockPeer.as_str(), unblock_peer);
+    handler.add_method_with_meta(Method::ListBlockedPeers.as_str(), list_blocked_peers);
     handler.add_method_with_meta(Method::ListPendings.as_str(), list_pendings);
     handler.add_method_with_meta(
         Method::ClosePendingTransport.as_str(),
@@ -103,9 +156,17 @@ pub(crate) async fn build_handler(handler: &mut MetaIoHandler<RpcMeta>) {
         Method::FetchMessagesOfTopic.as_str(),
         fetch_messages_of_topic,
     );
+    handler.add_method_with_meta(Method::FetchVNodeRange.as_str(), fetch_vnode_range);
+    handler.add_method_with_meta(Method::SubscribeTopic.as_str(), subscribe_topic);
+    handler.add_method_with_meta(Method::UnsubscribeTopic.as_str(), unsubscribe_topic);
     handler.add_method_with_meta(Method::RegisterService.as_str(), register_service);
     handler.add_method_with_meta(Method::LookupService.as_str(), lookup_service);
     handler.add_method_with_meta(Method::PollMessage.as_str(), poll_message);
+    handler.add_method_with_meta(Method::PollTopicMessage.as_str(), poll_topic_message);
+    handler.add_method_with_meta(Method::JoinSubring.as_str(), join_subring);
+    handler.add_method_with_meta(Method::LeaveSubring.as_str(), leave_subring);
+    handler.add_method_with_meta(Method::ListSubringMembers.as_str(), list_subring_members);
+    handler.add_method_with_meta(Method::BroadcastToSubring.as_str(), broadcast_to_subring);
 }
 
 /// Connect Peer VIA http
@@ -232,6 +293,42 @@ async fn close_connection(params: Params, meta: RpcMeta) -> Result<Value> {
     Ok(serde_json::json!({}))
 }
 
+/// Handle block peer
+async fn block_peer(params: Params, meta: RpcMeta) -> Result<Value> {
+    meta.require_authed()?;
+    let params: Vec<String> = params.parse()?;
+    let did = params
+        .first()
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
+    let did = Did::from_str(did).map_err(|_| Error::from(ServerError::InvalidDid))?;
+    meta.processor.block_peer(did).await?;
+    Ok(serde_json::json!({}))
+}
+
+/// Handle unblock peer
+async fn unblock_peer(params: Params, meta: RpcMeta) -> Result<Value> {
+    meta.require_authed()?;
+    let params: Vec<String> = params.parse()?;
+    let did = params
+        .first()
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
+    let did = Did::from_str(did).map_err(|_| Error::from(ServerError::InvalidDid))?;
+    meta.processor.unblock_peer(did).await?;
+    Ok(serde_json::json!({}))
+}
+
+/// Handle list blocked peers
+async fn list_blocked_peers(_params: Params, meta: RpcMeta) -> Result<Value> {
+    meta.require_authed()?;
+    let dids = meta
+        .processor
+        .blocked_peers()
+        .into_iter()
+        .map(|did| did.to_string())
+        .collect::<Vec<_>>();
+    Ok(serde_json::json!(dids))
+}
+
 /// Handle list pendings
 async fn list_pendings(_params: Params, meta: RpcMeta) -> Result<Value> {
     meta.require_authed()?;
@@ -405,8 +502,7 @@ async fn fetch_messages_of_topic(params: Params, meta: RpcMeta) -> Result<Value>
 
     let vid = VirtualNode::gen_did(topic).map_err(|_| Error::new(ErrorCode::InvalidParams))?;
 
-    meta.processor.storage_fetch(vid).await?;
-    let result = meta.processor.storage_check_cache(vid).await;
+    let result = meta.processor.storage_fetch_and_wait(vid).await?;
 
     if let Some(vnode) = result {
         let messages = vnode
@@ -422,6 +518,72 @@ async fn fetch_messages_of_topic(params: Params, meta: RpcMeta) -> Result<Value>
     }
 }
 
+/// Fetch virtual nodes in the clockwise range from param 0 to param 1.
+/// At most param 2 (default 100) virtual nodes are returned, with the start of next page.
+async fn fetch_vnode_range(params: Params, meta: RpcMeta) -> Result<Value> {
+    meta.require_authed()?;
+    let params: Vec<serde_json::Value> = params.parse()?;
+    let parse_did = |i: usize| -> Result<Did> {
+        let did = params
+            .get(i)
+            .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?
+            .as_str()
+            .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
+        Did::from_str(did).map_err(|_| Error::from(ServerError::InvalidDid))
+    };
+    let start = parse_did(0)?;
+    let end = parse_did(1)?;
+    let limit = match params.get(2) {
+        Some(v) => v
+            .as_u64()
+            .ok_or_else(|| Error::new(ErrorCode::InvalidParams))? as usize,
+        None => 100,
+    };
+
+    let (vnodes, next) = meta
+        .processor
+        .storage_fetch_range(start, end, limit)
+        .await?;
+
+    Ok(serde_json::json!({
+        "vnodes": vnodes,
+        "next": next.map(|did| did.to_string()),
+    }))
+}
+
+async fn subscribe_topic(params: Params, meta: RpcMeta) -> Result<Value> {
+    meta.require_authed()?;
+    let params: Vec<serde_json::Value> = params.parse()?;
+    let topic = params
+        .get(0)
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?
+        .as_str()
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
+
+    let vid = VirtualNode::gen_did(topic).map_err(|_| Error::new(ErrorCode::InvalidParams))?;
+    // Subscribe locally first, so that no pushed message will be missed.
+    meta.topic_receivers.subscribe(vid).await;
+    meta.processor.subscribe_topic(topic).await?;
+
+    Ok(serde_json::json!({}))
+}
+
+async fn unsubscribe_topic(params: Params, meta: RpcMeta) -> Result<Value> {
+    meta.require_authed()?;
+    let params: Vec<serde_json::Value> = params.parse()?;
+    let topic = params
+        .get(0)
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?
+        .as_str()
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
+
+    let vid = VirtualNode::gen_did(topic).map_err(|_| Error::new(ErrorCode::InvalidParams))?;
+    meta.processor.unsubscribe_topic(topic).await?;
+    meta.topic_receivers.unsubscribe(vid).await;
+
+    Ok(serde_json::json!({}))
+}
+
 async fn register_service(params: Params, meta: RpcMeta) -> Result<Value> {
     meta.require_authed()?;
     let params: Vec<serde_json::Value> = params.parse()?;
@@ -430,7 +592,14 @@ async fn register_service(params: Params, meta: RpcMeta) -> Result<Value> {
         .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?
         .as_str()
         .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
-    meta.processor.register_service(name).await?;
+    let ttl_ms = match params.get(1) {
+        Some(v) => Some(
+            v.as_u64()
+                .ok_or_else(|| Error::new(ErrorCode::InvalidParams))? as u128,
+        ),
+        None => None,
+    };
+    meta.processor.register_service(name, ttl_ms).await?;
     Ok(serde_json::json!({}))
 }
 
@@ -445,10 +614,11 @@ async fn lookup_service(params: Params, meta: RpcMeta) -> Result<Value> {
 
     let rid = VirtualNode::gen_did(name).map_err(|_| Error::new(ErrorCode::InvalidParams))?;
 
-    meta.processor.storage_fetch(rid).await?;
-    let result = meta.processor.storage_check_cache(rid).await;
+    let result = meta.processor.storage_fetch_and_wait(rid).await?;
 
     if let Some(vnode) = result {
+        // Only live providers are returned.
+        let vnode = vnode.prune_expired(get_epoch_ms());
         let dids = vnode
             .data
             .iter()
@@ -461,6 +631,77 @@ async fn lookup_service(params: Params, meta: RpcMeta) -> Result<Value> {
     }
 }
 
+async fn join_subring(params: Params, meta: RpcMeta) -> Result<Value> {
+    meta.require_authed()?;
+    let params: Vec<serde_json::Value> = params.parse()?;
+    let name = params
+        .get(0)
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?
+        .as_str()
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
+    meta.processor.subring_join(name).await?;
+    Ok(serde_json::json!({}))
+}
+
+/// Leave subring, or remove the member given by param 1 from subring.
+async fn leave_subring(params: Params, meta: RpcMeta) -> Result<Value> {
+    meta.require_authed()?;
+    let params: Vec<serde_json::Value> = params.parse()?;
+    let name = params
+        .get(0)
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?
+        .as_str()
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
+    match params.get(1) {
+        Some(v) => {
+            let member = v
+                .as_str()
+                .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
+            meta.processor.subring_remove_member(name, member).await?
+        }
+        None => meta.processor.subring_leave(name).await?,
+    }
+    Ok(serde_json::json!({}))
+}
+
+async fn list_subring_members(params: Params, meta: RpcMeta) -> Result<Value> {
+    meta.require_authed()?;
+    let params: Vec<serde_json::Value> = params.parse()?;
+    let name = params
+        .get(0)
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?
+        .as_str()
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
+    let dids = meta
+        .processor
+        .subring_members(name)
+        .await?
+        .into_iter()
+        .map(|did| did.to_string())
+        .collect::<Vec<String>>();
+    Ok(serde_json::json!(dids))
+}
+
+async fn broadcast_to_subring(params: Params, meta: RpcMeta) -> Result<Value> {
+    meta.require_authed()?;
+    let params: Vec<serde_json::Value> = params.parse()?;
+    let name = params
+        .get(0)
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?
+        .as_str()
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
+    let text = params
+        .get(1)
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?
+        .as_str()
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
+    let msg: BackendMessage =
+        BackendMessage::from((MessageType::SimpleText.into(), text.as_bytes()));
+    let msg: Vec<u8> = msg.into();
+    meta.processor.subring_broadcast(name, &msg).await?;
+    Ok(serde_json::json!({}))
+}
+
 async fn poll_message(params: Params, meta: RpcMeta) -> Result<Value> {
     let params: Vec<serde_json::Value> = params.parse()?;
     let wait_recv = params
@@ -485,3 +726,71 @@ async fn poll_message(params: Params, meta: RpcMeta) -> Result<Value> {
       "message": message,
     }))
 }
+
+/// Poll messages pushed from a subscribed topic, other messages are skipped.
+/// If `wait_recv` is set, wait until there is at least one message.
+async fn poll_topic_message(params: Params, meta: RpcMeta) -> Result<Value> {
+    meta.require_authed()?;
+    let params: Vec<serde_json::Value> = params.parse()?;
+    let topic = params
+        .get(0)
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?
+        .as_str()
+        .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
+    let wait_recv = params
+        .get(1)
+        .map(|v| v.as_bool().unwrap_or(false))
+        .unwrap_or(false);
+
+    let vid = VirtualNode::gen_did(topic).map_err(|_| Error::new(ErrorCode::InvalidParams))?;
+    let receiver = meta
+        .topic_receivers
+        .get(vid)
+        .await
+        .ok_or_else(|| Error::invalid_params("topic is not subscribed"))?;
+    let mut recv = receiver.lock().await;
+
+    let mut messages = vec![];
+    loop {
+        let msg = if wait_recv && messages.is_empty() {
+            match recv.recv().await {
+                Ok(msg) => msg,
+                Err(RecvError::Lagged(n)) => {
+                    tracing::warn!("{} messages of topic {} are dropped", n, topic);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            }
+        } else {
+            match recv.try_recv() {
+                Ok(msg) => msg,
+                Err(TryRecvError::Lagged(n)) => {
+                    tracing::warn!("{} messages of topic {} are dropped", n, topic);
+                    continue;
+                }
+                Err(_) => break,
+            }
+        };
+
+        if msg.message_type != u16::from(MessageType::TopicMessage) {
+            continue;
+        }
+        let msg: TopicMessages = match bincode::deserialize(&msg.data) {
+            Ok(msg) => msg,
+            Err(_) => continue,
+        };
+        if msg.vid != vid {
+            continue;
+        }
+        for data in msg.data.iter() {
+            match data.decode::<String>() {
+                Ok(data) => messages.push(data),
+                Err(e) => tracing::error!("Failed to decode message of topic: {}, {}", topic, e),
+            }
+        }
+    }
+
+    Ok(serde_json::json!({
+      "messages": messages,
+    }))
+}
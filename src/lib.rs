@@ -141,8 +141,9 @@ pub mod browser;
 pub mod cli;
 #[cfg(not(feature = "browser"))]
 pub mod config;
-pub mod consts;
 pub mod error;
+#[cfg(feature = "node")]
+pub mod file_transfer;
 pub mod jsonrpc;
 pub mod jsonrpc_client;
 pub mod logging;
@@ -16,10 +16,18 @@ use crate::backend::types::Timeout;
 use crate::error;
 use crate::error::Error;
 use crate::error::Result;
+#[cfg(feature = "node")]
+use crate::file_transfer;
+#[cfg(feature = "node")]
+use crate::file_transfer::FileTransfer;
+#[cfg(feature = "node")]
+use crate::file_transfer::FileTransferCallback;
 use crate::jsonrpc::method;
 use crate::jsonrpc::response::TransportAndIce;
 use crate::jsonrpc_client::SimpleClient;
 use crate::measure::PeriodicMeasure;
+use crate::prelude::rings_core::blocklist::Blocklist;
+use crate::prelude::rings_core::consts::DEFAULT_FETCH_TIMEOUT_MS;
 use crate::prelude::rings_core::dht::Did;
 use crate::prelude::rings_core::dht::Stabilization;
 use crate::prelude::rings_core::dht::TStabilize;
@@ -49,7 +57,10 @@ use crate::prelude::web3::signing::keccak256;
 use crate::prelude::CallbackFn;
 use crate::prelude::ChordStorageInterface;
 use crate::prelude::CustomMessage;
+use crate::prelude::MaybeEncrypted;
+use crate::prelude::PubSubInterface;
 use crate::prelude::Signer;
+use crate::prelude::SubringInterface;
 
 /// AddressType enum contains `DEFAULT` and `ED25519`.
 pub enum AddressType {
@@ -68,6 +79,10 @@ pub struct UnsignedInfo {
     auth: AuthorizedInfo,
     /// random secrekey generate by service
     random_key: SecretKey,
+    /// id of the network to join
+    network_id: u32,
+    /// difficulty of admission for Dids of the network
+    admission_difficulty: u8,
 }
 
 impl UnsignedInfo {
@@ -87,6 +102,8 @@ impl UnsignedInfo {
             auth,
             random_key,
             key_addr,
+            network_id: 0,
+            admission_difficulty: 0,
         })
     }
 
@@ -113,9 +130,23 @@ impl UnsignedInfo {
             auth,
             random_key,
             key_addr,
+            network_id: 0,
+            admission_difficulty: 0,
         })
     }
 
+    /// Set id of the network to join, nodes of other networks are rejected.
+    pub fn with_network_id(mut self, network_id: u32) -> Self {
+        self.network_id = network_id;
+        self
+    }
+
+    /// Set difficulty of admission for Dids of the network, 0 means disabled.
+    pub fn with_admission_difficulty(mut self, difficulty: u8) -> Self {
+        self.admission_difficulty = difficulty;
+        self
+    }
+
     /// Get auth string
     pub fn auth(&self) -> Result<String> {
         let s = self.auth.to_string().map_err(|_| Error::InvalidAuthData)?;
@@ -130,6 +161,9 @@ pub struct Processor {
     pub swarm: Arc<Swarm>,
     /// a stabilization instance,
     pub stabilization: Arc<Stabilization>,
+    /// a file transfer instance, receiving files offered by other nodes
+    #[cfg(feature = "node")]
+    pub file_transfer: Arc<FileTransfer>,
 }
 
 #[cfg(feature = "node")]
@@ -140,6 +174,8 @@ impl From<(Arc<Swarm>, Arc<Stabilization>)> for Processor {
         Self {
             swarm,
             stabilization,
+            #[cfg(feature = "node")]
+            file_transfer: Arc::new(FileTransfer::default()),
         }
     }
 }
@@ -176,16 +212,34 @@ impl Processor {
             .map_err(Error::Storage)?;
         let measure = PeriodicMeasure::new(ms);
 
+        let blocklist_path = [storage_path, "blocklist"].join("/");
+        let bs = PersistenceStorage::new_with_cap_and_path(50000, blocklist_path)
+            .await
+            .map_err(Error::Storage)?;
+        let blocklist = Blocklist::new_with_storage(bs)
+            .await
+            .map_err(Error::Storage)?;
+
+        let subring_path = [storage_path, "subring"].join("/");
+        let ss = PersistenceStorage::new_with_cap_and_path(50000, subring_path)
+            .await
+            .map_err(Error::Storage)?;
+
         let random_key = unsigned_info.random_key;
         let session_manager = SessionManager::new(&signed_data, &unsigned_info.auth, &random_key);
 
         let swarm = Arc::new(
             SwarmBuilder::new(&stuns, storage)
                 .session_manager(unsigned_info.key_addr, session_manager)
+                .network_id(unsigned_info.network_id)
+                .dht_admission_difficulty(unsigned_info.admission_difficulty)
                 .measure(Box::new(measure))
+                .blocklist(blocklist)
+                .dht_subring_storage(ss)
                 .build()
                 .map_err(Error::Swarm)?,
         );
+        swarm.dht().load_subrings().await.map_err(Error::Swarm)?;
 
         let stabilization = Arc::new(Stabilization::new(swarm.clone(), 20));
         Ok(Processor::from((swarm, stabilization)))
@@ -193,6 +247,11 @@ impl Processor {
 
     /// Listen processor message
     pub fn listen(&self, callback: Option<CallbackFn>) -> Join<impl Future, impl Future> {
+        #[cfg(feature = "node")]
+        let callback: Option<CallbackFn> = Some(Box::new(FileTransferCallback::new(
+            self.file_transfer.clone(),
+            callback,
+        )));
         let message_handler = Arc::new(self.swarm.create_message_handler(callback, None));
         let stab = Arc::clone(&self.stabilization);
         futures::future::join(async { message_handler.listen().await }, async {
@@ -294,8 +353,12 @@ impl Processor {
                 .map_err(|e| Error::RemoteRpcError(e.to_string()))?;
             let info: TransportAndIce =
                 serde_json::from_value(resp).map_err(|_| Error::JsonDeserializeError)?;
+            let answer = Encoded::from_encoded_str(info.ice.as_str());
+            self.swarm
+                .check_handshake_info(&answer)
+                .map_err(Error::RegisterIceError)?;
             let did = transport
-                .register_remote_info(Encoded::from_encoded_str(info.ice.as_str()))
+                .register_remote_info(answer)
                 .await
                 .map_err(Error::RegisterIceError)?;
             self.swarm
@@ -365,6 +428,9 @@ impl Processor {
     async fn handshake(&self, transport: &Arc<Transport>, data: &str) -> Result<Encoded> {
         // get offer from remote and send answer back
         let hs_info = Encoded::from_encoded_str(data);
+        self.swarm
+            .check_handshake_info(&hs_info)
+            .map_err(Error::RegisterIceError)?;
         let did = transport
             .register_remote_info(hs_info.to_owned())
             .await
@@ -401,6 +467,9 @@ impl Processor {
             .find_pending_transport(transport_id)
             .map_err(Error::PendingTransport)?
             .ok_or(Error::TransportNotFound)?;
+        self.swarm
+            .check_handshake_info(&ice)
+            .map_err(Error::RegisterIceError)?;
         let did = transport
             .register_remote_info(ice)
             .await
@@ -449,6 +518,26 @@ impl Processor {
         Ok(())
     }
 
+    /// Block a peer, its connection will be closed and its messages will be dropped.
+    pub async fn block_peer(&self, did: Did) -> Result<()> {
+        self.swarm.block_peer(did).await.map_err(Error::Swarm)
+    }
+
+    /// Unblock a peer.
+    pub async fn unblock_peer(&self, did: Did) -> Result<()> {
+        self.swarm.unblock_peer(did).await.map_err(Error::Swarm)
+    }
+
+    /// List blocked peers.
+    pub fn blocked_peers(&self) -> Vec<Did> {
+        self.swarm.blocked_peers()
+    }
+
+    /// Leave the network gracefully, stored data will be handed off to successor.
+    pub async fn leave(&self) -> Result<()> {
+        self.swarm.leave().await.map_err(Error::Swarm)
+    }
+
     /// List all pending transport.
     pub async fn list_pendings(&self) -> Result<Vec<Arc<Transport>>> {
         let pendings = self
@@ -488,18 +577,11 @@ impl Processor {
             msg,
         );
         let destination = Did::from_str(destination).map_err(|_| Error::InvalidDid)?;
-
-        let mut new_msg = Vec::with_capacity(msg.len() + 4);
-        // chunked mark
-        new_msg.push(0);
-        new_msg.extend_from_slice(&[0u8; 3]);
-        new_msg.extend_from_slice(msg);
-
-        let msg = Message::custom(&new_msg, None).map_err(Error::SendMessage)?;
+        let msg = pack_custom_message(msg)?;
 
         let uuid = self
             .swarm
-            .send_message(msg, destination)
+            .send_custom_message(msg, destination)
             .await
             .map_err(Error::SendMessage)?;
         Ok(uuid)
@@ -596,6 +678,28 @@ impl Processor {
             .map_err(error::Error::VNodeError)
     }
 
+    /// fetch virtual node from DHT and wait for the result
+    pub async fn storage_fetch_and_wait(&self, did: Did) -> Result<Option<vnode::VirtualNode>> {
+        self.swarm
+            .storage_fetch_and_wait(did, DEFAULT_FETCH_TIMEOUT_MS)
+            .await
+            .map_err(error::Error::VNodeError)
+    }
+
+    /// fetch virtual nodes in the clockwise range from `start` to `end`, at most `limit` of them.
+    /// The start of next page is returned if the range is not finished.
+    pub async fn storage_fetch_range(
+        &self,
+        start: Did,
+        end: Did,
+        limit: usize,
+    ) -> Result<(Vec<vnode::VirtualNode>, Option<Did>)> {
+        self.swarm
+            .storage_fetch_range_and_wait(start, end, limit, DEFAULT_FETCH_TIMEOUT_MS)
+            .await
+            .map_err(error::Error::VNodeError)
+    }
+
     /// store virtual node on DHT
     pub async fn storage_store(&self, vnode: vnode::VirtualNode) -> Result<()> {
         self.swarm
@@ -605,27 +709,119 @@ impl Processor {
     }
 
     /// append data to a virtual node on DHT
+    /// The topic is append-only, so that its history cannot be wiped by others.
     pub async fn storage_append_data(&self, topic: &str, data: Encoded) -> Result<()> {
+        let vnode: vnode::VirtualNode = (topic.to_string(), data)
+            .try_into()
+            .map_err(error::Error::VNodeError)?;
+        let vnode = vnode.with_owner(self.did(), vnode::VNodeWritePolicy::AppendOnly);
         self.swarm
-            .storage_append_data(topic, data)
+            .storage_operate(vnode::VNodeOperation::Extend(vnode))
             .await
             .map_err(error::Error::VNodeError)
     }
 
+    /// subscribe a topic, data appended to it will be pushed to current node
+    pub async fn subscribe_topic(&self, topic: &str) -> Result<()> {
+        self.swarm
+            .topic_subscribe(topic)
+            .await
+            .map_err(Error::TopicSubscriptionError)
+    }
+
+    /// unsubscribe a topic
+    pub async fn unsubscribe_topic(&self, topic: &str) -> Result<()> {
+        self.swarm
+            .topic_unsubscribe(topic)
+            .await
+            .map_err(Error::TopicSubscriptionError)
+    }
+
+    /// join a subring
+    pub async fn subring_join(&self, name: &str) -> Result<()> {
+        self.swarm
+            .subring_join(name)
+            .await
+            .map_err(Error::SubringError)
+    }
+
+    /// leave a subring
+    pub async fn subring_leave(&self, name: &str) -> Result<()> {
+        self.swarm
+            .subring_leave(name)
+            .await
+            .map_err(Error::SubringError)
+    }
+
+    /// remove a member from subring, only allowed for the creator
+    pub async fn subring_remove_member(&self, name: &str, member: &str) -> Result<()> {
+        let member = Did::from_str(member).map_err(|_| Error::InvalidDid)?;
+        self.swarm
+            .subring_remove_member(name, member)
+            .await
+            .map_err(Error::SubringError)
+    }
+
+    /// list members of a subring
+    pub async fn subring_members(&self, name: &str) -> Result<Vec<Did>> {
+        self.swarm
+            .subring_members(name)
+            .await
+            .map_err(Error::SubringError)
+    }
+
+    /// send message to all other members of a subring
+    pub async fn subring_broadcast(&self, name: &str, msg: &[u8]) -> Result<()> {
+        tracing::info!("subring_broadcast, subring: {}, text: {:?}", name, msg);
+        let data = pack_custom_message(msg)?;
+        self.swarm
+            .subring_broadcast(name, data)
+            .await
+            .map_err(Error::SubringError)
+    }
+
     /// register service
-    pub async fn register_service(&self, name: &str) -> Result<()> {
+    /// The service entries are append-only, so that they cannot be wiped by anyone.
+    /// With `ttl_ms`, the entry will expire unless registered again before that.
+    pub async fn register_service(&self, name: &str, ttl_ms: Option<u128>) -> Result<()> {
         let encoded_did = self
             .did()
             .to_string()
             .encode()
             .map_err(Error::ServiceRegisterError)?;
+        let mut vnode: vnode::VirtualNode = (name.to_string(), encoded_did)
+            .try_into()
+            .map_err(Error::ServiceRegisterError)?;
+        // A service is shared by all providers, so nobody owns it.
+        vnode.policy = vnode::VNodeWritePolicy::AppendOnly;
+        if let Some(ttl_ms) = ttl_ms {
+            vnode = vnode.with_ttl(ttl_ms);
+        }
         self.swarm
-            .storage_touch_data(name, encoded_did)
+            .storage_operate(vnode::VNodeOperation::Touch(vnode))
             .await
             .map_err(error::Error::ServiceRegisterError)
     }
 }
 
+#[cfg(feature = "node")]
+impl Processor {
+    /// receive files offered by other nodes into `dir`
+    pub async fn receive_file(&self, dir: &str) -> Result<()> {
+        self.file_transfer.receive_into(dir).await
+    }
+
+    /// send a file to node, resumed if the node has received a part of it.
+    /// Returns the id of transfer after the node has checked the hash of file.
+    /// - destination: did of destination
+    /// - path: path of file
+    pub async fn send_file(&self, destination: &str, path: &str) -> Result<uuid::Uuid> {
+        tracing::info!("send_file, destination: {}, path: {}", destination, path);
+        let destination = Did::from_str(destination).map_err(|_| Error::InvalidDid)?;
+        file_transfer::send_file(&self.swarm, destination, path).await
+    }
+}
+
 /// Peer struct
 #[derive(Clone)]
 pub struct Peer {
@@ -653,14 +849,14 @@ impl From<&(Did, Arc<Transport>)> for Peer {
     }
 }
 
+/// pack data to custom message
+fn pack_custom_message(msg: &[u8]) -> Result<MaybeEncrypted<CustomMessage>> {
+    MaybeEncrypted::new(CustomMessage(msg.to_vec()), None).map_err(Error::SendMessage)
+}
+
 /// unpack custom message to text
 pub fn unpack_text_message(msg: &CustomMessage) -> Result<String> {
-    let (left, right) = msg.0.split_at(4);
-    if left[0] != 0 {
-        return Err(Error::InvalidData);
-    }
-    let text = String::from_utf8(right.to_vec()).unwrap();
-    Ok(text)
+    String::from_utf8(msg.0.clone()).map_err(|_| Error::InvalidData)
 }
 
 #[cfg(test)]
@@ -941,6 +1137,97 @@ mod test {
         tokio::fs::remove_dir_all(path2).await.unwrap();
     }
 
+    #[tokio::test]
+    async fn test_processor_send_file() {
+        use std::path::Path;
+
+        use crate::file_transfer::file_hash;
+        use crate::file_transfer::transfer_id;
+        use crate::file_transfer::FileMessage;
+        use crate::file_transfer::FileOffer;
+        use crate::file_transfer::FILE_CHUNK_SIZE;
+
+        let (p1, path1) = new_processor().await;
+        let (p2, path2) = new_processor().await;
+        let did2 = p2.did().to_string();
+
+        for p in [&p1, &p2] {
+            let callback = Box::new(FileTransferCallback::new(p.file_transfer.clone(), None));
+            let msg_handler = Arc::new(p.swarm.create_message_handler(Some(callback), None));
+            tokio::spawn(async move { msg_handler.listen().await });
+        }
+
+        let (transport_1, offer) = p1.create_offer().await.unwrap();
+        let (transport_2, answer) = p2.answer_offer(offer.as_str()).await.unwrap();
+        p1.accept_answer(transport_1.id.to_string().as_str(), answer.as_str())
+            .await
+            .unwrap();
+        transport_1
+            .connect_success_promise()
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+        transport_2
+            .connect_success_promise()
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+        tokio::time::sleep(tokio::time::Duration::from_secs(3)).await;
+
+        let src_dir = PersistenceStorage::random_path("./tmp");
+        let dst_dir = PersistenceStorage::random_path("./tmp");
+        tokio::fs::create_dir_all(&src_dir).await.unwrap();
+        let file = format!("{}/artifact.bin", src_dir);
+        let data: Vec<u8> = (0..FILE_CHUNK_SIZE * 2 + 100)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        tokio::fs::write(&file, &data).await.unwrap();
+
+        // Offers are rejected before receiving is enabled.
+        assert!(p1.send_file(did2.as_str(), file.as_str()).await.is_err());
+        p2.receive_file(dst_dir.as_str()).await.unwrap();
+
+        // Leave a part of an interrupted transfer, with a chunk and a broken one.
+        let (size, hash) = file_hash(Path::new(&file), FILE_CHUNK_SIZE).await.unwrap();
+        let id = transfer_id("artifact.bin", &hash);
+        let part = format!("{}/{}-{}.part", dst_dir, p1.did(), id);
+        tokio::fs::write(&part, &data[..FILE_CHUNK_SIZE + 10])
+            .await
+            .unwrap();
+
+        // The transfer is resumed after the whole chunk.
+        let offer = FileOffer {
+            id,
+            name: "artifact.bin".to_string(),
+            size,
+            chunk_size: FILE_CHUNK_SIZE,
+            hash,
+        };
+        let reply = p2
+            .file_transfer
+            .handle_message(p1.did(), FileMessage::Offer(offer))
+            .await
+            .unwrap();
+        assert!(matches!(
+            reply,
+            Some(FileMessage::Accept { offset, .. }) if offset == FILE_CHUNK_SIZE as u64
+        ));
+
+        p1.send_file(did2.as_str(), file.as_str()).await.unwrap();
+        let received = tokio::fs::read(format!("{}/artifact.bin", dst_dir))
+            .await
+            .unwrap();
+        assert_eq!(received, data);
+        assert!(!Path::new(&part).exists());
+
+        tokio::fs::remove_dir_all(src_dir).await.unwrap();
+        tokio::fs::remove_dir_all(dst_dir).await.unwrap();
+        tokio::fs::remove_dir_all(path1).await.unwrap();
+        tokio::fs::remove_dir_all(path2).await.unwrap();
+    }
+
     #[test]
     fn test_create_and_verify_signature() {
         let key1 = SecretKey::random();
@@ -14,11 +14,13 @@ use http::HeaderMap;
 use http::HeaderValue;
 use jsonrpc_core::MetaIoHandler;
 use tokio::sync::broadcast::Receiver;
+use tokio::sync::broadcast::Sender;
 use tokio::sync::Mutex;
 use tower_http::cors::CorsLayer;
 
 use self::http_error::HttpError;
 use crate::backend::types::BackendMessage;
+use crate::jsonrpc::server::TopicReceivers;
 use crate::jsonrpc::RpcMeta;
 use crate::prelude::rings_core::ecc::PublicKey;
 use crate::processor::Processor;
@@ -29,13 +31,16 @@ struct JsonrpcState {
     io_handler: Arc<MetaIoHandler<RpcMeta>>,
     pubkey: Arc<PublicKey>,
     receiver: Arc<Mutex<Receiver<BackendMessage>>>,
+    topic_receivers: TopicReceivers,
 }
 
-/// Run a web server to handle jsonrpc request
+/// Run a web server to handle jsonrpc request.
+/// The `sender` is used to create receivers of subscribed topics.
 pub async fn run_service(
     addr: String,
     processor: Arc<Processor>,
     pubkey: Arc<PublicKey>,
+    sender: Sender<BackendMessage>,
     receiver: Receiver<BackendMessage>,
 ) -> anyhow::Result<()> {
     let binding_addr = addr.parse().unwrap();
@@ -50,6 +55,7 @@ pub async fn run_service(
         io_handler: jsonrpc_handler_layer,
         pubkey,
         receiver,
+        topic_receivers: TopicReceivers::new(sender),
     });
 
     let axum_make_service = Router::new()
@@ -81,7 +87,13 @@ async fn jsonrpc_io_handler(
         .io_handler
         .handle_request(
             &body,
-            (state.processor.clone(), state.receiver.clone(), is_auth).into(),
+            (
+                state.processor.clone(),
+                state.receiver.clone(),
+                state.topic_receivers.clone(),
+                is_auth,
+            )
+                .into(),
         )
         .await
         .ok_or(HttpError::BadRequest)?;